@@ -0,0 +1,322 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use smallvec::alloc::collections::BTreeMap;
+use crate::PID;
+use crate::hlc::HybridTime;
+use crate::vtime::{VTime, Dot};
+use crate::crdt::commutative::Commutative;
+use crate::crdt::commutative::event::{Event, Versioned};
+use crate::crdt::stability::StabilityTracker;
+
+/// Reliable causal broadcast protocol. It wraps a `Commutative` CRDT and makes sure, that every
+/// event is delivered to it exactly once and only after all events it causally depends on (as
+/// described by event's `vec_time`) have been delivered as well.
+///
+/// Events which dependencies are not yet satisfied are buffered. In that case `missing` returns
+/// a vector clock of all delivered events, which can be sent to other replicas, so that they can
+/// retransmit missing events using `replay`.
+///
+/// When configured `with_stability`, vector clocks carried by received events are used to track
+/// causal stability, and CRDT metadata is pruned every time a stable cut advances. Stable events
+/// have been delivered by all replicas, so they are also dropped from a retransmission buffer.
+#[derive(Debug)]
+pub struct ReliableBroadcast<S> {
+    id: PID,
+    seq_nr: u64,
+    vtime: VTime,
+    pending: BTreeMap<Dot, Event>,
+    delivered: Vec<Event>,
+    stability: Option<StabilityTracker>,
+    state: S,
+}
+
+impl<S> ReliableBroadcast<S> where S: Commutative, S::Operation: Serialize + DeserializeOwned {
+
+    pub fn new(id: PID, state: S) -> Self {
+        ReliableBroadcast {
+            id,
+            seq_nr: 0,
+            vtime: VTime::default(),
+            pending: BTreeMap::new(),
+            delivered: Vec::new(),
+            stability: None,
+            state,
+        }
+    }
+
+    /// Enables causal stability tracking within a given group of replicas.
+    pub fn with_stability<I: IntoIterator<Item=PID>>(mut self, members: I) -> Self {
+        self.stability = Some(StabilityTracker::new(members));
+        self
+    }
+
+    /// Returns a vector clock of events observed by all replicas, if stability tracking is
+    /// enabled.
+    pub fn stable(&self) -> Option<&VTime> {
+        self.stability.as_ref().map(|tracker| tracker.stable())
+    }
+
+    /// Acknowledges, that a given replica has delivered all events described by `vtime`. Replicas
+    /// that don't broadcast any events should periodically send such acknowledgements, otherwise
+    /// they would hold back causal stability. Returns true if CRDT state has been pruned.
+    pub fn ack(&mut self, id: PID, vtime: &VTime) -> bool {
+        self.stabilize(id, vtime)
+    }
+
+    /// Replica identifier of a current broadcast participant.
+    pub fn id(&self) -> PID { self.id }
+
+    /// Vector clock of all events delivered so far.
+    pub fn vtime(&self) -> &VTime { &self.vtime }
+
+    /// CRDT state, which contains all of the delivered events.
+    pub fn state(&self) -> &S { &self.state }
+
+    /// Applies a given operation locally and returns an `Event` representing it, which should be
+    /// sent to all other replicas.
+    pub fn broadcast(&mut self, op: S::Operation) -> crate::Result<Event> {
+        let payload = serde_cbor::to_vec(&op)?;
+        let dot = self.vtime.inc(self.id);
+        self.seq_nr += 1;
+        let event = Event::new(self.id, dot.seq_nr(), self.seq_nr, HybridTime::now(), self.vtime.clone(), payload);
+        let versioned = Versioned::new(self.id, event.sys_time(), self.vtime.clone(), op);
+        self.state.apply(versioned);
+        self.delivered.push(event.clone());
+
+        let vtime = self.vtime.clone();
+        self.stabilize(self.id, &vtime);
+        Ok(event)
+    }
+
+    /// Receives an event broadcast by another replica. If all of its causal dependencies were
+    /// already delivered, that event is delivered immediately, together with all previously
+    /// buffered events that were waiting for it. Returns a number of delivered events.
+    ///
+    /// Events, that have been already delivered or buffered, are ignored.
+    pub fn receive(&mut self, event: Event) -> crate::Result<usize> {
+        let dot = event.dot();
+        if self.vtime.contains(&dot) || self.pending.contains_key(&dot) {
+            return Ok(0);
+        }
+        self.pending.insert(dot, event);
+
+        let mut count = 0;
+        loop {
+            let ready = self.pending.iter()
+                .find(|(_, e)| self.is_ready(e))
+                .map(|(dot, _)| *dot);
+
+            match ready {
+                None => break,
+                Some(dot) => {
+                    let event = self.pending.remove(&dot).expect("Defect: ReliableBroadcast::receive - ready event not found");
+                    self.deliver(event)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// If there are any events awaiting for their causal dependencies, returns a vector clock of
+    /// all delivered events, which should be used to request missing events from other replicas.
+    pub fn missing(&self) -> Option<&VTime> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(&self.vtime)
+        }
+    }
+
+    /// Returns all delivered events (in their causal order), which were not observed by a given
+    /// vector clock. Used to retransmit events to replicas which have missed them.
+    pub fn replay<'a>(&'a self, since: &'a VTime) -> impl Iterator<Item=&'a Event> + 'a {
+        self.delivered.iter().filter(move |e| !since.contains(&e.dot()))
+    }
+
+    fn is_ready(&self, event: &Event) -> bool {
+        let origin = event.origin();
+        event.vec_time().iter().all(|(id, &seq_nr)| {
+            if *id == origin {
+                seq_nr == self.vtime.get(id) + 1
+            } else {
+                seq_nr <= self.vtime.get(id)
+            }
+        })
+    }
+
+    fn deliver(&mut self, event: Event) -> crate::Result<()> {
+        let value: S::Operation = serde_cbor::from_slice(event.payload())?;
+        let versioned = Versioned::new(event.origin(), event.sys_time(), event.vec_time().clone(), value);
+
+        self.vtime.set(event.dot());
+        self.seq_nr += 1;
+        if !self.state.redundant(&versioned) {
+            self.state.apply(versioned);
+        }
+
+        let vtime = self.vtime.clone();
+        self.stabilize(self.id, &vtime);
+        self.stabilize(event.origin(), event.vec_time());
+
+        self.delivered.push(event.with_local_seq_nr(self.seq_nr));
+        Ok(())
+    }
+
+    fn stabilize(&mut self, id: PID, vtime: &VTime) -> bool {
+        if let Some(tracker) = self.stability.as_mut() {
+            if let Some(stable) = tracker.observe(id, vtime) {
+                self.delivered.retain(|e| !stable.contains(&e.dot()));
+                return self.state.prune(stable);
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::Commutative;
+    use crate::crdt::commutative::event::Versioned;
+    use crate::crdt::commutative::broadcast::ReliableBroadcast;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+    const C: PID = 3;
+
+    /// Test CRDT, which records operations in order of their delivery.
+    #[derive(Debug, Default)]
+    struct Journal(Vec<u32>, Option<VTime>);
+
+    impl Commutative for Journal {
+        type Operation = u32;
+
+        fn redundant(&self, _: &Versioned<Self::Operation>) -> bool { false }
+
+        fn apply(&mut self, v: Versioned<Self::Operation>) -> bool {
+            self.0.push(v.value);
+            true
+        }
+
+        fn prune(&mut self, timestamp: &VTime) -> bool {
+            self.1 = Some(timestamp.clone());
+            true
+        }
+    }
+
+    #[test]
+    fn rcb_deliver_local() {
+        let mut a = ReliableBroadcast::new(A, Journal::default());
+        let mut b = ReliableBroadcast::new(B, Journal::default());
+
+        let e = a.broadcast(1).unwrap();
+        assert_eq!(a.state().0, vec![1]);
+
+        assert_eq!(b.receive(e).unwrap(), 1);
+        assert_eq!(b.state().0, vec![1]);
+        assert!(b.missing().is_none());
+    }
+
+    #[test]
+    fn rcb_deliver_exactly_once() {
+        let mut a = ReliableBroadcast::new(A, Journal::default());
+        let mut b = ReliableBroadcast::new(B, Journal::default());
+
+        let e = a.broadcast(1).unwrap();
+        assert_eq!(b.receive(e.clone()).unwrap(), 1);
+        assert_eq!(b.receive(e.clone()).unwrap(), 0);
+        assert_eq!(a.receive(e).unwrap(), 0);
+
+        assert_eq!(a.state().0, vec![1]);
+        assert_eq!(b.state().0, vec![1]);
+    }
+
+    #[test]
+    fn rcb_causal_order() {
+        let mut a = ReliableBroadcast::new(A, Journal::default());
+        let mut b = ReliableBroadcast::new(B, Journal::default());
+        let mut c = ReliableBroadcast::new(C, Journal::default());
+
+        let e1 = a.broadcast(1).unwrap();
+        assert_eq!(b.receive(e1.clone()).unwrap(), 1);
+        let e2 = b.broadcast(2).unwrap();
+
+        // e2 causally depends on e1, so it must wait
+        assert_eq!(c.receive(e2).unwrap(), 0);
+        assert!(c.state().0.is_empty());
+        assert!(c.missing().is_some());
+
+        assert_eq!(c.receive(e1).unwrap(), 2);
+        assert_eq!(c.state().0, vec![1, 2]);
+        assert!(c.missing().is_none());
+    }
+
+    #[test]
+    fn rcb_retransmit_missing() {
+        let mut a = ReliableBroadcast::new(A, Journal::default());
+        let mut b = ReliableBroadcast::new(B, Journal::default());
+
+        let _e1 = a.broadcast(1).unwrap(); // lost
+        let e2 = a.broadcast(2).unwrap();
+
+        assert_eq!(b.receive(e2).unwrap(), 0);
+        let since = b.missing().cloned().expect("b should be missing events");
+
+        let events: Vec<_> = a.replay(&since).cloned().collect();
+        assert_eq!(events.len(), 2);
+        for e in events {
+            b.receive(e).unwrap();
+        }
+
+        assert_eq!(b.state().0, vec![1, 2]);
+        assert!(b.missing().is_none());
+    }
+
+    #[test]
+    fn rcb_stability_prune() {
+        let mut a = ReliableBroadcast::new(A, Journal::default()).with_stability(vec![A, B]);
+        let mut b = ReliableBroadcast::new(B, Journal::default()).with_stability(vec![A, B]);
+
+        let e1 = a.broadcast(1).unwrap();
+        b.receive(e1).unwrap();
+        assert!(a.state().1.is_none()); // B didn't acknowledge e1 yet
+
+        let e2 = b.broadcast(2).unwrap();
+        a.receive(e2).unwrap();
+
+        // e2 carries B's acknowledgement of e1
+        let stable = a.stable().cloned().unwrap();
+        assert_eq!(stable.get(&A), 1);
+        assert_eq!(stable.get(&B), 1);
+        assert_eq!(a.state().1.as_ref(), Some(&stable));
+
+        let e3 = a.broadcast(3).unwrap();
+        assert_eq!(a.stable().unwrap().get(&A), 1);
+        b.receive(e3).unwrap();
+
+        assert!(a.ack(B, &b.vtime().clone()));
+        assert_eq!(a.stable().unwrap().get(&A), 2);
+    }
+
+    #[test]
+    fn rcb_stability_trims_delivered() {
+        let mut a = ReliableBroadcast::new(A, Journal::default()).with_stability(vec![A, B]);
+        let mut b = ReliableBroadcast::new(B, Journal::default()).with_stability(vec![A, B]);
+
+        let e1 = a.broadcast(1).unwrap();
+        let e2 = a.broadcast(2).unwrap();
+        assert_eq!(a.replay(&VTime::default()).count(), 2);
+
+        b.receive(e1).unwrap();
+        assert!(a.ack(B, &b.vtime().clone()));
+        // e1 is stable and no longer needs to be retransmitted
+        let replayed: Vec<_> = a.replay(&VTime::default()).map(|e| e.dot()).collect();
+        assert_eq!(replayed, vec![e2.dot()]);
+
+        b.receive(e2).unwrap();
+        assert!(a.ack(B, &b.vtime().clone()));
+        assert_eq!(a.replay(&VTime::default()).count(), 0);
+    }
+}
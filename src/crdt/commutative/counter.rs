@@ -1,14 +1,27 @@
 use serde::{Serialize, Deserialize};
-use crate::PID;
 use crate::crdt::commutative::event::Versioned;
+use crate::crdt::commutative::Commutative;
 use crate::crdt::convergent::Materialize;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Operation-based counter. Its operations are increments (or decrements, when negative) of
+/// a counter value. Since addition is commutative, operations can be applied in any order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Counter(i64);
 
-impl Default for Counter {
-    fn default() -> Self {
-        Counter(0)
+impl Commutative for Counter {
+    type Operation = i64;
+
+    fn redundant(&self, v: &Versioned<Self::Operation>) -> bool {
+        v.value == 0
+    }
+
+    fn apply(&mut self, v: Versioned<Self::Operation>) -> bool {
+        if v.value == 0 {
+            false
+        } else {
+            self.0 += v.value;
+            true
+        }
     }
 }
 
@@ -18,4 +31,56 @@ impl<'m> Materialize<'m> for Counter {
     fn value(&'m self) -> Self::Value {
         self.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::counter::Counter;
+    use crate::crdt::commutative::event::Versioned;
+    use crate::crdt::commutative::Commutative;
+    use crate::crdt::convergent::Materialize;
+    use crate::hlc::HybridTime;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn op(vtime: &mut VTime, origin: PID, value: i64) -> Versioned<i64> {
+        vtime.inc(origin);
+        Versioned::new(origin, HybridTime::now(), vtime.clone(), value)
+    }
+
+    #[test]
+    fn counter_identity() {
+        let a = Counter::default();
+        assert_eq!(a.value(), 0);
+    }
+
+    #[test]
+    fn counter_commutativity() {
+        let mut ta = VTime::default();
+        let mut tb = VTime::default();
+        let o1 = op(&mut ta, A, 3);
+        let o2 = op(&mut tb, B, -1);
+
+        let mut a = Counter::default();
+        assert!(a.apply(o1.clone()));
+        assert!(a.apply(o2.clone()));
+
+        let mut b = Counter::default();
+        assert!(b.apply(o2));
+        assert!(b.apply(o1));
+
+        assert_eq!(a.value(), 2);
+        assert_eq!(b.value(), 2);
+    }
+
+    #[test]
+    fn counter_redundant() {
+        let mut vtime = VTime::default();
+        let a = Counter::default();
+        assert!(a.redundant(&op(&mut vtime, A, 0)));
+        assert!(!a.redundant(&op(&mut vtime, A, 1)));
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{Serialize, Deserialize};
 use crate::PID;
 use crate::hlc::HybridTime;
-use crate::vtime::VTime;
+use crate::vtime::{VTime, Dot};
 use std::cmp::Ordering;
 use std::convert::TryInto;
 use serde::de::DeserializeOwned;
@@ -27,6 +27,30 @@ impl Event {
             payload
         }
     }
+
+    /// Replica identifier of an event creator.
+    pub fn origin(&self) -> PID { self.origin }
+
+    /// Sequence number of this event in a scope of its `origin` replica.
+    pub fn origin_seq_nr(&self) -> u64 { self.origin_seq_nr }
+
+    /// Sequence number of this event in a scope of a replica which has delivered it.
+    pub fn local_seq_nr(&self) -> u64 { self.local_seq_nr }
+
+    pub fn sys_time(&self) -> HybridTime { self.sys_time }
+
+    /// Vector clock describing all events, that causally happened before current one.
+    pub fn vec_time(&self) -> &VTime { &self.vec_time }
+
+    pub fn payload(&self) -> &[u8] { self.payload.as_slice() }
+
+    /// Unique identifier of this event in a scope of the whole cluster.
+    pub fn dot(&self) -> Dot { Dot::new(self.origin, self.origin_seq_nr) }
+
+    pub(crate) fn with_local_seq_nr(mut self, local_seq_nr: u64) -> Self {
+        self.local_seq_nr = local_seq_nr;
+        self
+    }
 }
 
 impl<T: DeserializeOwned> TryInto<Versioned<T>> for Event {
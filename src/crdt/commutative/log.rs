@@ -0,0 +1,251 @@
+use std::convert::TryInto;
+use sled::Transactional;
+use sled::transaction::ConflictableTransactionError;
+use crate::PID;
+use crate::vtime::VTime;
+use crate::crdt::commutative::event::Event;
+
+const VTIME_KEY: &[u8] = b"vtime";
+
+/// Persistent event log backed by sled. Events are stored in order of their local delivery (keyed
+/// by `local_seq_nr`, assigned by the log itself) and indexed by their `(origin, origin_seq_nr)`
+/// pair. Log also keeps a vector clock of all stored events, so that after restart a replica can
+/// ask others only for events it has not seen and serve the ones that others are missing via
+/// `replay`.
+#[derive(Debug)]
+pub struct EventLog {
+    events: sled::Tree,
+    index: sled::Tree,
+    meta: sled::Tree,
+    vtime: VTime,
+    seq_nr: u64,
+}
+
+impl EventLog {
+
+    /// Opens an event log stored inside of a given database. If that database already contains
+    /// a log, its vector clock and the last local sequence number are recovered.
+    pub fn open(db: &sled::Db) -> crate::Result<Self> {
+        let events = db.open_tree("events")?;
+        let index = db.open_tree("events_index")?;
+        let meta = db.open_tree("events_meta")?;
+
+        let vtime = match meta.get(VTIME_KEY)? {
+            None => VTime::default(),
+            Some(bytes) => serde_cbor::from_slice(bytes.as_ref())?,
+        };
+        let seq_nr = match events.last()? {
+            None => 0,
+            Some((key, _)) => decode_seq_nr(key.as_ref())?,
+        };
+
+        Ok(EventLog { events, index, meta, vtime, seq_nr })
+    }
+
+    /// Vector clock of all events stored in this log.
+    pub fn vtime(&self) -> &VTime { &self.vtime }
+
+    /// Local sequence number of the most recently appended event.
+    pub fn last_seq_nr(&self) -> u64 { self.seq_nr }
+
+    /// Appends a given event at the end of the log, returning a local sequence number assigned to
+    /// it. Events that were already stored are ignored, in which case `None` is returned. Events of
+    /// the same origin must be appended in order of their sequence numbers - an event that would
+    /// leave a gap in the log is rejected with an error.
+    pub fn append(&mut self, event: Event) -> crate::Result<Option<u64>> {
+        let dot = event.dot();
+        let last = self.vtime.get(&dot.pid());
+        if dot.seq_nr() <= last {
+            return Ok(None);
+        } else if dot.seq_nr() != last + 1 {
+            return Err(anyhow::anyhow!(
+                "EventLog cannot append event {:?}: expected sequence number {}", dot, last + 1));
+        }
+
+        let seq_nr = self.seq_nr + 1;
+        let event = event.with_local_seq_nr(seq_nr);
+        let mut vtime = self.vtime.clone();
+        vtime.set(dot);
+
+        let seq_key = seq_nr.to_be_bytes();
+        let dot_key = dot_key(dot.pid(), dot.seq_nr());
+        let event_bytes = serde_cbor::to_vec(&event)?;
+        let vtime_bytes = serde_cbor::to_vec(&vtime)?;
+
+        (&self.events, &self.index, &self.meta)
+            .transaction(|(events, index, meta)| {
+                events.insert(&seq_key, event_bytes.as_slice())?;
+                index.insert(&dot_key, &seq_key)?;
+                meta.insert(VTIME_KEY, vtime_bytes.as_slice())?;
+                Ok::<(), ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| anyhow::anyhow!("EventLog failed to append event {:?}: {:?}", dot, e))?;
+
+        self.seq_nr = seq_nr;
+        self.vtime = vtime;
+        Ok(Some(seq_nr))
+    }
+
+    /// Returns an event stored under a given local sequence number.
+    pub fn get(&self, local_seq_nr: u64) -> crate::Result<Option<Event>> {
+        match self.events.get(local_seq_nr.to_be_bytes())? {
+            None => Ok(None),
+            Some(bytes) => Ok(Some(serde_cbor::from_slice(bytes.as_ref())?)),
+        }
+    }
+
+    /// Returns an event created by a given `origin` replica with a given sequence number.
+    pub fn get_by_origin(&self, origin: PID, origin_seq_nr: u64) -> crate::Result<Option<Event>> {
+        match self.index.get(dot_key(origin, origin_seq_nr))? {
+            None => Ok(None),
+            Some(key) => self.get(decode_seq_nr(key.as_ref())?),
+        }
+    }
+
+    /// Returns all events, which were not observed by a given vector clock, in order in which they
+    /// were appended to this log.
+    pub fn replay(&self, since: &VTime) -> crate::Result<Vec<Event>> {
+        let mut seq_nrs = Vec::new();
+        for (&origin, &seq_nr) in self.vtime.iter() {
+            let from = since.get(&origin);
+            if seq_nr > from {
+                let range = dot_key(origin, from + 1)..=dot_key(origin, seq_nr);
+                for entry in self.index.range(range) {
+                    let (_, key) = entry?;
+                    seq_nrs.push(decode_seq_nr(key.as_ref())?);
+                }
+            }
+        }
+        seq_nrs.sort_unstable();
+
+        let mut result = Vec::with_capacity(seq_nrs.len());
+        for seq_nr in seq_nrs {
+            let event = self.get(seq_nr)?
+                .ok_or_else(|| anyhow::anyhow!("EventLog index points to missing event {}", seq_nr))?;
+            result.push(event);
+        }
+        Ok(result)
+    }
+
+    /// Flushes all appended events to disk.
+    pub async fn flush(&self) -> crate::Result<()> {
+        self.events.flush_async().await?;
+        Ok(())
+    }
+}
+
+fn dot_key(origin: PID, seq_nr: u64) -> [u8; 12] {
+    let mut key = [0u8; 12];
+    key[..4].copy_from_slice(&origin.to_be_bytes());
+    key[4..].copy_from_slice(&seq_nr.to_be_bytes());
+    key
+}
+
+fn decode_seq_nr(bytes: &[u8]) -> crate::Result<u64> {
+    let bytes: [u8; 8] = bytes.try_into()
+        .map_err(|_| anyhow::anyhow!("EventLog: invalid sequence number key"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::event::Event;
+    use crate::crdt::commutative::log::EventLog;
+    use crate::hlc::HybridTime;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn event(vtime: &mut VTime, origin: PID, payload: u8) -> Event {
+        let dot = vtime.inc(origin);
+        Event::new(origin, dot.seq_nr(), 0, HybridTime::now(), vtime.clone(), vec![payload])
+    }
+
+    fn db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn event_log_append() {
+        let db = db();
+        let mut log = EventLog::open(&db).unwrap();
+        let mut vtime = VTime::default();
+
+        let e1 = event(&mut vtime, A, 1);
+        let e2 = event(&mut vtime, B, 2);
+
+        assert_eq!(log.append(e1.clone()).unwrap(), Some(1));
+        assert_eq!(log.append(e2).unwrap(), Some(2));
+        assert_eq!(log.append(e1).unwrap(), None); // duplicate
+
+        assert_eq!(log.vtime(), &vtime);
+        assert_eq!(log.get(2).unwrap().unwrap().payload(), &[2]);
+        assert_eq!(log.get_by_origin(A, 1).unwrap().unwrap().local_seq_nr(), 1);
+        assert!(log.get_by_origin(A, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn event_log_append_out_of_order() {
+        let db = db();
+        let mut log = EventLog::open(&db).unwrap();
+        let mut vtime = VTime::default();
+
+        let e1 = event(&mut vtime, A, 1);
+        let e2 = event(&mut vtime, A, 2);
+
+        assert!(log.append(e2.clone()).is_err()); // A:1 is missing
+        assert_eq!(log.vtime(), &VTime::default());
+        assert_eq!(log.last_seq_nr(), 0);
+
+        assert_eq!(log.append(e1).unwrap(), Some(1));
+        assert_eq!(log.append(e2).unwrap(), Some(2));
+        assert_eq!(log.vtime(), &vtime);
+        assert_eq!(log.get_by_origin(A, 2).unwrap().unwrap().payload(), &[2]);
+    }
+
+    #[test]
+    fn event_log_replay() {
+        let db = db();
+        let mut log = EventLog::open(&db).unwrap();
+        let mut vtime = VTime::default();
+
+        log.append(event(&mut vtime, A, 1)).unwrap();
+        log.append(event(&mut vtime, B, 2)).unwrap();
+        let since = vtime.clone();
+        log.append(event(&mut vtime, A, 3)).unwrap();
+        log.append(event(&mut vtime, B, 4)).unwrap();
+        log.append(event(&mut vtime, A, 5)).unwrap();
+
+        let replayed: Vec<u8> = log.replay(&since).unwrap()
+            .into_iter()
+            .map(|e| e.payload()[0])
+            .collect();
+        assert_eq!(replayed, vec![3, 4, 5]);
+
+        let all: Vec<u8> = log.replay(&VTime::default()).unwrap()
+            .into_iter()
+            .map(|e| e.payload()[0])
+            .collect();
+        assert_eq!(all, vec![1, 2, 3, 4, 5]);
+
+        assert!(log.replay(&vtime).unwrap().is_empty());
+    }
+
+    #[test]
+    fn event_log_recover() {
+        let db = db();
+        let mut vtime = VTime::default();
+        {
+            let mut log = EventLog::open(&db).unwrap();
+            log.append(event(&mut vtime, A, 1)).unwrap();
+            log.append(event(&mut vtime, B, 2)).unwrap();
+        }
+
+        let mut log = EventLog::open(&db).unwrap();
+        assert_eq!(log.vtime(), &vtime);
+        assert_eq!(log.last_seq_nr(), 2);
+        assert_eq!(log.append(event(&mut vtime, A, 3)).unwrap(), Some(3));
+    }
+}
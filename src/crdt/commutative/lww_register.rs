@@ -1,9 +1,11 @@
 use serde::{Serialize, Deserialize};
-use crate::PID;
 use crate::crdt::commutative::event::Versioned;
-use serde::export::PhantomData;
+use crate::crdt::commutative::Commutative;
 use crate::crdt::convergent::Materialize;
 
+/// Operation-based last write wins register. Each assignment is tagged with its `Versioned`
+/// metadata: an assignment which causally follows the current one always replaces it, while
+/// concurrent assignments are resolved using hybrid time (and replica id as a tie breaker).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LWWRegister<T>(Option<Versioned<T>>);
 
@@ -13,10 +15,97 @@ impl<T> Default for LWWRegister<T>{
     }
 }
 
+impl<T> LWWRegister<T> {
+    pub fn is_empty(&self) -> bool { self.0.is_none() }
+}
+
+impl<T> Commutative for LWWRegister<T> {
+    type Operation = T;
+
+    fn redundant(&self, v: &Versioned<Self::Operation>) -> bool {
+        match self.0.as_ref() {
+            None => false,
+            Some(current) => v <= current,
+        }
+    }
+
+    fn apply(&mut self, v: Versioned<Self::Operation>) -> bool {
+        if self.redundant(&v) {
+            false
+        } else {
+            self.0 = Some(v);
+            true
+        }
+    }
+}
+
 impl<'m, T: 'm> Materialize<'m> for LWWRegister<T> {
     type Value = Option<&'m T>;
 
     fn value(&'m self) -> Self::Value {
         self.0.as_ref().map(|v| &v.value)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::lww_register::LWWRegister;
+    use crate::crdt::commutative::event::Versioned;
+    use crate::crdt::commutative::Commutative;
+    use crate::crdt::convergent::Materialize;
+    use crate::hlc::HybridTime;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn op<T>(vtime: &mut VTime, origin: PID, value: T) -> Versioned<T> {
+        vtime.inc(origin);
+        Versioned::new(origin, HybridTime::now(), vtime.clone(), value)
+    }
+
+    #[test]
+    fn lww_register_identity() {
+        let a: LWWRegister<u32> = LWWRegister::default();
+        assert!(a.is_empty());
+        assert_eq!(a.value(), None);
+    }
+
+    #[test]
+    fn lww_register_causal_override() {
+        let mut vtime = VTime::default();
+        let o1 = op(&mut vtime, A, "A");
+        let o2 = op(&mut vtime, B, "B");
+
+        let mut a = LWWRegister::default();
+        assert!(a.apply(o1.clone()));
+        assert!(a.apply(o2.clone()));
+        assert_eq!(a.value(), Some(&"B"));
+
+        // an older assignment cannot override the newer one
+        assert!(a.redundant(&o1));
+        assert!(!a.apply(o1));
+        assert!(!a.apply(o2));
+        assert_eq!(a.value(), Some(&"B"));
+    }
+
+    #[test]
+    fn lww_register_concurrent() {
+        let mut ta = VTime::default();
+        let mut tb = VTime::default();
+        let o1 = op(&mut ta, A, "A");
+        let o2 = op(&mut tb, B, "B"); // issued later in hybrid time
+
+        let mut a = LWWRegister::default();
+        assert!(a.apply(o1.clone()));
+        assert!(a.apply(o2.clone()));
+
+        let mut b = LWWRegister::default();
+        assert!(b.apply(o2));
+        assert!(!b.apply(o1));
+
+        assert_eq!(a.value(), Some(&"B"));
+        assert_eq!(b.value(), Some(&"B"));
+    }
+}
@@ -1,11 +1,13 @@
 use serde::{Serialize, Deserialize};
-use crate::PID;
 use crate::crdt::commutative::event::Versioned;
-use serde::export::PhantomData;
+use crate::crdt::commutative::Commutative;
 use smallvec::alloc::collections::BTreeSet;
 use crate::crdt::convergent::Materialize;
 use smallvec::SmallVec;
+use std::cmp::Ordering;
 
+/// Operation-based multi-value register. An assignment replaces all values it causally follows,
+/// while values assigned concurrently are retained side by side.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MVRegister<T>(BTreeSet<Versioned<T>>);
 
@@ -15,10 +17,99 @@ impl<T> Default for MVRegister<T>{
     }
 }
 
+impl<T> MVRegister<T> {
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl<T> Commutative for MVRegister<T> {
+    type Operation = T;
+
+    fn redundant(&self, v: &Versioned<Self::Operation>) -> bool {
+        self.0.iter().any(|e| {
+            matches!(v.vec_time.partial_cmp(&e.vec_time), Some(Ordering::Less | Ordering::Equal))
+        })
+    }
+
+    fn apply(&mut self, v: Versioned<Self::Operation>) -> bool {
+        if self.redundant(&v) {
+            false
+        } else {
+            self.0.retain(|e| e.vec_time.partial_cmp(&v.vec_time) != Some(Ordering::Less));
+            self.0.insert(v);
+            true
+        }
+    }
+}
+
 impl<'m, T: 'm> Materialize<'m> for MVRegister<T> {
     type Value = SmallVec<[&'m T; 2]>;
 
     fn value(&'m self) -> Self::Value {
         self.0.iter().map(|v| &v.value).collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::mv_register::MVRegister;
+    use crate::crdt::commutative::event::Versioned;
+    use crate::crdt::commutative::Commutative;
+    use crate::crdt::convergent::{Materialize, Convergent};
+    use crate::hlc::HybridTime;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn op<T>(vtime: &mut VTime, origin: PID, value: T) -> Versioned<T> {
+        vtime.inc(origin);
+        Versioned::new(origin, HybridTime::now(), vtime.clone(), value)
+    }
+
+    #[test]
+    fn mv_register_identity() {
+        let a: MVRegister<u32> = MVRegister::default();
+        assert!(a.is_empty());
+        assert!(a.value().is_empty());
+    }
+
+    #[test]
+    fn mv_register_concurrent() {
+        let mut ta = VTime::default();
+        let mut tb = VTime::default();
+        let o1 = op(&mut ta, A, "A");
+        let o2 = op(&mut tb, B, "B");
+
+        let mut a = MVRegister::default();
+        assert!(a.apply(o1.clone()));
+        assert!(a.apply(o2.clone()));
+
+        let mut b = MVRegister::default();
+        assert!(b.apply(o2));
+        assert!(b.apply(o1));
+
+        assert_eq!(a.value().as_slice(), &[&"A", &"B"]);
+        assert_eq!(b.value().as_slice(), &[&"A", &"B"]);
+    }
+
+    #[test]
+    fn mv_register_assign_override() {
+        let mut ta = VTime::default();
+        let mut tb = VTime::default();
+        let o1 = op(&mut ta, A, "A");
+        let o2 = op(&mut tb, B, "B");
+        ta.merge(&tb);
+        let o3 = op(&mut ta, A, "C"); // observed both A and B
+
+        let mut a = MVRegister::default();
+        assert!(a.apply(o1.clone()));
+        assert!(a.apply(o2.clone()));
+        assert!(a.apply(o3));
+        assert_eq!(a.value().as_slice(), &[&"C"]);
+
+        assert!(a.redundant(&o1));
+        assert!(!a.apply(o2));
+        assert_eq!(a.value().as_slice(), &[&"C"]);
+    }
+}
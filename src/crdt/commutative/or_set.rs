@@ -0,0 +1,204 @@
+use serde::{Serialize, Deserialize};
+use smallvec::SmallVec;
+use smallvec::alloc::collections::{BTreeMap, BTreeSet};
+use std::cmp::Ordering;
+use crate::vtime::VTime;
+use crate::crdt::commutative::event::Versioned;
+use crate::crdt::commutative::Commutative;
+use crate::crdt::convergent::Materialize;
+
+/// Operations supported by an operation-based `ORSet`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation<T> {
+    Add(T),
+    Remove(T),
+}
+
+/// Pure operation-based (add-wins) observed-remove set. It relies on causal delivery of its
+/// operations: removal of an element discards only these additions, which happened before it,
+/// while concurrent additions are retained.
+///
+/// Every addition remembers the vector clock of an operation that produced it. Once that
+/// timestamp becomes causally stable (see: `prune`), it can no longer be concurrent to any
+/// incoming operation, so an element is moved to a plain set and its metadata is discarded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ORSet<T: Ord> {
+    stable: BTreeSet<T>,
+    unstable: BTreeMap<T, SmallVec<[VTime; 1]>>,
+}
+
+impl<T: Ord> ORSet<T> {
+    pub fn contains(&self, value: &T) -> bool {
+        self.stable.contains(value) || self.unstable.contains_key(value)
+    }
+
+    pub fn is_empty(&self) -> bool { self.stable.is_empty() && self.unstable.is_empty() }
+
+    pub fn len(&self) -> usize {
+        self.stable.len() + self.unstable.keys().filter(|k| !self.stable.contains(k)).count()
+    }
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        ORSet {
+            stable: BTreeSet::new(),
+            unstable: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord> Commutative for ORSet<T> {
+    type Operation = Operation<T>;
+
+    fn redundant(&self, v: &Versioned<Self::Operation>) -> bool {
+        match &v.value {
+            Operation::Add(_) => false,
+            Operation::Remove(value) => !self.contains(value),
+        }
+    }
+
+    fn apply(&mut self, v: Versioned<Self::Operation>) -> bool {
+        let vec_time = v.vec_time;
+        match v.value {
+            Operation::Add(value) => {
+                let timestamps = self.unstable.entry(value).or_default();
+                // additions of the same element from the causal past become redundant
+                timestamps.retain(|t| (*t).partial_cmp(&vec_time) != Some(Ordering::Less));
+                timestamps.push(vec_time);
+                true
+            },
+            Operation::Remove(value) => {
+                let mut changed = self.stable.remove(&value);
+                if let Some(timestamps) = self.unstable.get_mut(&value) {
+                    let len = timestamps.len();
+                    timestamps.retain(|t| (*t).partial_cmp(&vec_time) != Some(Ordering::Less));
+                    changed = changed || timestamps.len() != len;
+                    if timestamps.is_empty() {
+                        self.unstable.remove(&value);
+                    }
+                }
+                changed
+            },
+        }
+    }
+
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        let mut changed = false;
+        let unstable = std::mem::take(&mut self.unstable);
+        for (value, mut timestamps) in unstable {
+            let len = timestamps.len();
+            timestamps.retain(|t| !matches!((*t).partial_cmp(timestamp), Some(Ordering::Less | Ordering::Equal)));
+            changed = changed || timestamps.len() != len;
+            if timestamps.is_empty() {
+                self.stable.insert(value);
+            } else {
+                // an element is still kept alive by its unstable additions
+                self.unstable.insert(value, timestamps);
+            }
+        }
+        changed
+    }
+}
+
+impl<'m, T: Ord + 'm> Materialize<'m> for ORSet<T> {
+    type Value = BTreeSet<&'m T>;
+
+    fn value(&'m self) -> Self::Value {
+        self.stable.iter().chain(self.unstable.keys()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::commutative::or_set::{ORSet, Operation};
+    use crate::crdt::commutative::event::Versioned;
+    use crate::crdt::commutative::Commutative;
+    use crate::crdt::convergent::{Materialize, Convergent};
+    use smallvec::alloc::collections::BTreeSet;
+    use crate::hlc::HybridTime;
+    use crate::vtime::VTime;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn op<T>(vtime: &mut VTime, origin: PID, value: Operation<T>) -> Versioned<Operation<T>> {
+        vtime.inc(origin);
+        Versioned::new(origin, HybridTime::now(), vtime.clone(), value)
+    }
+
+    #[test]
+    fn orset_identity() {
+        let a: ORSet<u32> = ORSet::default();
+        assert!(a.is_empty());
+        assert_eq!(a.value(), BTreeSet::new());
+    }
+
+    #[test]
+    fn orset_add_remove() {
+        let mut vtime = VTime::default();
+        let mut a = ORSet::default();
+
+        assert!(a.apply(op(&mut vtime, A, Operation::Add("A"))));
+        assert!(a.apply(op(&mut vtime, A, Operation::Add("B"))));
+        assert_eq!(a.len(), 2);
+
+        let remove = op(&mut vtime, A, Operation::Remove("A"));
+        assert!(!a.redundant(&remove));
+        assert!(a.apply(remove));
+
+        let remove = op(&mut vtime, A, Operation::Remove("A"));
+        assert!(a.redundant(&remove));
+
+        assert_eq!(a.value(), vec![&"B"].into_iter().collect());
+    }
+
+    #[test]
+    fn orset_add_wins() {
+        let mut ta = VTime::default();
+        let mut tb = VTime::default();
+
+        let add = op(&mut ta, A, Operation::Add("A"));
+        tb.merge(&ta);
+        let remove = op(&mut tb, B, Operation::Remove("A"));
+        let concurrent_add = op(&mut ta, A, Operation::Add("A"));
+
+        let mut a = ORSet::default();
+        a.apply(add.clone());
+        a.apply(concurrent_add.clone());
+        a.apply(remove.clone());
+
+        let mut b = ORSet::default();
+        b.apply(add);
+        b.apply(remove);
+        b.apply(concurrent_add);
+
+        let expected: BTreeSet<_> = vec![&"A"].into_iter().collect();
+        assert_eq!(a.value(), expected);
+        assert_eq!(b.value(), expected);
+    }
+
+    #[test]
+    fn orset_prune() {
+        let mut vtime = VTime::default();
+        let mut a = ORSet::default();
+
+        a.apply(op(&mut vtime, A, Operation::Add("A")));
+        let stable = vtime.clone();
+        a.apply(op(&mut vtime, A, Operation::Add("B")));
+
+        assert!(a.prune(&stable));
+        assert!(!a.prune(&stable));
+        assert_eq!(a.unstable.len(), 1);
+        assert!(a.stable.contains("A"));
+        assert_eq!(a.value(), vec![&"A", &"B"].into_iter().collect());
+
+        // removal of stable element
+        assert!(a.apply(op(&mut vtime, B, Operation::Remove("A"))));
+        assert_eq!(a.value(), vec![&"B"].into_iter().collect());
+
+        assert!(a.prune(&vtime));
+        assert!(a.unstable.is_empty());
+    }
+}
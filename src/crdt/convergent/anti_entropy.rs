@@ -0,0 +1,318 @@
+use serde::{Serialize, Deserialize};
+use smallvec::alloc::collections::BTreeMap;
+use crate::crdt::convergent::{Convergent, DeltaConvergent};
+use crate::PID;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Messages exchanged between replicas taking part in delta-based anti-entropy protocol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message<S, D> {
+    /// Join of all deltas produced by `from` replica, which were not yet acknowledged by
+    /// a recipient. `seq_nr` is a sequence number of the next delta to be produced by sender.
+    Delta { from: PID, seq_nr: u64, delta: D },
+    /// Full state of `from` replica, send when a recipient is too far behind to catch up using
+    /// buffered deltas.
+    State { from: PID, seq_nr: u64, state: S },
+    /// Acknowledgement that all deltas up to (but excluding) a given `seq_nr` have been merged.
+    Ack { from: PID, seq_nr: u64 },
+}
+
+/// Delta-based anti-entropy protocol. It wraps a `DeltaConvergent` CRDT and keeps a buffer of
+/// deltas produced by local updates or received from other replicas, each one tagged with
+/// a sequence number and a replica it originates from. For every neighbour it remembers the latest
+/// acknowledged sequence number, so that `sync` only ships deltas which neighbour has not seen,
+/// joined together into a single message. Deltas received from a neighbour are never sent back
+/// to it.
+///
+/// Deltas acknowledged by all neighbours are garbage collected. When a neighbour is so far behind,
+/// that deltas it needs are no longer buffered (buffer capacity has been exceeded or a full state
+/// has been merged in the meantime), a full state is sent instead.
+#[derive(Debug)]
+pub struct AntiEntropy<S: DeltaConvergent> {
+    id: PID,
+    state: S,
+    seq_nr: u64,
+    /// Buffered deltas together with identifiers of replicas they were produced or received by.
+    deltas: BTreeMap<u64, (PID, S::Delta)>,
+    acks: BTreeMap<PID, u64>,
+    capacity: usize,
+}
+
+impl<S> AntiEntropy<S>
+    where S: DeltaConvergent + Convergent + Clone,
+          S::Delta: Convergent + Clone {
+
+    pub fn new(id: PID, state: S) -> Self {
+        AntiEntropy {
+            id,
+            state,
+            seq_nr: 0,
+            deltas: BTreeMap::new(),
+            acks: BTreeMap::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Sets the maximum number of buffered deltas.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn id(&self) -> PID { self.id }
+
+    pub fn state(&self) -> &S { &self.state }
+
+    /// Registers a new neighbour replica, that state should be replicated to.
+    pub fn add_neighbour(&mut self, id: PID) {
+        self.acks.entry(id).or_insert(0);
+    }
+
+    pub fn remove_neighbour(&mut self, id: &PID) {
+        self.acks.remove(id);
+        self.gc();
+    }
+
+    /// Updates a local CRDT state using a given function. A delta produced by that update is
+    /// buffered in order to be send to neighbours.
+    pub fn update<F, R>(&mut self, f: F) -> R where F: FnOnce(&mut S) -> R {
+        let result = f(&mut self.state);
+        if let Some(delta) = self.state.delta() {
+            self.push(self.id, delta);
+        }
+        result
+    }
+
+    /// Returns a message, that should be sent to a given neighbour in order to bring it up to date
+    /// with a current replica. Returns `None` if neighbour has already acknowledged all updates.
+    pub fn sync(&self, to: PID) -> Option<Message<S, S::Delta>> {
+        let ack = *self.acks.get(&to)?;
+        if ack >= self.seq_nr {
+            return None;
+        }
+
+        let mut deltas = self.deltas.range(ack..);
+        match deltas.next() {
+            Some((&seq_nr, (_, first))) if seq_nr == ack => {
+                let mut delta = first.clone();
+                for (_, (_, d)) in deltas.filter(|(_, (origin, _))| *origin != to) {
+                    delta.merge(d);
+                }
+                Some(Message::Delta { from: self.id, seq_nr: self.seq_nr, delta })
+            },
+            _ => Some(Message::State { from: self.id, seq_nr: self.seq_nr, state: self.state.clone() }),
+        }
+    }
+
+    /// Handles a message received from another replica, returning an optional reply.
+    pub fn receive(&mut self, msg: Message<S, S::Delta>) -> Option<Message<S, S::Delta>> {
+        match msg {
+            Message::Delta { from, seq_nr, delta } => {
+                if self.state.merge_delta(&delta) {
+                    // buffer received delta, so that it can be propagated further
+                    self.push(from, delta);
+                    self.skip_received();
+                }
+                Some(Message::Ack { from: self.id, seq_nr })
+            },
+            Message::State { seq_nr, state, .. } => {
+                if self.state.merge(&state) {
+                    // changes received with a full state cannot be expressed as deltas, so all
+                    // neighbours need to receive a full state as well
+                    self.deltas.clear();
+                    self.seq_nr += 1;
+                }
+                Some(Message::Ack { from: self.id, seq_nr })
+            },
+            Message::Ack { from, seq_nr } => {
+                if let Some(ack) = self.acks.get_mut(&from) {
+                    if *ack < seq_nr {
+                        *ack = seq_nr.min(self.seq_nr);
+                        self.skip_received();
+                        self.gc();
+                    }
+                }
+                None
+            },
+        }
+    }
+
+    fn push(&mut self, origin: PID, delta: S::Delta) {
+        self.deltas.insert(self.seq_nr, (origin, delta));
+        self.seq_nr += 1;
+        while self.deltas.len() > self.capacity {
+            let oldest = *self.deltas.keys().next().expect("Defect: AntiEntropy::push - delta buffer is empty");
+            self.deltas.remove(&oldest);
+        }
+    }
+
+    /// Moves acknowledgements of neighbours past the oldest deltas they haven't acknowledged, if
+    /// these deltas have been received from them in the first place.
+    fn skip_received(&mut self) {
+        for (id, ack) in self.acks.iter_mut() {
+            while matches!(self.deltas.get(ack), Some((origin, _)) if origin == id) {
+                *ack += 1;
+            }
+        }
+    }
+
+    fn gc(&mut self) {
+        if let Some(&min) = self.acks.values().min() {
+            while let Some(&oldest) = self.deltas.keys().next() {
+                if oldest < min {
+                    self.deltas.remove(&oldest);
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::convergent::anti_entropy::{AntiEntropy, Message};
+    use crate::crdt::convergent::bcounter::BCounter;
+    use crate::crdt::convergent::or_set::ORSet;
+    use crate::crdt::convergent::{Materialize, DeltaConvergent, Convergent};
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+    const C: PID = 3;
+
+    /// Sends all pending updates from `from` to `to`, returning true if any message was sent.
+    fn sync<S>(from: &mut AntiEntropy<S>, to: &mut AntiEntropy<S>) -> bool
+        where S: DeltaConvergent + Convergent + Clone, S::Delta: Convergent + Clone {
+        if let Some(msg) = from.sync(to.id()) {
+            if let Some(ack) = to.receive(msg) {
+                from.receive(ack);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn replica<S>(id: PID, state: S, neighbours: &[PID]) -> AntiEntropy<S>
+        where S: DeltaConvergent + Convergent + Clone, S::Delta: Convergent + Clone {
+        let mut replica = AntiEntropy::new(id, state);
+        for &n in neighbours {
+            replica.add_neighbour(n);
+        }
+        replica
+    }
+
+    #[test]
+    fn anti_entropy_delta_sync() {
+        let mut a = replica(A, BCounter::default(), &[B]);
+        let mut b = replica(B, BCounter::default(), &[A]);
+
+        a.update(|s| s.add(A, 3)).unwrap();
+        b.update(|s| s.add(B, 2)).unwrap();
+
+        assert!(matches!(a.sync(B), Some(Message::Delta { .. })));
+        assert!(sync(&mut a, &mut b));
+        assert!(sync(&mut b, &mut a));
+
+        assert_eq!(a.state().value(), 5);
+        assert_eq!(b.state().value(), 5);
+
+        // a has acknowledged b's deltas, b has nothing new to send
+        a.update(|s| s.add(A, 1)).unwrap();
+        assert!(sync(&mut a, &mut b));
+        assert!(!sync(&mut a, &mut b));
+        assert_eq!(b.state().value(), 6);
+    }
+
+    #[test]
+    fn anti_entropy_transitive() {
+        let mut a = replica(A, ORSet::default(), &[B]);
+        let mut b = replica(B, ORSet::default(), &[A, C]);
+        let mut c = replica(C, ORSet::default(), &[B]);
+
+        a.update(|s| s.insert(A, "A"));
+        c.update(|s| s.insert(C, "C"));
+
+        sync(&mut a, &mut b);
+        sync(&mut c, &mut b);
+        sync(&mut b, &mut a);
+        sync(&mut b, &mut c);
+
+        let expected = vec![&"A", &"C"].into_iter().collect();
+        assert_eq!(a.state().value(), expected);
+        assert_eq!(b.state().value(), expected);
+        assert_eq!(c.state().value(), expected);
+    }
+
+    #[test]
+    fn anti_entropy_skips_origin() {
+        let mut a = replica(A, ORSet::default(), &[B]);
+        let mut b = replica(B, ORSet::default(), &[A, C]);
+        let mut c = replica(C, ORSet::default(), &[B]);
+
+        a.update(|s| s.insert(A, "A"));
+        sync(&mut a, &mut b);
+        assert!(b.sync(A).is_none()); // a's own delta is not sent back to it
+
+        c.update(|s| s.insert(C, "C"));
+        sync(&mut c, &mut b);
+        match b.sync(A) {
+            Some(Message::Delta { delta, .. }) => {
+                let mut state = ORSet::default();
+                state.merge_delta(&delta);
+                assert_eq!(state.value(), vec![&"C"].into_iter().collect());
+            },
+            other => panic!("expected delta, got {:?}", other),
+        }
+        assert!(sync(&mut b, &mut a));
+        assert!(sync(&mut b, &mut c));
+        assert!(b.deltas.is_empty());
+        assert_eq!(a.state().value(), vec![&"A", &"C"].into_iter().collect());
+    }
+
+    #[test]
+    fn anti_entropy_gc() {
+        let mut a = replica(A, BCounter::default(), &[B, C]);
+        let mut b = replica(B, BCounter::default(), &[A]);
+        let mut c = replica(C, BCounter::default(), &[A]);
+
+        a.update(|s| s.add(A, 1)).unwrap();
+        a.update(|s| s.add(A, 1)).unwrap();
+        sync(&mut a, &mut b);
+        assert_eq!(a.deltas.len(), 2); // C didn't acknowledge them yet
+
+        sync(&mut a, &mut c);
+        assert!(a.deltas.is_empty());
+    }
+
+    #[test]
+    fn anti_entropy_full_state_fallback() {
+        let mut a = replica(A, BCounter::default(), &[B]).with_capacity(2);
+        let mut b = replica(B, BCounter::default(), &[A]);
+
+        for _ in 0..5 {
+            a.update(|s| s.add(A, 1)).unwrap();
+        }
+
+        assert!(matches!(a.sync(B), Some(Message::State { .. })));
+        assert!(sync(&mut a, &mut b));
+        assert_eq!(b.state().value(), 5);
+        assert!(a.sync(B).is_none());
+    }
+
+    #[test]
+    fn anti_entropy_message_serialization() {
+        let mut a = replica(A, ORSet::default(), &[B]);
+        let mut b = replica(B, ORSet::default(), &[A]);
+        a.update(|s| s.insert(A, 1u32));
+
+        let msg = a.sync(B).unwrap();
+        let bytes = serde_cbor::to_vec(&msg).unwrap();
+        let msg = serde_cbor::from_slice(bytes.as_slice()).unwrap();
+        b.receive(msg);
+
+        assert_eq!(b.state().value(), vec![&1].into_iter().collect());
+    }
+}
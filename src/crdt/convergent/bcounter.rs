@@ -1,5 +1,5 @@
 use crate::PID;
-use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize};
+use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize, Prune};
 use serde::{Serialize,Deserialize};
 use smallvec::alloc::collections::BTreeMap;
 use crate::crdt::convergent::gcounter::GCounter;
@@ -129,6 +129,8 @@ impl DeltaConvergent for BCounter {
     }
 }
 
+impl Prune for BCounter {}
+
 impl<'m> Materialize<'m> for BCounter {
     type Value = u64;
 
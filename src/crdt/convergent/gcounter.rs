@@ -1,5 +1,5 @@
 use crate::vtime::VTime;
-use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize};
+use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize, Prune};
 use serde::{Serialize,Deserialize};
 use crate::PID;
 
@@ -54,6 +54,8 @@ impl DeltaConvergent for GCounter {
     }
 }
 
+impl Prune for GCounter {}
+
 impl<'m> Materialize<'m> for GCounter {
     type Value = u64;
 
@@ -0,0 +1,554 @@
+use serde::{Serialize, Deserialize};
+use smallvec::alloc::collections::{BTreeMap, BTreeSet};
+use crate::crdt::convergent::{Convergent, Materialize};
+use crate::crdt::convergent::lww_register::LWWRegister;
+use crate::crdt::convergent::or_map::ORMap;
+use crate::crdt::convergent::rga::RGA;
+use crate::hlc::HybridTime;
+use crate::vtime::Dot;
+use crate::PID;
+
+/// A materialized JSON value of a `Json` document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    /// Returns a value found under a given path, if it exists.
+    pub fn get(&self, path: &[Segment]) -> Option<&JsonValue> {
+        match path.split_first() {
+            None => Some(self),
+            Some((Segment::Key(key), rest)) => match self {
+                JsonValue::Object(map) => map.get(key)?.get(rest),
+                _ => None,
+            },
+            Some((Segment::Index(i), rest)) => match self {
+                JsonValue::Array(items) => items.get(*i)?.get(rest),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self { JsonValue::Bool(value) }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self { JsonValue::Number(value) }
+}
+
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self { JsonValue::Number(value as f64) }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self { JsonValue::String(value.to_string()) }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self { JsonValue::String(value) }
+}
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self { JsonValue::Array(value.into_iter().map(Into::into).collect()) }
+}
+
+/// A single segment of a path used to navigate through a `Json` document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Segment {
+    /// A key of an object field.
+    Key(String),
+    /// An index of an array element.
+    Index(usize),
+}
+
+impl From<&str> for Segment {
+    fn from(key: &str) -> Self { Segment::Key(key.to_string()) }
+}
+
+impl From<String> for Segment {
+    fn from(key: String) -> Self { Segment::Key(key) }
+}
+
+impl From<usize> for Segment {
+    fn from(index: usize) -> Self { Segment::Index(index) }
+}
+
+/// Type of a value currently stored in a `Node`, together with a value itself in case of
+/// primitives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Kind {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Object,
+    Array,
+}
+
+/// Array of nested nodes. Order of elements is maintained by an RGA of their identifiers, while
+/// nodes themselves are stored separately, so that they can be concurrently updated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Array {
+    order: RGA<()>,
+    items: BTreeMap<Dot, Node>,
+}
+
+/// A single node of a `Json` document. A type of value stored by a node is decided by a last write
+/// wins register, so that concurrent assignments of different types resolve to the same value on
+/// every replica. Object fields are stored in an add-wins `ORMap`, while array elements in `RGA`.
+/// Removed array elements are dropped together with their nested nodes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Node {
+    kind: LWWRegister<Kind, HybridTime>,
+    object: ORMap<String, Node>,
+    array: Array,
+}
+
+impl Node {
+    fn kind(&self) -> Option<&Kind> { self.kind.value() }
+
+    /// Overrides current node with a given value.
+    fn assign(&mut self, id: PID, value: JsonValue) {
+        match value {
+            JsonValue::Null => self.kind.assign(id, Kind::Null),
+            JsonValue::Bool(v) => self.kind.assign(id, Kind::Bool(v)),
+            JsonValue::Number(v) => self.kind.assign(id, Kind::Number(v)),
+            JsonValue::String(v) => self.kind.assign(id, Kind::String(v)),
+            JsonValue::Object(fields) => {
+                self.kind.assign(id, Kind::Object);
+                self.object.clear();
+                for (key, value) in fields {
+                    self.object.entry(key).or_default(id).assign(id, value);
+                }
+            },
+            JsonValue::Array(items) => {
+                self.kind.assign(id, Kind::Array);
+                self.clear_array();
+                for (i, value) in items.into_iter().enumerate() {
+                    self.insert(id, i, value);
+                }
+            },
+        }
+    }
+
+    /// Returns a nested node under a given path segment, creating it if necessary. If current
+    /// node is not a container of a matching type, it's turned into an empty one.
+    fn child(&mut self, id: PID, segment: &Segment) -> crate::Result<&mut Node> {
+        match segment {
+            Segment::Key(key) => {
+                if self.kind() != Some(&Kind::Object) {
+                    self.kind.assign(id, Kind::Object);
+                    self.object.clear();
+                }
+                Ok(self.object.entry(key.clone()).and_modify(id, |_| {}).or_default(id))
+            },
+            Segment::Index(index) => {
+                if self.kind() != Some(&Kind::Array) {
+                    self.kind.assign(id, Kind::Array);
+                    self.clear_array();
+                }
+                let len = self.array.order.len();
+                if *index == len {
+                    self.insert(id, len, JsonValue::Null);
+                } else if *index > len {
+                    return Err(anyhow::anyhow!("Cannot access element {} of an array of length {}", index, len));
+                }
+                let dot = self.array.order.id(*index).expect("Defect: Node::child - array index not found");
+                Ok(self.array.items.entry(dot).or_default())
+            },
+        }
+    }
+
+    /// Returns a nested node under a given path without modifying any of the nodes on the way.
+    fn get(&self, path: &[Segment]) -> Option<&Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((Segment::Key(key), rest)) => match self.kind() {
+                Some(Kind::Object) => self.object.lookup(key)?.get(rest),
+                _ => None,
+            },
+            Some((Segment::Index(index), rest)) => match self.kind() {
+                Some(Kind::Array) => {
+                    let dot = self.array.order.id(*index)?;
+                    self.array.items.get(&dot)?.get(rest)
+                },
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns a mutable nested node under a given path. Unlike `child`, it doesn't mark any of
+    /// the nodes on the way as updated, so that their concurrent removals are not reverted.
+    fn get_mut(&mut self, path: &[Segment]) -> Option<&mut Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((Segment::Key(key), rest)) => match self.kind() {
+                Some(Kind::Object) => self.object.lookup_mut(key)?.get_mut(rest),
+                _ => None,
+            },
+            Some((Segment::Index(index), rest)) => match self.kind() {
+                Some(Kind::Array) => {
+                    let dot = self.array.order.id(*index)?;
+                    self.array.items.get_mut(&dot)?.get_mut(rest)
+                },
+                _ => None,
+            },
+        }
+    }
+
+    /// Inserts a new array element at a given index.
+    fn insert(&mut self, id: PID, index: usize, value: JsonValue) {
+        let dot = self.array.order.insert(id, index, ());
+        let mut node = Node::default();
+        node.assign(id, value);
+        self.array.items.insert(dot, node);
+    }
+
+    /// Removes a nested node under a given path segment. Returns false if it didn't exist.
+    fn remove(&mut self, segment: &Segment) -> bool {
+        match (segment, self.kind()) {
+            (Segment::Key(key), Some(Kind::Object)) => self.object.remove(key).is_some(),
+            (Segment::Index(index), Some(Kind::Array)) => {
+                match self.array.order.id(*index) {
+                    None => false,
+                    Some(dot) => {
+                        self.array.order.remove(*index);
+                        self.array.items.remove(&dot);
+                        true
+                    },
+                }
+            },
+            _ => false,
+        }
+    }
+
+    fn clear_array(&mut self) {
+        while self.array.order.remove(0).is_some() {}
+        self.array.items.clear();
+    }
+}
+
+impl Convergent for Node {
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = self.kind.merge(&other.kind);
+        changed = self.object.merge(&other.object) || changed;
+        // `ORMap` merges only the fields it has seen updated, while nested removals don't update
+        // their ancestors, so all fields that survived have to be merged as well
+        for (key, node) in other.object.iter() {
+            if let Some(existing) = self.object.lookup_mut(key) {
+                changed = existing.merge(node) || changed;
+            }
+        }
+
+        changed = self.array.order.merge(&other.array.order) || changed;
+        let alive: BTreeSet<Dot> = self.array.order.ids().collect();
+        self.array.items.retain(|dot, _| alive.contains(dot));
+        for (dot, node) in other.array.items.iter().filter(|(dot, _)| alive.contains(dot)) {
+            match self.array.items.get_mut(dot) {
+                Some(existing) => changed = existing.merge(node) || changed,
+                None => {
+                    self.array.items.insert(*dot, node.clone());
+                    changed = true;
+                },
+            }
+        }
+        changed
+    }
+}
+
+impl<'m> Materialize<'m> for Node {
+    type Value = JsonValue;
+
+    fn value(&'m self) -> Self::Value {
+        match self.kind() {
+            None | Some(Kind::Null) => JsonValue::Null,
+            Some(Kind::Bool(v)) => JsonValue::Bool(*v),
+            Some(Kind::Number(v)) => JsonValue::Number(*v),
+            Some(Kind::String(v)) => JsonValue::String(v.clone()),
+            Some(Kind::Object) => JsonValue::Object(self.object.value()
+                .into_iter()
+                .map(|(key, value)| (key.clone(), value))
+                .collect()),
+            Some(Kind::Array) => JsonValue::Array(self.array.order.ids()
+                .filter_map(|dot| self.array.items.get(&dot))
+                .map(|node| node.value())
+                .collect()),
+        }
+    }
+}
+
+/// JSON document CRDT. It's a tree of nodes, where objects are represented as `ORMap`s, arrays as
+/// `RGA`s and primitive values as last write wins registers. Nodes are addressed using paths of
+/// object keys and array indexes, eg. `["users".into(), 0.into(), "name".into()]`.
+///
+/// Concurrent updates of different parts of the same document are merged together. Nested updates
+/// of a field win over its concurrent removal, while concurrent assignments of the same field
+/// are resolved using last write wins semantics - unless both of them assign an object (or both
+/// an array), in which case their fields (or elements) are merged together.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Json(Node);
+
+impl Json {
+
+    /// Assigns a value under a given path, creating all missing objects and arrays on the way.
+    /// An array index equal to array's length appends a new element to it.
+    pub fn set<V: Into<JsonValue>>(&mut self, id: PID, path: &[Segment], value: V) -> crate::Result<()> {
+        let mut node = &mut self.0;
+        for segment in path {
+            node = node.child(id, segment)?;
+        }
+        node.assign(id, value.into());
+        Ok(())
+    }
+
+    /// Inserts a value into an array under a given path, shifting all elements after it.
+    pub fn insert<V: Into<JsonValue>>(&mut self, id: PID, path: &[Segment], value: V) -> crate::Result<()> {
+        match path.split_last() {
+            Some((Segment::Index(index), parent)) => {
+                let len = match self.0.get(parent) {
+                    Some(node) if node.kind() == Some(&Kind::Array) => node.array.order.len(),
+                    _ => return Err(anyhow::anyhow!("Cannot insert into {:?}: it's not an array", parent)),
+                };
+                if *index > len {
+                    return Err(anyhow::anyhow!("Cannot insert element {} into an array of length {}", index, len));
+                }
+                let mut node = &mut self.0;
+                for segment in parent {
+                    node = node.child(id, segment)?;
+                }
+                node.insert(id, *index, value.into());
+                Ok(())
+            },
+            _ => Err(anyhow::anyhow!("Cannot insert into {:?}: path must end with an array index", path)),
+        }
+    }
+
+    /// Removes a value under a given path. Returns false if it didn't exist.
+    pub fn remove(&mut self, path: &[Segment]) -> crate::Result<bool> {
+        match path.split_last() {
+            None => Err(anyhow::anyhow!("Cannot remove a document root")),
+            Some((segment, parent)) => match self.0.get_mut(parent) {
+                None => Ok(false),
+                Some(node) => Ok(node.remove(segment)),
+            },
+        }
+    }
+
+    /// Returns a materialized value under a given path.
+    pub fn get(&self, path: &[Segment]) -> Option<JsonValue> {
+        self.0.get(path).map(|node| node.value())
+    }
+}
+
+impl Convergent for Json {
+    fn merge(&mut self, other: &Self) -> bool {
+        self.0.merge(&other.0)
+    }
+}
+
+impl<'m> Materialize<'m> for Json {
+    type Value = JsonValue;
+
+    fn value(&'m self) -> Self::Value {
+        self.0.value()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::convergent::json::{Json, JsonValue, Segment};
+    use crate::crdt::convergent::{Materialize, Convergent};
+    use smallvec::alloc::collections::BTreeMap;
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+
+    fn object(fields: Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    #[test]
+    fn json_identity() {
+        let a = Json::default();
+        assert_eq!(a.value(), JsonValue::Null);
+    }
+
+    #[test]
+    fn json_set_nested() {
+        let mut a = Json::default();
+        a.set(A, &["a".into(), "b".into(), 0.into()], "x").unwrap();
+        a.set(A, &["a".into(), "b".into(), 1.into()], 2i64).unwrap();
+        a.set(A, &["a".into(), "c".into()], true).unwrap();
+
+        let expected = object(vec![
+            ("a", object(vec![
+                ("b", vec![JsonValue::from("x"), JsonValue::from(2i64)].into()),
+                ("c", true.into()),
+            ])),
+        ]);
+        assert_eq!(a.value(), expected);
+        assert_eq!(a.get(&["a".into(), "b".into(), 1.into()]), Some(JsonValue::Number(2.0)));
+
+        // indexes past the end of an array are not allowed
+        assert!(a.set(A, &["a".into(), "b".into(), 5.into()], 1i64).is_err());
+    }
+
+    #[test]
+    fn json_replace_with_different_type() {
+        let mut a = Json::default();
+        a.set(A, &["a".into(), "b".into()], 1i64).unwrap();
+        a.set(A, &["a".into()], vec![1i64, 2]).unwrap();
+        assert_eq!(a.get(&["a".into()]), Some(vec![1i64, 2].into()));
+
+        a.set(A, &["a".into(), "c".into()], "x").unwrap();
+        assert_eq!(a.get(&["a".into()]), Some(object(vec![("c", "x".into())])));
+    }
+
+    #[test]
+    fn json_insert_remove() {
+        let mut a = Json::default();
+        a.set(A, &["list".into()], vec!["a", "c"]).unwrap();
+        a.insert(A, &["list".into(), 1.into()], "b").unwrap();
+        assert_eq!(a.get(&["list".into()]), Some(vec!["a", "b", "c"].into()));
+
+        assert!(a.remove(&["list".into(), 0.into()]).unwrap());
+        assert!(!a.remove(&["list".into(), 5.into()]).unwrap());
+        assert!(a.insert(A, &["list".into()], "d").is_err());
+        assert_eq!(a.get(&["list".into()]), Some(vec!["b", "c"].into()));
+
+        assert!(a.remove(&["list".into()]).unwrap());
+        assert_eq!(a.value(), JsonValue::Object(BTreeMap::new()));
+    }
+
+    #[test]
+    fn json_concurrent_updates() {
+        let mut a = Json::default();
+        a.set(A, &["users".into()], vec![object(vec![("name", "Alice".into())])]).unwrap();
+        let mut b = a.clone();
+
+        a.set(A, &["users".into(), 0.into(), "age".into()], 30i64).unwrap();
+        a.set(A, &["title".into()], "A").unwrap();
+        b.set(B, &["users".into(), 1.into()], object(vec![("name", "Bob".into())])).unwrap();
+        b.set(B, &["title".into()], "B").unwrap();
+
+        let a2 = a.clone();
+        assert!(a.merge(&b));
+        assert!(b.merge(&a2));
+
+        let expected = object(vec![
+            ("title", "B".into()),
+            ("users", JsonValue::Array(vec![
+                object(vec![("age", 30i64.into()), ("name", "Alice".into())]),
+                object(vec![("name", "Bob".into())]),
+            ])),
+        ]);
+        assert_eq!(a.value(), expected);
+        assert_eq!(b.value(), expected);
+        assert!(!a.merge(&b));
+    }
+
+    #[test]
+    fn json_update_wins_over_remove() {
+        let mut a = Json::default();
+        a.set(A, &["a".into(), "b".into()], 1i64).unwrap();
+        let mut b = a.clone();
+
+        a.remove(&["a".into()]).unwrap();
+        b.set(B, &["a".into(), "c".into()], 2i64).unwrap();
+
+        let a2 = a.clone();
+        a.merge(&b);
+        b.merge(&a2);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.get(&["a".into(), "c".into()]), Some(JsonValue::Number(2.0)));
+    }
+
+    #[test]
+    fn json_concurrent_assign() {
+        let mut a = Json::default();
+        a.set(A, &["o".into()], object(vec![("x", 0i64.into())])).unwrap();
+        let mut b = a.clone();
+
+        a.set(A, &["o".into()], object(vec![("x", 1i64.into())])).unwrap();
+        a.set(A, &["v".into()], "A").unwrap();
+        b.set(B, &["o".into()], object(vec![("y", 2i64.into())])).unwrap();
+        b.set(B, &["v".into()], vec!["B"]).unwrap();
+
+        let a2 = a.clone();
+        a.merge(&b);
+        b.merge(&a2);
+
+        // concurrently assigned objects are merged, other assignments are last write wins
+        let expected = object(vec![
+            ("o", object(vec![("x", 1i64.into()), ("y", 2i64.into())])),
+            ("v", vec!["B"].into()),
+        ]);
+        assert_eq!(a.value(), expected);
+        assert_eq!(b.value(), expected);
+    }
+
+    #[test]
+    fn json_nested_remove_does_not_revive_parent() {
+        let mut a = Json::default();
+        a.set(A, &["a".into()], object(vec![("b", 1i64.into()), ("c", 2i64.into())])).unwrap();
+        let mut b = a.clone();
+        let mut c = a.clone();
+
+        assert!(a.remove(&["a".into()]).unwrap());
+        assert!(b.remove(&["a".into(), "b".into()]).unwrap());
+
+        // nested removal is propagated to replicas that didn't remove its parent
+        c.merge(&b);
+        assert_eq!(c.get(&["a".into()]), Some(object(vec![("c", 2i64.into())])));
+
+        let a2 = a.clone();
+        a.merge(&b);
+        b.merge(&a2);
+        assert_eq!(a.value(), JsonValue::Object(BTreeMap::new()));
+        assert_eq!(b.value(), JsonValue::Object(BTreeMap::new()));
+    }
+
+    #[test]
+    fn json_remove_drops_array_items() {
+        let mut a = Json::default();
+        a.set(A, &["list".into()], vec!["x", "y"]).unwrap();
+        let mut b = a.clone();
+
+        assert!(a.remove(&["list".into(), 0.into()]).unwrap());
+        let a2 = a.clone();
+        a.merge(&b);
+        b.merge(&a2);
+
+        for replica in [&a, &b].iter() {
+            assert_eq!(replica.get(&["list".into()]), Some(vec!["y"].into()));
+            assert_eq!(replica.0.get(&["list".into()]).unwrap().array.items.len(), 1);
+        }
+    }
+
+    #[test]
+    fn json_serialization() {
+        let mut a = Json::default();
+        a.set(A, &["a".into(), 0.into()], "x").unwrap();
+        let path: Vec<Segment> = vec!["a".into(), 1.into()];
+        a.set(A, &path, JsonValue::Null).unwrap();
+
+        let bytes = serde_cbor::to_vec(&a).unwrap();
+        let b: Json = serde_cbor::from_slice(bytes.as_slice()).unwrap();
+        assert_eq!(b.value(), a.value());
+
+        let value = serde_cbor::to_vec(&a.value()).unwrap();
+        let value: JsonValue = serde_cbor::from_slice(value.as_slice()).unwrap();
+        assert_eq!(value, a.value());
+    }
+}
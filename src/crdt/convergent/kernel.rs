@@ -1,27 +1,41 @@
 use serde::{Serialize, Deserialize};
 use crate::vtime::{VTime, Dot};
 use smallvec::alloc::collections::BTreeSet;
-use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent};
+use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent, Prune};
 use std::collections::BTreeMap;
 use smallvec::SmallVec;
 use smallvec::alloc::collections::btree_map::{Values, Keys};
 use std::iter::FusedIterator;
 use crate::dotted_version::DottedVersion;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::ops::Deref;
+use std::borrow::Borrow;
+use std::marker::PhantomData;
 use crate::PID;
 
+/// A shared pointer used by kernel-based CRDTs (`ORSet`, `ORMap` and `MVRegister`) to share their
+/// values between state and its deltas.
+///
+/// `Rc<T>` is the default, single-threaded option. Use `Arc<T>` to make these CRDTs `Send` and
+/// `Sync`, ie. when they need to be moved between tokio tasks.
+pub trait SharedPtr<T>: Clone + Ord + Deref<Target=T> + Borrow<T> + From<T> {}
+
+impl<T: Ord> SharedPtr<T> for Rc<T> {}
+
+impl<T: Ord> SharedPtr<T> for Arc<T> {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Kernel<T: Ord> {
+pub struct Kernel<T: Ord, P: SharedPtr<T> = Rc<T>> {
     seen: DottedVersion,
-    entries: BTreeMap<Rc<T>, SmallVec<[Dot;1]>>,
+    entries: BTreeMap<P, SmallVec<[Dot;1]>>,
 
     #[serde(skip_serializing, skip_deserializing, default = "Option::default")]
-    delta: Option<Delta<T>>,
+    delta: Option<Delta<T, P>>,
 }
 
-impl<T: Ord> Kernel<T> {
-    pub fn insert(&mut self, id: PID, value: Rc<T>) -> Dot {
+impl<T: Ord, P: SharedPtr<T>> Kernel<T, P> {
+    pub fn insert(&mut self, id: PID, value: P) -> Dot {
         let dot = self.seen.inc(id);
         let e = self.entries.entry(value.clone()).or_default();
         e.push(dot);
@@ -55,29 +69,36 @@ impl<T: Ord> Kernel<T> {
     pub fn is_empty(&self) -> bool { self.entries.is_empty() }
 
 
-    pub(crate) fn merge_with<F>(&mut self, other: &Self, mut f: F) -> bool where F:FnMut(MergeOp<'_, T>) -> () {
+    pub(crate) fn merge_with<F>(&mut self, other: &Self, mut f: F) -> bool where F:FnMut(MergeOp<'_, T, P>) -> () {
         let mut changed = false;
 
         // insert all values that were not "seen"
         for (value, other_dots) in other.entries.iter() {
-            let e = self.entries.entry(value.clone()).or_default();
             for dot in other_dots {
-                if !e.contains(dot) {
-                    e.push(*dot);
-                    changed = true;
+                if !self.seen.contains(dot) {
+                    let e = self.entries.entry(value.clone()).or_default();
+                    if !e.contains(dot) {
+                        e.push(*dot);
+                        changed = true;
 
-                    f(MergeOp::Updated(value.clone()));
+                        f(MergeOp::Updated(value.clone()));
+                    }
                 }
             }
         }
 
-        // remove all values that were seen but are not present in other
+        // remove all dots that were seen but are not present in other, an entry is removed
+        // only when none of its dots (eg. concurrent re-insertions) have survived
         self.entries.drain_filter(|value, dots| {
-            if dots.iter().any(|d| other.seen.contains(d)) && !other.entries.contains_key(value) {
+            let other_dots = other.entries.get(value);
+            let len = dots.len();
+            dots.retain(|d| !other.seen.contains(d) || other_dots.map(|o| o.contains(d)).unwrap_or(false));
+            if dots.is_empty() {
                 changed = true;
                 f(MergeOp::Removed(value));
                 true
             } else {
+                changed = changed || dots.len() != len;
                 false
             }
         });
@@ -86,7 +107,7 @@ impl<T: Ord> Kernel<T> {
         changed
     }
 
-    pub(crate) fn merge_with_delta<F>(&mut self, other: &Delta<T>, mut f: F) -> bool where F:FnMut(MergeOp<'_, T>) -> () {
+    pub(crate) fn merge_with_delta<F>(&mut self, other: &Delta<T, P>, mut f: F) -> bool where F:FnMut(MergeOp<'_, T, P>) -> () {
         let mut changed = false;
         for (value, dots) in other.inserts.iter() {
             let unseen = dots.iter().any(|dot| !self.seen.contains(dot));
@@ -122,12 +143,12 @@ impl<T: Ord> Kernel<T> {
 }
 
 
-pub(crate) enum MergeOp<'a, T> {
-    Updated(Rc<T>),
+pub(crate) enum MergeOp<'a, T, P: SharedPtr<T>> {
+    Updated(P),
     Removed(&'a T),
 }
 
-impl<T: Ord> Default for Kernel<T> {
+impl<T: Ord, P: SharedPtr<T>> Default for Kernel<T, P> {
     fn default() -> Self {
         Kernel {
             seen: DottedVersion::default(),
@@ -137,14 +158,14 @@ impl<T: Ord> Default for Kernel<T> {
     }
 }
 
-impl<T: Ord> Convergent for Kernel<T> {
+impl<T: Ord, P: SharedPtr<T>> Convergent for Kernel<T, P> {
     fn merge(&mut self, other: &Self) -> bool {
         self.merge_with(other, |_| {})
     }
 }
 
-impl<T: Ord> DeltaConvergent for Kernel<T> {
-    type Delta = Delta<T>;
+impl<T: Ord, P: SharedPtr<T>> DeltaConvergent for Kernel<T, P> {
+    type Delta = Delta<T, P>;
 
     fn delta(&mut self) -> Option<Self::Delta> {
         self.delta.take()
@@ -156,8 +177,14 @@ impl<T: Ord> DeltaConvergent for Kernel<T> {
 }
 
 
-impl<'m, T: Ord + 'm> Materialize<'m> for Kernel<T> {
-    type Value = Value<'m, T>;
+impl<T: Ord, P: SharedPtr<T>> Prune for Kernel<T, P> {
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        self.seen.prune(timestamp)
+    }
+}
+
+impl<'m, T: Ord + 'm, P: SharedPtr<T> + 'm> Materialize<'m> for Kernel<T, P> {
+    type Value = Value<'m, P>;
 
     fn value(&'m self) -> Self::Value {
         Value(self.entries.keys())
@@ -165,44 +192,47 @@ impl<'m, T: Ord + 'm> Materialize<'m> for Kernel<T> {
 }
 
 #[derive(Clone, Debug)]
-pub struct Value<'a, T>(Keys<'a, Rc<T>, SmallVec<[Dot;1]>>);
+pub struct Value<'a, P>(Keys<'a, P, SmallVec<[Dot;1]>>);
 
-impl<'a, V> Iterator for Value<'a, V> {
-    type Item = &'a V;
+impl<'a, P: Deref> Iterator for Value<'a, P> {
+    type Item = &'a P::Target;
 
-    fn next(&mut self) -> Option<&'a V> { self.0.next().map(|rc| rc.deref()) }
+    fn next(&mut self) -> Option<Self::Item> { self.0.next().map(|rc| rc.deref()) }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.0.size_hint()
     }
 
-    fn last(mut self) -> Option<&'a V> {
+    fn last(mut self) -> Option<Self::Item> {
         self.next_back()
     }
 }
 
-impl<'a, V> DoubleEndedIterator for Value<'a, V> {
-    fn next_back(&mut self) -> Option<&'a V> {
+impl<'a, P: Deref> DoubleEndedIterator for Value<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
         self.0.next_back().map(|rc| rc.deref())
     }
 }
 
-impl<V> ExactSizeIterator for Value<'_, V> {
+impl<P: Deref> ExactSizeIterator for Value<'_, P> {
     fn len(&self) -> usize {
         self.0.len()
     }
 }
 
-impl<V> FusedIterator for Value<'_, V> {}
+impl<P: Deref> FusedIterator for Value<'_, P> {}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Delta<T: Ord> {
-    inserts: BTreeMap<Rc<T>, SmallVec<[Dot;1]>>,
-    removals: SmallVec<[Dot;1]>
+pub struct Delta<T: Ord, P: SharedPtr<T> = Rc<T>> {
+    inserts: BTreeMap<P, SmallVec<[Dot;1]>>,
+    removals: SmallVec<[Dot;1]>,
+
+    #[serde(skip)]
+    _value: PhantomData<T>,
 }
 
-impl<T: Ord> Delta<T> {
-    fn insert(&mut self, value: Rc<T>, dot: Dot) {
+impl<T: Ord, P: SharedPtr<T>> Delta<T, P> {
+    fn insert(&mut self, value: P, dot: Dot) {
         let e = self.inserts.entry(value).or_default();
         if !e.contains(&dot) {
             e.push(dot);
@@ -221,29 +251,30 @@ impl<T: Ord> Delta<T> {
 
     pub fn has_removals(&self) -> bool { !self.removals.is_empty() }
 
-    pub fn keys(&self) -> DeltaKeys<'_, T> { DeltaKeys(self.inserts.keys()) }
+    pub fn keys(&self) -> DeltaKeys<'_, P> { DeltaKeys(self.inserts.keys()) }
 }
 
-pub struct DeltaKeys<'a, T>(std::collections::btree_map::Keys<'a, Rc<T>, SmallVec<[Dot;1]>>);
+pub struct DeltaKeys<'a, P>(std::collections::btree_map::Keys<'a, P, SmallVec<[Dot;1]>>);
 
-impl<'a, T> Iterator for DeltaKeys<'a, T> {
-    type Item = Rc<T>;
+impl<'a, P: Clone> Iterator for DeltaKeys<'a, P> {
+    type Item = P;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next().cloned()
     }
 }
 
-impl<T: Ord> Default for Delta<T> {
+impl<T: Ord, P: SharedPtr<T>> Default for Delta<T, P> {
     fn default() -> Self {
         Delta {
             inserts: BTreeMap::new(),
             removals: SmallVec::default(),
+            _value: PhantomData,
         }
     }
 }
 
-impl<T: Ord> Convergent for Delta<T> {
+impl<T: Ord, P: SharedPtr<T>> Convergent for Delta<T, P> {
     fn merge(&mut self, other: &Self) -> bool {
         let mut inserts_changed = false;
         for (key, value) in other.inserts.iter() {
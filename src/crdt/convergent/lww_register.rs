@@ -1,4 +1,4 @@
-use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent};
+use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent, Prune};
 use serde::{Serialize, Deserialize};
 use crate::hlc::HybridTime;
 use serde::export::PhantomData;
@@ -81,6 +81,8 @@ impl<T: Clone, C> DeltaConvergent for LWWRegister<T, C> {
     }
 }
 
+impl<T, C> Prune for LWWRegister<T, C> {}
+
 impl<'m, T: 'm, C> Materialize<'m> for LWWRegister<T, C> {
     type Value = Option<&'m T>;
 
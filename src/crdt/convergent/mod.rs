@@ -1,11 +1,17 @@
-mod bcounter;
-mod mv_register;
-mod kernel;
-mod or_set;
-mod or_map;
+pub mod bcounter;
+pub mod mv_register;
+pub mod kernel;
+pub mod or_set;
+pub mod or_map;
 mod gcounter;
 mod pncounter;
 mod lww_register;
+pub mod rga;
+pub mod text;
+pub mod json;
+pub mod anti_entropy;
+
+use crate::vtime::VTime;
 
 /// A convergent trait that can be used to merge data from two instances together. Returns a true,
 /// when self has been changed in result of merge operation (there were new updates carried by
@@ -52,4 +58,11 @@ pub trait DeltaConvergent {
 pub trait Materialize<'m> {
     type Value;
     fn value(&'m self) -> Self::Value;
-}
\ No newline at end of file
+}
+
+/// Trait implemented by delta-state CRDTs, which can compact their metadata once updates they
+/// refer to have become causally stable - meaning that they have been observed by all replicas
+/// (see: `crdt::stability::StabilityTracker`). Returns true if any metadata has been discarded.
+pub trait Prune {
+    fn prune(&mut self, _timestamp: &VTime) -> bool { false }
+}
@@ -1,31 +1,35 @@
-use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent, kernel};
-use crate::crdt::convergent::kernel::{Kernel, Value};
+use crate::crdt::convergent::{Convergent, Materialize, DeltaConvergent, Prune, kernel};
+use crate::vtime::VTime;
+use crate::crdt::convergent::kernel::{Kernel, Value, SharedPtr};
 use serde::{Serialize, Deserialize};
 use std::rc::Rc;
 use crate::PID;
 
-/// Multi-value register.
+/// Multi-value register, which stores its values behind a shared pointer `P`, see [`SharedPtr`].
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct MVRegister<T: Ord>(Kernel<T>);
+pub struct SharedMVRegister<T: Ord, P: SharedPtr<T>>(Kernel<T, P>);
 
-impl<T: Ord> MVRegister<T> {
+/// Multi-value register, which shares its values using `Rc` pointers.
+pub type MVRegister<T> = SharedMVRegister<T, Rc<T>>;
+
+impl<T: Ord, P: SharedPtr<T>> SharedMVRegister<T, P> {
 
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
 
     pub fn assign(&mut self, id: PID, value: T) {
         self.0.clear();
-        self.0.insert(id, Rc::new(value));
+        self.0.insert(id, P::from(value));
     }
 }
 
-impl<T: Ord> Default for MVRegister<T> {
+impl<T: Ord, P: SharedPtr<T>> Default for SharedMVRegister<T, P> {
     fn default() -> Self {
-        MVRegister(Kernel::default())
+        SharedMVRegister(Kernel::default())
     }
 }
 
-impl<'m, T: Ord + 'm> Materialize<'m> for MVRegister<T> {
-    type Value = Value<'m, T>;
+impl<'m, T: Ord + 'm, P: SharedPtr<T> + 'm> Materialize<'m> for SharedMVRegister<T, P> {
+    type Value = Value<'m, P>;
 
     fn value(&'m self) -> Self::Value {
         let kernel = &self.0;
@@ -33,14 +37,20 @@ impl<'m, T: Ord + 'm> Materialize<'m> for MVRegister<T> {
     }
 }
 
-impl<T: Ord> Convergent for MVRegister<T> {
+impl<T: Ord, P: SharedPtr<T>> Convergent for SharedMVRegister<T, P> {
     fn merge(&mut self, other: &Self) -> bool {
         self.0.merge(&other.0)
     }
 }
 
-impl<T: Ord> DeltaConvergent for MVRegister<T> {
-    type Delta = Delta<T>;
+impl<T: Ord, P: SharedPtr<T>> Prune for SharedMVRegister<T, P> {
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        self.0.prune(timestamp)
+    }
+}
+
+impl<T: Ord, P: SharedPtr<T>> DeltaConvergent for SharedMVRegister<T, P> {
+    type Delta = Delta<T, P>;
 
     fn delta(&mut self) -> Option<Self::Delta> {
         self.0.delta()
@@ -51,7 +61,7 @@ impl<T: Ord> DeltaConvergent for MVRegister<T> {
     }
 }
 
-pub type Delta<T> = kernel::Delta<T>;
+pub type Delta<T, P = Rc<T>> = kernel::Delta<T, P>;
 
 #[cfg(test)]
 mod test {
@@ -1,24 +1,29 @@
-use crate::crdt::convergent::kernel::{Kernel, MergeOp};
+use crate::crdt::convergent::kernel::{Kernel, MergeOp, SharedPtr};
 use serde::{Serialize, Deserialize};
-use crate::crdt::convergent::{Materialize, Convergent, kernel, DeltaConvergent};
+use std::rc::Rc;
+use crate::crdt::convergent::{Materialize, Convergent, kernel, DeltaConvergent, Prune};
+use crate::vtime::VTime;
 use smallvec::alloc::collections::BTreeMap;
 use std::cmp::Ordering;
-use std::rc::Rc;
 use std::ops::{Deref, DerefMut};
 use crate::PID;
 
 type IEntry<'a, K, V> = std::collections::btree_map::Entry<'a, K, V>;
 
+/// Observed-remove map, which stores its keys behind a shared pointer `P`, see [`SharedPtr`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ORMap<K: Ord, V> {
-    kernel: Kernel<K>,
-    entries: BTreeMap<Rc<K>, V>,
+pub struct SharedORMap<K: Ord, V, P: SharedPtr<K>> {
+    kernel: Kernel<K, P>,
+    entries: BTreeMap<P, V>,
 }
 
-impl<K: Ord, V> ORMap<K, V> {
+/// Observed-remove map, which shares its keys using `Rc` pointers.
+pub type ORMap<K, V> = SharedORMap<K, V, Rc<K>>;
+
+impl<K: Ord, V, P: SharedPtr<K>> SharedORMap<K, V, P> {
 
-    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
-        let key = Rc::new(key);
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, P> {
+        let key = P::from(key);
         Entry {
             key,
             handle: self,
@@ -30,24 +35,46 @@ impl<K: Ord, V> ORMap<K, V> {
         self.entries.remove(key)
     }
 
+    pub fn clear(&mut self) {
+        self.kernel.clear();
+        self.entries.clear();
+    }
+
     pub fn is_empty(&self) -> bool { self.entries.is_empty() }
 
     pub fn len(&self) -> usize { self.kernel.len() }
+
+    /// Returns a value stored under a given key without materializing it.
+    pub fn lookup(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Returns a mutable reference to a value stored under a given key. Unlike `and_modify`, it
+    /// doesn't mark that key as updated, so it won't be revived if it was concurrently removed,
+    /// but also changes made this way are not propagated by `merge` on their own.
+    pub fn lookup_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key)
+    }
+
+    /// Iterates over all keys and their (non-materialized) values.
+    pub fn iter(&self) -> impl Iterator<Item=(&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k.deref(), v))
+    }
 }
 
-impl<'m, K: Ord, V: Materialize<'m>> ORMap<K, V> {
+impl<'m, K: Ord, V: Materialize<'m>, P: SharedPtr<K>> SharedORMap<K, V, P> {
     pub fn get(&'m self, key: &K) -> Option<V::Value> {
         self.entries.get(key).map(|v| v.value())
     }
 }
 
 #[derive(Debug)]
-pub struct Entry<'a, K: Ord, V> {
-    key: Rc<K>,
-    handle: &'a mut ORMap<K, V>,
+pub struct Entry<'a, K: Ord, V, P: SharedPtr<K> = Rc<K>> {
+    key: P,
+    handle: &'a mut SharedORMap<K, V, P>,
 }
 
-impl<'a, K: Ord, V> Entry<'a, K, V> {
+impl<'a, K: Ord, V, P: SharedPtr<K>> Entry<'a, K, V, P> {
     pub fn key(&self) -> &K { self.key.deref() }
 
     pub fn or_insert(self, id: PID, default: V) -> &'a mut V {
@@ -103,7 +130,7 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     }
 }
 
-impl<'a, K: Ord, V: Default> Entry<'a, K, V> {
+impl<'a, K: Ord, V: Default, P: SharedPtr<K>> Entry<'a, K, V, P> {
 
     pub fn or_default(self, id: PID) -> &'a mut V {
         let key = self.key;
@@ -120,16 +147,16 @@ impl<'a, K: Ord, V: Default> Entry<'a, K, V> {
 
 }
 
-impl<K: Ord, V> Default for ORMap<K, V> {
+impl<K: Ord, V, P: SharedPtr<K>> Default for SharedORMap<K, V, P> {
     fn default() -> Self {
-        ORMap {
+        SharedORMap {
             kernel: Kernel::default(),
             entries: BTreeMap::new(),
         }
     }
 }
 
-impl<'m, K: Ord + 'm, V: Materialize<'m>> Materialize<'m> for ORMap<K, V> {
+impl<'m, K: Ord + 'm, V: Materialize<'m>, P: SharedPtr<K> + 'm> Materialize<'m> for SharedORMap<K, V, P> {
     type Value = BTreeMap<&'m K, V::Value>;
 
     fn value(&'m self) -> Self::Value {
@@ -139,7 +166,7 @@ impl<'m, K: Ord + 'm, V: Materialize<'m>> Materialize<'m> for ORMap<K, V> {
     }
 }
 
-impl<K: Ord, V: Convergent + Default> Convergent for ORMap<K, V> {
+impl<K: Ord, V: Convergent + Default, P: SharedPtr<K>> Convergent for SharedORMap<K, V, P> {
     fn merge(&mut self, other: &Self) -> bool {
         let kernel = &mut self.kernel;
         let entries = &mut self.entries;
@@ -159,8 +186,18 @@ impl<K: Ord, V: Convergent + Default> Convergent for ORMap<K, V> {
     }
 }
 
-impl<K: Ord, V: DeltaConvergent + Default> DeltaConvergent for ORMap<K, V> {
-    type Delta = Delta<K, V::Delta>;
+impl<K: Ord, V: Prune, P: SharedPtr<K>> Prune for SharedORMap<K, V, P> {
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        let mut changed = self.kernel.prune(timestamp);
+        for value in self.entries.values_mut() {
+            changed = value.prune(timestamp) || changed;
+        }
+        changed
+    }
+}
+
+impl<K: Ord, V: DeltaConvergent + Default, P: SharedPtr<K>> DeltaConvergent for SharedORMap<K, V, P> {
+    type Delta = Delta<K, V::Delta, P>;
 
     fn delta(&mut self) -> Option<Self::Delta> {
         if let Some(mut kernel_delta) = self.kernel.delta() {
@@ -197,12 +234,12 @@ impl<K: Ord, V: DeltaConvergent + Default> DeltaConvergent for ORMap<K, V> {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Delta<K: Ord, D> {
-    kernel: kernel::Delta<K>,
-    entries: BTreeMap<Rc<K>, D>,
+pub struct Delta<K: Ord, D, P: SharedPtr<K> = Rc<K>> {
+    kernel: kernel::Delta<K, P>,
+    entries: BTreeMap<P, D>,
 }
 
-impl<K: Ord, D> Default for Delta<K, D> {
+impl<K: Ord, D, P: SharedPtr<K>> Default for Delta<K, D, P> {
     fn default() -> Self {
         Delta {
             kernel: kernel::Delta::default(),
@@ -211,7 +248,7 @@ impl<K: Ord, D> Default for Delta<K, D> {
     }
 }
 
-impl<K: Ord, D: Convergent + Default> Convergent for Delta<K, D> {
+impl<K: Ord, D: Convergent + Default, P: SharedPtr<K>> Convergent for Delta<K, D, P> {
     fn merge(&mut self, other: &Self) -> bool {
         let mut changed = self.kernel.merge(&other.kernel);
         for (key, value) in other.entries.iter() {
@@ -232,13 +269,14 @@ impl<K: Ord, D: Convergent + Default> Convergent for Delta<K, D> {
 #[cfg(test)]
 mod test {
     use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent};
-    use crate::crdt::convergent::or_map::ORMap;
+    use crate::crdt::convergent::or_map::{ORMap, SharedORMap};
     use crate::crdt::convergent::lww_register::LWWRegister;
     use crate::hlc::HybridTime;
     use std::collections::{BTreeMap, BTreeSet};
     use crate::crdt::convergent::mv_register::MVRegister;
-    use crate::crdt::convergent::or_set::ORSet;
+    use crate::crdt::convergent::or_set::{ORSet, SharedORSet};
     use futures::StreamExt;
+    use std::sync::Arc;
     use crate::PID;
 
     const A: PID = 1;
@@ -321,4 +359,23 @@ mod test {
         assert!(!a.merge(&b2));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn ormap_send_across_threads() {
+        type Map = SharedORMap<String, SharedORSet<u32, Arc<u32>>, Arc<String>>;
+
+        let mut a = Map::default();
+        a.entry("key".to_string()).or_default(A).insert(A, 1);
+
+        let a = std::thread::spawn(move || {
+            let mut b = Map::default();
+            b.entry("key".to_string()).or_default(B).insert(B, 2);
+            a.merge(&b);
+            a
+        }).join().unwrap();
+
+        let key = "key".to_string();
+        let mut expected = BTreeMap::new();
+        expected.insert(&key, vec![&1,&2].into_iter().collect::<BTreeSet<&u32>>());
+        assert_eq!(a.value(), expected);
+    }
+}
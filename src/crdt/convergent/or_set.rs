@@ -1,18 +1,22 @@
-use crate::crdt::convergent::kernel::{Kernel};
+use crate::crdt::convergent::kernel::{Kernel, SharedPtr};
 use serde::{Serialize, Deserialize};
-use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent, kernel};
-use crate::vtime::Dot;
+use std::rc::Rc;
+use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent, Prune, kernel};
+use crate::vtime::{Dot, VTime};
 use smallvec::SmallVec;
 use smallvec::alloc::collections::{BTreeSet, BTreeMap};
-use std::rc::Rc;
 use crate::PID;
 
+/// Observed-remove set, which stores its elements behind a shared pointer `P`, see [`SharedPtr`].
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ORSet<T: Ord>(Kernel<T>);
+pub struct SharedORSet<T: Ord, P: SharedPtr<T>>(Kernel<T, P>);
 
-impl<T: Ord> ORSet<T> {
+/// Observed-remove set, which shares its elements using `Rc` pointers.
+pub type ORSet<T> = SharedORSet<T, Rc<T>>;
+
+impl<T: Ord, P: SharedPtr<T>> SharedORSet<T, P> {
     pub fn insert(&mut self, id: PID, value: T) {
-        self.0.insert(id, Rc::new(value));
+        self.0.insert(id, P::from(value));
     }
 
     pub fn remove(&mut self, value: &T) {
@@ -24,13 +28,13 @@ impl<T: Ord> ORSet<T> {
     pub fn len(&self) -> usize { self.0.len() }
 }
 
-impl<T: Ord> Default for ORSet<T> {
+impl<T: Ord, P: SharedPtr<T>> Default for SharedORSet<T, P> {
     fn default() -> Self {
-        ORSet(Kernel::default())
+        SharedORSet(Kernel::default())
     }
 }
 
-impl<'m, T: Ord + 'm> Materialize<'m> for ORSet<T> where T: Ord {
+impl<'m, T: Ord + 'm, P: SharedPtr<T> + 'm> Materialize<'m> for SharedORSet<T, P> {
     type Value = BTreeSet<&'m T>;
 
     fn value(&'m self) -> Self::Value {
@@ -39,14 +43,20 @@ impl<'m, T: Ord + 'm> Materialize<'m> for ORSet<T> where T: Ord {
     }
 }
 
-impl<T: Ord> Convergent for ORSet<T> {
+impl<T: Ord, P: SharedPtr<T>> Convergent for SharedORSet<T, P> {
     fn merge(&mut self, other: &Self) -> bool {
         self.0.merge(&other.0)
     }
 }
 
-impl<T: Ord> DeltaConvergent for ORSet<T> {
-    type Delta = Delta<T>;
+impl<T: Ord, P: SharedPtr<T>> Prune for SharedORSet<T, P> {
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        self.0.prune(timestamp)
+    }
+}
+
+impl<T: Ord, P: SharedPtr<T>> DeltaConvergent for SharedORSet<T, P> {
+    type Delta = Delta<T, P>;
 
     fn delta(&mut self) -> Option<Self::Delta> {
         self.0.delta()
@@ -57,13 +67,14 @@ impl<T: Ord> DeltaConvergent for ORSet<T> {
     }
 }
 
-pub type Delta<T> = kernel::Delta<T>;
+pub type Delta<T, P = Rc<T>> = kernel::Delta<T, P>;
 
 #[cfg(test)]
 mod test {
-    use crate::crdt::convergent::or_set::ORSet;
+    use crate::crdt::convergent::or_set::{ORSet, SharedORSet};
     use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent};
     use smallvec::alloc::collections::BTreeSet;
+    use std::sync::Arc;
     use crate::PID;
 
     const A: PID = 1;
@@ -161,9 +172,39 @@ mod test {
 
         let mut expected = BTreeSet::new();
         expected.insert(&"A");
-        expected.insert(&"A");
+        expected.insert(&"B");
 
+        let a2 = a.clone();
         assert!(a.merge(&b));
+        assert!(b.merge(&a2));
         assert_eq!(a.value(), expected);
+        assert_eq!(b.value(), expected);
     }
-}
\ No newline at end of file
+
+    fn assert_send<T: Send + Sync>(_: &T) {}
+
+    #[test]
+    fn orset_send_across_threads() {
+        let mut a: SharedORSet<String, Arc<String>> = SharedORSet::default();
+        a.insert(A, "A".to_string());
+
+        let delta = a.delta().expect("delta: A");
+        let b = std::thread::spawn(move || {
+            let mut b: SharedORSet<String, Arc<String>> = SharedORSet::default();
+            b.merge_delta(&delta);
+            b.insert(B, "B".to_string());
+            b
+        }).join().unwrap();
+
+        assert!(a.merge(&b));
+        assert_eq!(a.len(), 2);
+
+        // a whole set can be moved into another thread as well
+        assert_send(&a);
+        let a = std::thread::spawn(move || {
+            a.remove(&"A".to_string());
+            a
+        }).join().unwrap();
+        assert_eq!(a.value(), vec![&"B".to_string()].into_iter().collect());
+    }
+}
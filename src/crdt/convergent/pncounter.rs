@@ -1,6 +1,6 @@
 use crate::crdt::convergent::gcounter;
 use crate::crdt::convergent::gcounter::{GCounter};
-use crate::crdt::convergent::{DeltaConvergent, Convergent, Materialize};
+use crate::crdt::convergent::{DeltaConvergent, Convergent, Materialize, Prune};
 use serde::{Serialize,Deserialize};
 use crate::PID;
 
@@ -67,6 +67,8 @@ impl DeltaConvergent for PNCounter {
     }
 }
 
+impl Prune for PNCounter {}
+
 impl<'m> Materialize<'m> for PNCounter {
     type Value = i64;
 
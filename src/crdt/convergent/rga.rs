@@ -0,0 +1,517 @@
+use serde::{Serialize, Deserialize};
+use smallvec::alloc::collections::{BTreeMap, BTreeSet};
+use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize, Prune};
+use crate::dotted_version::DottedVersion;
+use crate::vtime::Dot;
+use crate::PID;
+
+/// A single element of `RGA`. Removed elements are kept as tombstones (with no value), since
+/// concurrently inserted elements may still refer to them as their origin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node<T> {
+    id: Dot,
+    lamport: u64,
+    origin: Option<Dot>,
+    value: Slot<T>,
+}
+
+impl<T> Node<T> {
+    /// Ordering key used to resolve concurrent insertions of elements after the same origin.
+    fn key(&self) -> (u64, PID) { (self.lamport, self.id.pid()) }
+
+    fn is_removed(&self) -> bool { matches!(self.value, Slot::Removed) }
+}
+
+/// Value of an `RGA` element. Unlike `Option`, it's serialized unambiguously even for unit types.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Slot<T> {
+    Value(T),
+    Removed,
+}
+
+impl<T> Slot<T> {
+    fn as_ref(&self) -> Option<&T> {
+        match self {
+            Slot::Value(value) => Some(value),
+            Slot::Removed => None,
+        }
+    }
+
+    fn take(&mut self) -> Option<T> {
+        match std::mem::replace(self, Slot::Removed) {
+            Slot::Value(value) => Some(value),
+            Slot::Removed => None,
+        }
+    }
+}
+
+/// Replicated Growable Array - an ordered list CRDT. Every element is uniquely identified by
+/// a `Dot` and remembers an element after which it was originally inserted (its origin).
+///
+/// Elements inserted concurrently after the same origin are ordered by their Lamport timestamps
+/// (with replica identifiers used as tie breakers), so that the most recent insertion lands
+/// closer to its origin. Removed elements are retained as tombstones.
+///
+/// Deltas are not required to arrive in causal order: insertions which origin is not known yet,
+/// and removals of elements which have not been inserted yet, are kept aside until their
+/// dependencies are merged in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RGA<T> {
+    seen: DottedVersion,
+    lamport: u64,
+    nodes: Vec<Node<T>>,
+    pending: BTreeMap<Dot, Node<T>>,
+    pending_removals: BTreeSet<Dot>,
+
+    #[serde(skip_serializing, skip_deserializing, default = "Option::default")]
+    delta: Option<Delta<T>>,
+}
+
+impl<T: Clone> RGA<T> {
+
+    /// Inserts a `value` at a given `index`, shifting all elements after it to the right.
+    /// Returns a `Dot` uniquely identifying inserted element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, id: PID, index: usize, value: T) -> Dot {
+        let origin = if index == 0 {
+            None
+        } else {
+            let i = self.position(index - 1).expect("RGA::insert - index out of bounds");
+            Some(self.nodes[i].id)
+        };
+        self.lamport += 1;
+        let node = Node {
+            id: self.seen.inc(id),
+            lamport: self.lamport,
+            origin,
+            value: Slot::Value(value),
+        };
+        let dot = node.id;
+
+        let mut delta = self.delta.take().unwrap_or_default();
+        delta.inserts.insert(dot, node.clone());
+        self.delta = Some(delta);
+
+        self.place(node);
+        dot
+    }
+
+    /// Removes an element at a given `index`, returning its value. Returns `None` if `index` is
+    /// out of bounds.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let i = self.position(index)?;
+        let node = &mut self.nodes[i];
+        let value = node.value.take();
+
+        let mut delta = self.delta.take().unwrap_or_default();
+        delta.removals.insert(node.id);
+        self.delta = Some(delta);
+
+        value
+    }
+}
+
+impl<T> RGA<T> {
+
+    /// Returns a number of (non-removed) elements.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().filter(|n| !n.is_removed()).count()
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns an element at a given `index`.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.iter().nth(index)
+    }
+
+    /// Returns an identifier of an element at a given `index`.
+    pub fn id(&self, index: usize) -> Option<Dot> {
+        self.position(index).map(|i| self.nodes[i].id)
+    }
+
+    /// Returns a current index of an element identified by a given `Dot`, unless it doesn't exist
+    /// or has been removed.
+    pub fn index_of(&self, id: &Dot) -> Option<usize> {
+        let mut index = 0;
+        for node in self.nodes.iter() {
+            if node.id == *id {
+                return if node.is_removed() { None } else { Some(index) };
+            } else if !node.is_removed() {
+                index += 1;
+            }
+        }
+        None
+    }
+
+    /// Iterates over (non-removed) elements in their order.
+    pub fn iter(&self) -> impl Iterator<Item=&T> {
+        self.nodes.iter().filter_map(|n| n.value.as_ref())
+    }
+
+    /// Iterates over identifiers of (non-removed) elements in their order.
+    pub fn ids(&self) -> impl Iterator<Item=Dot> + '_ {
+        self.nodes.iter().filter(|n| !n.is_removed()).map(|n| n.id)
+    }
+
+    /// Maps an index of a visible element into its position within `nodes` (including tombstones).
+    fn position(&self, index: usize) -> Option<usize> {
+        self.nodes.iter()
+            .enumerate()
+            .filter(|(_, n)| !n.is_removed())
+            .nth(index)
+            .map(|(i, _)| i)
+    }
+
+    fn find(&self, id: &Dot) -> Option<usize> {
+        self.nodes.iter().position(|n| n.id == *id)
+    }
+
+    fn is_known(&self, id: &Option<Dot>) -> bool {
+        match id {
+            None => true,
+            Some(dot) => self.seen.contains(dot),
+        }
+    }
+
+    /// Integrates a given node into a current list. If its origin is not known yet, node is kept
+    /// as pending. Returns true if a current list has been changed.
+    fn integrate(&mut self, node: Node<T>) -> bool {
+        if self.seen.contains(&node.id) {
+            return node.is_removed() && self.tombstone(node.id);
+        }
+        if !self.is_known(&node.origin) {
+            return match self.pending.get_mut(&node.id) {
+                None => {
+                    self.pending.insert(node.id, node);
+                    true
+                },
+                Some(pending) if node.is_removed() && !pending.is_removed() => {
+                    pending.value = Slot::Removed;
+                    true
+                },
+                Some(_) => false,
+            };
+        }
+
+        self.place(node);
+        true
+    }
+
+    /// Puts a given node, which origin is known, at its position in a current list.
+    fn place(&mut self, mut node: Node<T>) {
+        if self.pending_removals.remove(&node.id) {
+            node.value = Slot::Removed;
+        }
+
+        let mut i = match node.origin {
+            None => 0,
+            Some(origin) => self.find(&origin).expect("Defect: RGA::place - origin not found") + 1,
+        };
+        // skip over elements inserted concurrently after the same origin with a higher priority
+        // (together with their successors, as they always have higher Lamport timestamps)
+        let key = node.key();
+        while i < self.nodes.len() && self.nodes[i].key() > key {
+            i += 1;
+        }
+
+        self.seen.add(node.id);
+        self.lamport = self.lamport.max(node.lamport);
+        let id = node.id;
+        self.nodes.insert(i, node);
+
+        // integrate pending nodes, which were waiting for a current one
+        let ready: Vec<_> = self.pending.iter()
+            .filter(|(_, n)| n.origin == Some(id))
+            .map(|(dot, _)| *dot)
+            .collect();
+        for dot in ready {
+            if let Some(node) = self.pending.remove(&dot) {
+                self.integrate(node);
+            }
+        }
+    }
+
+    /// Marks an element identified by a given `Dot` as removed. If that element was not inserted
+    /// yet, its removal is kept as pending. Returns true if a current list has been changed.
+    fn tombstone(&mut self, id: Dot) -> bool {
+        if self.seen.contains(&id) {
+            let i = self.find(&id).expect("Defect: RGA::tombstone - observed node not found");
+            self.nodes[i].value.take().is_some()
+        } else if let Some(node) = self.pending.get_mut(&id) {
+            node.value.take().is_some()
+        } else {
+            self.pending_removals.insert(id)
+        }
+    }
+}
+
+impl<T> Default for RGA<T> {
+    fn default() -> Self {
+        RGA {
+            seen: DottedVersion::default(),
+            lamport: 0,
+            nodes: Vec::new(),
+            pending: BTreeMap::new(),
+            pending_removals: BTreeSet::new(),
+            delta: None,
+        }
+    }
+}
+
+impl<T: Clone> Convergent for RGA<T> {
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        // every node always follows its origin, so nodes of a other list can be integrated in order
+        for node in other.nodes.iter() {
+            changed = self.integrate(node.clone()) || changed;
+        }
+        for node in other.pending.values() {
+            changed = self.integrate(node.clone()) || changed;
+        }
+        for dot in other.pending_removals.iter() {
+            changed = self.tombstone(*dot) || changed;
+        }
+        changed
+    }
+}
+
+impl<T: Clone> DeltaConvergent for RGA<T> {
+    type Delta = Delta<T>;
+
+    fn delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+
+    fn merge_delta(&mut self, other: &Self::Delta) -> bool {
+        let mut changed = false;
+        // origin of a node always has a lower Lamport timestamp than the node itself
+        let mut inserts: Vec<_> = other.inserts.values().collect();
+        inserts.sort_by_key(|n| n.key());
+        for node in inserts {
+            changed = self.integrate(node.clone()) || changed;
+        }
+        for dot in other.removals.iter() {
+            changed = self.tombstone(*dot) || changed;
+        }
+        changed
+    }
+}
+
+impl<T> Prune for RGA<T> {}
+
+impl<'m, T: 'm> Materialize<'m> for RGA<T> {
+    type Value = Vec<&'m T>;
+
+    fn value(&'m self) -> Self::Value {
+        self.iter().collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delta<T> {
+    inserts: BTreeMap<Dot, Node<T>>,
+    removals: BTreeSet<Dot>,
+}
+
+impl<T> Default for Delta<T> {
+    fn default() -> Self {
+        Delta {
+            inserts: BTreeMap::new(),
+            removals: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Clone> Convergent for Delta<T> {
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (dot, node) in other.inserts.iter() {
+            match self.inserts.get_mut(dot) {
+                None => {
+                    self.inserts.insert(*dot, node.clone());
+                    changed = true;
+                },
+                Some(existing) if node.is_removed() && !existing.is_removed() => {
+                    existing.value = Slot::Removed;
+                    changed = true;
+                },
+                Some(_) => {},
+            }
+        }
+        for dot in other.removals.iter() {
+            changed = self.removals.insert(*dot) || changed;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::convergent::rga::RGA;
+    use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent};
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+    const C: PID = 3;
+
+    fn rga(id: PID, values: &[char]) -> RGA<char> {
+        let mut rga = RGA::default();
+        for (i, &c) in values.iter().enumerate() {
+            rga.insert(id, i, c);
+        }
+        rga
+    }
+
+    fn string(rga: &RGA<char>) -> String {
+        rga.iter().collect()
+    }
+
+    #[test]
+    fn rga_identity() {
+        let a: RGA<u32> = RGA::default();
+        assert!(a.is_empty());
+        assert_eq!(a.value(), Vec::<&u32>::new());
+    }
+
+    #[test]
+    fn rga_insert_remove() {
+        let mut a = rga(A, &['a', 'c']);
+        a.insert(A, 1, 'b');
+        a.insert(A, 0, '_');
+        assert_eq!(string(&a), "_abc");
+        assert_eq!(a.len(), 4);
+
+        assert_eq!(a.remove(0), Some('_'));
+        assert_eq!(a.remove(5), None);
+        assert_eq!(string(&a), "abc");
+        assert_eq!(a.get(1), Some(&'b'));
+        assert_eq!(a.index_of(&a.id(2).unwrap()), Some(2));
+    }
+
+    #[test]
+    fn rga_idempotency() {
+        let mut a = rga(A, &['a', 'b']);
+        let b = a.clone();
+
+        assert!(!a.merge(&b));
+        assert_eq!(string(&a), "ab");
+    }
+
+    #[test]
+    fn rga_concurrent_inserts() {
+        let mut a = rga(A, &['a', 'b']);
+        let mut b = RGA::default();
+        b.merge(&a);
+        let mut c = b.clone();
+
+        a.insert(A, 1, 'x');
+        b.insert(B, 1, 'y');
+        c.insert(C, 1, 'z');
+        c.insert(C, 2, 'w');
+
+        let mut a2 = a.clone();
+        let mut b2 = b.clone();
+        let c2 = c.clone();
+
+        // (a + b) + c
+        assert!(a.merge(&b));
+        assert!(a.merge(&c));
+
+        // a + (c + b)
+        assert!(c.merge(&b2));
+        assert!(a2.merge(&c));
+
+        // (b + c) + a
+        assert!(b2.merge(&c2));
+        assert!(b2.merge(&a2));
+
+        assert_eq!(string(&a), string(&a2));
+        assert_eq!(string(&a), string(&b2));
+        assert_eq!(string(&a), "azwyxb");
+    }
+
+    #[test]
+    fn rga_concurrent_remove() {
+        let mut a = rga(A, &['a', 'b', 'c']);
+        let mut b = RGA::default();
+        b.merge(&a);
+
+        // insert after an element concurrently removed by other replica
+        a.remove(1);
+        b.insert(B, 2, 'x');
+
+        let a2 = a.clone();
+        assert!(a.merge(&b));
+        assert!(b.merge(&a2));
+
+        assert_eq!(string(&a), "axc");
+        assert_eq!(string(&b), "axc");
+    }
+
+    #[test]
+    fn rga_delta() {
+        let mut a = RGA::default();
+        let mut b = RGA::default();
+
+        a.insert(A, 0, 'a');
+        a.insert(A, 1, 'c');
+        assert!(b.merge_delta(&a.delta().expect("delta: A")));
+        assert_eq!(string(&b), "ac");
+
+        // after obtaining delta, inner delta should be empty
+        assert!(a.delta().is_none());
+
+        a.insert(A, 1, 'b');
+        a.remove(0);
+        assert!(b.merge_delta(&a.delta().expect("delta: A")));
+        assert_eq!(string(&a), "bc");
+        assert_eq!(string(&b), "bc");
+    }
+
+    #[test]
+    fn rga_delta_out_of_order() {
+        let mut a = RGA::default();
+        let mut b = RGA::default();
+
+        a.insert(A, 0, 'a');
+        let d1 = a.delta().expect("delta: 1");
+        a.insert(A, 1, 'b');
+        let d2 = a.delta().expect("delta: 2");
+        a.remove(0);
+        let d3 = a.delta().expect("delta: 3");
+
+        assert!(b.merge_delta(&d3));
+        assert!(b.merge_delta(&d2));
+        assert!(b.is_empty());
+
+        assert!(b.merge_delta(&d1));
+        assert_eq!(string(&b), "b");
+
+        // deltas can be joined together
+        let mut d = d3.clone();
+        assert!(d.merge(&d2));
+        assert!(d.merge(&d1));
+        assert!(!d.merge(&d1));
+        let mut c = RGA::default();
+        assert!(c.merge_delta(&d));
+        assert_eq!(string(&c), "b");
+    }
+
+    #[test]
+    fn rga_serialize_unit_elements() {
+        let mut a: RGA<()> = RGA::default();
+        a.insert(A, 0, ());
+        a.insert(A, 1, ());
+        a.remove(0);
+
+        // removed and present unit elements must not be confused with each other
+        let bytes = serde_cbor::to_vec(&a).unwrap();
+        let b: RGA<()> = serde_cbor::from_slice(bytes.as_slice()).unwrap();
+        assert_eq!(b.len(), 1);
+    }
+}
@@ -0,0 +1,679 @@
+use std::ops::Range;
+use serde::{Serialize, Deserialize};
+use smallvec::alloc::collections::BTreeMap;
+use crate::crdt::convergent::{Convergent, DeltaConvergent, Materialize, Prune};
+use crate::dotted_version::DottedVersion;
+use crate::vtime::Dot;
+use crate::PID;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Content {
+    Text(String),
+    /// Tombstone of a given number of removed characters.
+    Deleted(usize),
+}
+
+impl Content {
+    fn len(&self) -> usize {
+        match self {
+            Content::Text(s) => s.chars().count(),
+            Content::Deleted(len) => *len,
+        }
+    }
+
+    /// Splits current content at a given character offset, returning its right part.
+    fn split(&mut self, offset: usize) -> Content {
+        match self {
+            Content::Text(s) => {
+                let at = s.char_indices().nth(offset).map(|(i, _)| i).unwrap_or_else(|| s.len());
+                Content::Text(s.split_off(at))
+            },
+            Content::Deleted(len) => {
+                let right = *len - offset;
+                *len = offset;
+                Content::Deleted(right)
+            },
+        }
+    }
+}
+
+/// A run of characters inserted by the same replica one after another. Characters within a block
+/// have consecutive sequence numbers and Lamport timestamps, and every one of them was inserted
+/// right after the previous one, so that a whole block can be identified by its first character.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    id: Dot,
+    lamport: u64,
+    origin: Option<Dot>,
+    content: Content,
+}
+
+impl Block {
+    fn len(&self) -> usize { self.content.len() }
+
+    fn is_deleted(&self) -> bool { matches!(self.content, Content::Deleted(_)) }
+
+    /// Ordering key used to resolve concurrent insertions after the same origin.
+    fn key(&self) -> (u64, PID) { (self.lamport, self.id.pid()) }
+
+    /// Returns an identifier of a character at a given offset within current block.
+    fn dot(&self, offset: usize) -> Dot {
+        Dot::new(self.id.pid(), self.id.seq_nr() + offset as u64)
+    }
+
+    /// Returns an offset of a character identified by a given `Dot`, if it belongs to current block.
+    fn offset(&self, dot: &Dot) -> Option<usize> {
+        let start = self.id.seq_nr();
+        if dot.pid() == self.id.pid() && dot.seq_nr() >= start && dot.seq_nr() < start + self.len() as u64 {
+            Some((dot.seq_nr() - start) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Splits current block at a given character offset, returning its right part.
+    fn split(&mut self, offset: usize) -> Block {
+        Block {
+            id: self.dot(offset),
+            lamport: self.lamport + offset as u64,
+            origin: Some(self.dot(offset - 1)),
+            content: self.content.split(offset),
+        }
+    }
+
+    /// Checks if a given block directly continues the current one, so that both can be joined.
+    fn continued_by(&self, next: &Block) -> bool {
+        let len = self.len() as u64;
+        next.id == self.dot(self.len())
+            && next.lamport == self.lamport + len
+            && next.origin == Some(self.dot(self.len() - 1))
+            && self.is_deleted() == next.is_deleted()
+    }
+
+    fn append(&mut self, next: Block) {
+        match (&mut self.content, next.content) {
+            (Content::Text(s), Content::Text(n)) => s.push_str(&n),
+            (Content::Deleted(len), Content::Deleted(n)) => *len += n,
+            _ => panic!("Defect: Block::append - cannot join text with a tombstone"),
+        }
+    }
+}
+
+/// A set of character identifiers, stored as ranges of sequence numbers per replica.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteSet(BTreeMap<PID, Vec<Range<u64>>>);
+
+impl DeleteSet {
+    /// Adds a given range of sequence numbers of replica `id`. Returns true if any of them was
+    /// not present before.
+    fn insert(&mut self, id: PID, range: Range<u64>) -> bool {
+        let ranges = self.0.entry(id).or_default();
+        let covered = ranges.iter().any(|r| r.start <= range.start && range.end <= r.end);
+        if covered || range.start >= range.end {
+            return false;
+        }
+        ranges.push(range);
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+        for r in ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => merged.push(r),
+            }
+        }
+        *ranges = merged;
+        true
+    }
+
+    /// Removes and returns all sequence numbers of replica `id` overlapping with a given range.
+    fn take(&mut self, id: PID, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut taken = Vec::new();
+        if let Some(ranges) = self.0.get_mut(&id) {
+            let mut remaining = Vec::with_capacity(ranges.len());
+            for r in ranges.drain(..) {
+                let start = r.start.max(range.start);
+                let end = r.end.min(range.end);
+                if start < end {
+                    taken.push(start..end);
+                    if r.start < start { remaining.push(r.start..start) }
+                    if end < r.end { remaining.push(end..r.end) }
+                } else {
+                    remaining.push(r);
+                }
+            }
+            *ranges = remaining;
+            if ranges.is_empty() {
+                self.0.remove(&id);
+            }
+        }
+        taken
+    }
+
+    fn iter(&self) -> impl Iterator<Item=(PID, Range<u64>)> + '_ {
+        self.0.iter().flat_map(|(id, ranges)| ranges.iter().map(move |r| (*id, r.clone())))
+    }
+}
+
+/// Collaborative plain text CRDT. Conceptually it's an RGA of characters: every character is
+/// identified by a `Dot`, remembers a character after which it was inserted (its origin) and
+/// characters inserted concurrently after the same origin are ordered by their Lamport timestamps.
+///
+/// To keep documents small, characters are stored in run-length encoded blocks: consecutive
+/// characters typed by the same replica are kept together in a single block (as are removed
+/// characters, which are retained as tombstones). Blocks are split on demand when other
+/// characters are inserted or removed in their middle.
+///
+/// All positions are expressed in Unicode scalar values (`char`s), not in bytes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Text {
+    seen: DottedVersion,
+    lamport: u64,
+    blocks: Vec<Block>,
+    pending: Vec<Block>,
+    pending_removals: DeleteSet,
+
+    #[serde(skip_serializing, skip_deserializing, default = "Option::default")]
+    delta: Option<Delta>,
+}
+
+impl Text {
+
+    /// Inserts a given string at a given character position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos > len`.
+    pub fn insert(&mut self, id: PID, pos: usize, value: &str) {
+        let len = value.chars().count();
+        if len == 0 {
+            return;
+        }
+        let origin = if pos == 0 {
+            None
+        } else {
+            Some(self.dot_at(pos - 1).expect("Text::insert - position out of bounds"))
+        };
+        let last = self.seen.inc_by(id, len as u64);
+        let block = Block {
+            id: Dot::new(id, last.seq_nr() + 1 - len as u64),
+            lamport: self.lamport + 1,
+            origin,
+            content: Content::Text(value.to_string()),
+        };
+
+        let mut delta = self.delta.take().unwrap_or_default();
+        delta.insert(block.clone());
+        self.delta = Some(delta);
+
+        self.place(block);
+    }
+
+    /// Removes characters within a given range of character positions.
+    pub fn delete(&mut self, range: Range<usize>) {
+        let mut removed = Vec::new();
+        let mut pos = 0;
+        let mut i = 0;
+        while i < self.blocks.len() && pos < range.end {
+            if self.blocks[i].is_deleted() {
+                i += 1;
+                continue;
+            }
+            let len = self.blocks[i].len();
+            if pos + len <= range.start {
+                pos += len;
+                i += 1;
+                continue;
+            }
+            let start = range.start.saturating_sub(pos);
+            let end = (range.end - pos).min(len);
+            let block = &self.blocks[i];
+            removed.push((block.id.pid(), block.dot(start).seq_nr()..block.dot(end - 1).seq_nr() + 1));
+            pos += len;
+            i += 1;
+        }
+
+        let mut delta = self.delta.take().unwrap_or_default();
+        for (id, seq_nrs) in removed {
+            delta.removals.insert(id, seq_nrs.clone());
+            self.tombstone(id, seq_nrs);
+        }
+        self.delta = Some(delta);
+    }
+
+    /// Returns a number of characters.
+    pub fn len(&self) -> usize {
+        self.blocks.iter().filter(|b| !b.is_deleted()).map(Block::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(Block::is_deleted)
+    }
+
+    /// Returns a number of blocks used to store current text, including tombstones.
+    pub fn blocks(&self) -> usize { self.blocks.len() }
+
+    /// Returns an identifier of a visible character at a given position.
+    fn dot_at(&self, pos: usize) -> Option<Dot> {
+        let mut remaining = pos;
+        for block in self.blocks.iter().filter(|b| !b.is_deleted()) {
+            let len = block.len();
+            if remaining < len {
+                return Some(block.dot(remaining));
+            }
+            remaining -= len;
+        }
+        None
+    }
+
+    /// Returns an index of a block containing a given character and character's offset within it.
+    fn find(&self, dot: &Dot) -> Option<(usize, usize)> {
+        self.blocks.iter()
+            .enumerate()
+            .find_map(|(i, b)| b.offset(dot).map(|offset| (i, offset)))
+    }
+
+    fn is_known(&self, dot: &Option<Dot>) -> bool {
+        match dot {
+            None => true,
+            Some(dot) => self.seen.contains(dot),
+        }
+    }
+
+    /// Integrates a given block into a current text. Characters which were already observed are
+    /// only checked for removal, while these which origin is not known yet are kept as pending.
+    /// Returns true if a current text has been changed.
+    fn integrate(&mut self, mut block: Block) -> bool {
+        let known = (0..block.len()).take_while(|&i| self.seen.contains(&block.dot(i))).count();
+        let mut changed = false;
+        if known > 0 {
+            let rest = if known < block.len() { Some(block.split(known)) } else { None };
+            if block.is_deleted() {
+                let start = block.id.seq_nr();
+                changed = self.tombstone(block.id.pid(), start..start + known as u64);
+            }
+            match rest {
+                None => return changed,
+                Some(rest) => block = rest,
+            }
+        }
+
+        if self.is_known(&block.origin) {
+            self.place(block);
+            true
+        } else if self.pending.contains(&block) {
+            changed
+        } else {
+            self.pending.push(block);
+            true
+        }
+    }
+
+    /// Puts a given block, which origin is known, at its position in a current text.
+    fn place(&mut self, block: Block) {
+        let mut i = match block.origin {
+            None => 0,
+            Some(origin) => {
+                let (i, offset) = self.find(&origin).expect("Defect: Text::place - origin not found");
+                if offset + 1 < self.blocks[i].len() {
+                    let right = self.blocks[i].split(offset + 1);
+                    self.blocks.insert(i + 1, right);
+                }
+                i + 1
+            }
+        };
+        // skip over blocks inserted concurrently after the same origin with a higher priority
+        // (together with their successors, as they always have higher Lamport timestamps)
+        let key = block.key();
+        while i < self.blocks.len() && self.blocks[i].key() > key {
+            i += 1;
+        }
+
+        let (id, len) = (block.id, block.len() as u64);
+        for seq_nr in id.seq_nr()..id.seq_nr() + len {
+            self.seen.add(Dot::new(id.pid(), seq_nr));
+        }
+        self.lamport = self.lamport.max(block.lamport + len - 1);
+        self.blocks.insert(i, block);
+        self.join(i);
+
+        // apply removals, which were waiting for inserted characters
+        for seq_nrs in self.pending_removals.take(id.pid(), id.seq_nr()..id.seq_nr() + len) {
+            self.tombstone(id.pid(), seq_nrs);
+        }
+
+        // integrate pending blocks, which were waiting for inserted characters
+        while let Some(i) = self.pending.iter().position(|b| self.is_known(&b.origin)) {
+            let block = self.pending.remove(i);
+            self.integrate(block);
+        }
+    }
+
+    /// Marks characters of replica `id` within a given range of sequence numbers as removed.
+    /// Removals of characters, which were not observed yet, are kept as pending. Returns true if
+    /// a current text has been changed.
+    fn tombstone(&mut self, id: PID, seq_nrs: Range<u64>) -> bool {
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.blocks.len() {
+            let block = &self.blocks[i];
+            let start = block.id.seq_nr();
+            let end = start + block.len() as u64;
+            if block.id.pid() != id || block.is_deleted() || end <= seq_nrs.start || seq_nrs.end <= start {
+                i += 1;
+                continue;
+            }
+            // cut off parts of a block, which are not removed
+            if start < seq_nrs.start {
+                let right = self.blocks[i].split((seq_nrs.start - start) as usize);
+                self.blocks.insert(i + 1, right);
+                i += 1;
+                continue;
+            }
+            if seq_nrs.end < end {
+                let right = self.blocks[i].split((seq_nrs.end - start) as usize);
+                self.blocks.insert(i + 1, right);
+            }
+            let block = &mut self.blocks[i];
+            block.content = Content::Deleted(block.len());
+            changed = true;
+            // joining with a previous block may shift current one
+            i = self.join(i) + 1;
+        }
+
+        for seq_nr in seq_nrs.clone() {
+            if !self.seen.contains(&Dot::new(id, seq_nr)) {
+                changed = self.pending_removals.insert(id, seq_nr..seq_nr + 1) || changed;
+            }
+        }
+        changed
+    }
+
+    /// Tries to join a block at a given index with its neighbours. Returns a new index of that block.
+    fn join(&mut self, i: usize) -> usize {
+        if i + 1 < self.blocks.len() && self.blocks[i].continued_by(&self.blocks[i + 1]) {
+            let next = self.blocks.remove(i + 1);
+            self.blocks[i].append(next);
+        }
+        if i > 0 && self.blocks[i - 1].continued_by(&self.blocks[i]) {
+            let block = self.blocks.remove(i);
+            self.blocks[i - 1].append(block);
+            i - 1
+        } else {
+            i
+        }
+    }
+}
+
+impl Convergent for Text {
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        // every block always follows its origin, so blocks of other text can be integrated in order
+        for block in other.blocks.iter().chain(other.pending.iter()) {
+            changed = self.integrate(block.clone()) || changed;
+        }
+        for (id, seq_nrs) in other.pending_removals.iter() {
+            changed = self.tombstone(id, seq_nrs) || changed;
+        }
+        changed
+    }
+}
+
+impl DeltaConvergent for Text {
+    type Delta = Delta;
+
+    fn delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+
+    fn merge_delta(&mut self, other: &Self::Delta) -> bool {
+        let mut changed = false;
+        // origin of a block always has a lower Lamport timestamp than the block itself
+        let mut inserts: Vec<_> = other.inserts.values().collect();
+        inserts.sort_by_key(|b| b.key());
+        for block in inserts {
+            changed = self.integrate(block.clone()) || changed;
+        }
+        for (id, seq_nrs) in other.removals.iter() {
+            changed = self.tombstone(id, seq_nrs) || changed;
+        }
+        changed
+    }
+}
+
+impl Prune for Text {}
+
+impl<'m> Materialize<'m> for Text {
+    type Value = String;
+
+    fn value(&'m self) -> Self::Value {
+        let mut result = String::new();
+        for block in self.blocks.iter() {
+            if let Content::Text(s) = &block.content {
+                result.push_str(s);
+            }
+        }
+        result
+    }
+}
+
+/// Delta of a `Text`, consisting of inserted blocks and ranges of removed characters.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Delta {
+    inserts: BTreeMap<Dot, Block>,
+    removals: DeleteSet,
+}
+
+impl Delta {
+    /// Adds an inserted block, joining it with a block it directly continues.
+    fn insert(&mut self, block: Block) {
+        if block.id.seq_nr() > 1 {
+            let prev = Dot::new(block.id.pid(), block.id.seq_nr() - 1);
+            if let Some((_, last)) = self.inserts.range_mut(..=prev).next_back() {
+                if last.continued_by(&block) {
+                    last.append(block);
+                    return;
+                }
+            }
+        }
+        self.inserts.insert(block.id, block);
+    }
+
+    fn covers(&self, dot: &Dot) -> bool {
+        self.inserts.range(..=*dot).next_back()
+            .map(|(_, b)| b.offset(dot).is_some())
+            .unwrap_or(false)
+    }
+}
+
+impl Convergent for Delta {
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for block in other.inserts.values() {
+            // only insert characters which are not already covered by current delta
+            let mut block = block.clone();
+            loop {
+                let covered = (0..block.len()).take_while(|&i| self.covers(&block.dot(i))).count();
+                if covered == block.len() {
+                    break;
+                } else if covered > 0 {
+                    block = block.split(covered);
+                }
+                let uncovered = (0..block.len()).take_while(|&i| !self.covers(&block.dot(i))).count();
+                let rest = if uncovered < block.len() { Some(block.split(uncovered)) } else { None };
+                self.insert(block);
+                changed = true;
+                match rest {
+                    None => break,
+                    Some(rest) => block = rest,
+                }
+            }
+        }
+        for (id, seq_nrs) in other.removals.iter() {
+            changed = self.removals.insert(id, seq_nrs) || changed;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::convergent::text::Text;
+    use crate::crdt::convergent::{Materialize, Convergent, DeltaConvergent};
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+    const C: PID = 3;
+
+    #[test]
+    fn text_identity() {
+        let a = Text::default();
+        assert!(a.is_empty());
+        assert_eq!(a.value(), "");
+    }
+
+    #[test]
+    fn text_insert_delete() {
+        let mut a = Text::default();
+        a.insert(A, 0, "hello");
+        a.insert(A, 5, " world");
+        assert_eq!(a.blocks(), 1); // consecutive inserts are kept in a single block
+
+        a.insert(A, 5, ",");
+        assert_eq!(a.value(), "hello, world");
+        assert_eq!(a.len(), 12);
+
+        a.delete(0..1);
+        a.insert(A, 0, "H");
+        a.delete(5..12);
+        assert_eq!(a.value(), "Hello");
+        assert_eq!(a.len(), 5);
+    }
+
+    #[test]
+    fn text_utf8() {
+        let mut a = Text::default();
+        a.insert(A, 0, "zażółć");
+        a.insert(A, 3, "🦀");
+        assert_eq!(a.value(), "zaż🦀ółć");
+
+        a.delete(2..5);
+        assert_eq!(a.value(), "załć");
+        assert_eq!(a.len(), 4);
+    }
+
+    #[test]
+    fn text_idempotency() {
+        let mut a = Text::default();
+        a.insert(A, 0, "hello");
+        a.delete(1..2);
+        let b = a.clone();
+
+        assert!(!a.merge(&b));
+        assert_eq!(a.value(), "hllo");
+    }
+
+    #[test]
+    fn text_concurrent_inserts() {
+        let mut a = Text::default();
+        a.insert(A, 0, "ab");
+        let mut b = Text::default();
+        b.merge(&a);
+        let mut c = b.clone();
+
+        a.insert(A, 1, "xx");
+        b.insert(B, 1, "yy");
+        c.insert(C, 1, "zz");
+        c.delete(0..1);
+
+        let mut a2 = a.clone();
+        let mut b2 = b.clone();
+        let c2 = c.clone();
+
+        // (a + b) + c
+        assert!(a.merge(&b));
+        assert!(a.merge(&c));
+
+        // a + (c + b)
+        assert!(c.merge(&b2));
+        assert!(a2.merge(&c));
+
+        // (b + c) + a
+        assert!(b2.merge(&c2));
+        assert!(b2.merge(&a2));
+
+        // runs of concurrently inserted characters are not interleaved
+        assert_eq!(a.value(), "zzyyxxb");
+        assert_eq!(a2.value(), a.value());
+        assert_eq!(b2.value(), a.value());
+    }
+
+    #[test]
+    fn text_insert_into_split_block() {
+        let mut a = Text::default();
+        a.insert(A, 0, "abcd");
+        let mut b = a.clone();
+
+        a.insert(A, 2, "x");
+        b.delete(1..3);
+        b.insert(B, 1, "y");
+
+        let a2 = a.clone();
+        assert!(a.merge(&b));
+        assert!(b.merge(&a2));
+
+        assert_eq!(a.value(), "ayxd");
+        assert_eq!(b.value(), "ayxd");
+    }
+
+    #[test]
+    fn text_delta() {
+        let mut a = Text::default();
+        let mut b = Text::default();
+
+        a.insert(A, 0, "hel");
+        a.insert(A, 3, "lo");
+        let delta = a.delta().expect("delta: A");
+        assert_eq!(delta.inserts.len(), 1);
+        assert!(b.merge_delta(&delta));
+        assert_eq!(b.value(), "hello");
+
+        // after obtaining delta, inner delta should be empty
+        assert!(a.delta().is_none());
+
+        a.delete(1..4);
+        a.insert(A, 1, "ell");
+        assert!(b.merge_delta(&a.delta().expect("delta: A")));
+        assert_eq!(a.value(), "hello");
+        assert_eq!(b.value(), "hello");
+    }
+
+    #[test]
+    fn text_delta_out_of_order() {
+        let mut a = Text::default();
+        let mut b = Text::default();
+
+        a.insert(A, 0, "abc");
+        let d1 = a.delta().expect("delta: 1");
+        a.insert(A, 1, "x");
+        let d2 = a.delta().expect("delta: 2");
+        a.delete(0..1);
+        let d3 = a.delta().expect("delta: 3");
+
+        assert!(b.merge_delta(&d3));
+        assert!(b.merge_delta(&d2));
+        assert!(b.is_empty());
+
+        assert!(b.merge_delta(&d1));
+        assert_eq!(b.value(), "xbc");
+
+        // deltas can be joined together
+        let mut d = d1.clone();
+        assert!(d.merge(&d3));
+        assert!(d.merge(&d2));
+        assert!(!d.merge(&d1));
+        let mut c = Text::default();
+        assert!(c.merge_delta(&d));
+        assert_eq!(c.value(), "xbc");
+    }
+}
@@ -1,2 +1,3 @@
 pub mod convergent;
-pub mod commutative;
\ No newline at end of file
+pub mod commutative;
+pub mod stability;
\ No newline at end of file
@@ -0,0 +1,135 @@
+use crate::PID;
+use crate::vtime::VTime;
+use crate::mtime::MTime;
+use crate::crdt::convergent::{Convergent, Prune};
+use crate::crdt::commutative::Commutative;
+
+/// Tracker of causal stability. It keeps the most recent vector clocks acknowledged by every
+/// member of a replica group (as a matrix clock) and computes a stable cut: a vector clock of all
+/// events, that have been observed by every member. Events below that cut can no longer be
+/// concurrent to any future event, so CRDTs are free to discard metadata related to them.
+#[derive(Debug, Clone)]
+pub struct StabilityTracker {
+    clocks: MTime,
+    stable: VTime,
+}
+
+impl StabilityTracker {
+
+    /// Creates a new tracker for a given group of replicas. Until every one of them acknowledges
+    /// its vector clock, no event is considered stable.
+    pub fn new<I: IntoIterator<Item=PID>>(members: I) -> Self {
+        let mut clocks = MTime::default();
+        for id in members {
+            clocks.replace(id, VTime::default());
+        }
+        StabilityTracker {
+            clocks,
+            stable: VTime::default(),
+        }
+    }
+
+    /// Returns the current stable cut.
+    pub fn stable(&self) -> &VTime { &self.stable }
+
+    /// Acknowledges, that a given replica has observed all events described by `vtime`. Returns
+    /// a new stable cut if it has advanced in result. Acknowledgements of replicas, which are not
+    /// members of a tracked group, are ignored.
+    pub fn observe(&mut self, id: PID, vtime: &VTime) -> Option<&VTime> {
+        if !self.clocks.contains(&id) || !self.clocks.merge_vtime(id, vtime) {
+            return None;
+        }
+        self.advance()
+    }
+
+    /// Removes a replica from a tracked group. Since removed replica will no longer hold back
+    /// a stable cut, it may advance in result.
+    pub fn remove_member(&mut self, id: &PID) -> Option<&VTime> {
+        self.clocks.remove(id)?;
+        self.advance()
+    }
+
+    /// Compacts metadata of a given delta-state CRDT using the current stable cut.
+    pub fn prune<C: Prune>(&self, crdt: &mut C) -> bool {
+        crdt.prune(&self.stable)
+    }
+
+    /// Compacts metadata of a given operation-based CRDT using the current stable cut.
+    pub fn prune_commutative<C: Commutative>(&self, crdt: &mut C) -> bool {
+        crdt.prune(&self.stable)
+    }
+
+    fn advance(&mut self) -> Option<&VTime> {
+        let min = self.clocks.min();
+        if self.stable.merge(&min) {
+            Some(&self.stable)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crdt::stability::StabilityTracker;
+    use crate::crdt::convergent::Prune;
+    use crate::dotted_version::DottedVersion;
+    use crate::vtime::{VTime, Dot};
+    use crate::PID;
+
+    const A: PID = 1;
+    const B: PID = 2;
+    const C: PID = 3;
+
+    fn vtime(a: u64, b: u64, c: u64) -> VTime {
+        let mut ts = VTime::default();
+        ts.inc_by(A, a);
+        ts.inc_by(B, b);
+        ts.inc_by(C, c);
+        ts
+    }
+
+    #[test]
+    fn stability_requires_all_members() {
+        let mut tracker = StabilityTracker::new(vec![A, B, C]);
+
+        assert!(tracker.observe(A, &vtime(2, 1, 0)).is_none());
+        assert!(tracker.observe(B, &vtime(1, 2, 0)).is_none());
+        assert_eq!(tracker.observe(C, &vtime(1, 1, 1)), Some(&vtime(1, 1, 0)));
+
+        // stale acknowledgement doesn't change anything
+        assert!(tracker.observe(C, &vtime(0, 0, 1)).is_none());
+        // acknowledgement from non-member is ignored
+        assert!(tracker.observe(4, &vtime(5, 5, 5)).is_none());
+
+        assert!(tracker.observe(C, &vtime(2, 2, 1)).is_none()); // A is still behind
+        assert_eq!(tracker.observe(A, &vtime(2, 2, 0)), Some(&vtime(1, 2, 0)));
+        assert_eq!(tracker.stable(), &vtime(1, 2, 0));
+    }
+
+    #[test]
+    fn stability_remove_member() {
+        let mut tracker = StabilityTracker::new(vec![A, B, C]);
+        tracker.observe(A, &vtime(2, 0, 0));
+        tracker.observe(B, &vtime(2, 0, 0));
+
+        assert_eq!(tracker.remove_member(&C), Some(&vtime(2, 0, 0)));
+    }
+
+    #[test]
+    fn stability_prune_dotted_version() {
+        let mut tracker = StabilityTracker::new(vec![A, B]);
+        tracker.observe(A, &vtime(0, 3, 0));
+        tracker.observe(B, &vtime(0, 3, 0));
+
+        let mut detached = DottedVersion::default();
+        detached.add(Dot::new(B, 2));
+        assert!(detached.contains(&Dot::new(B, 2)));
+        assert!(!detached.contains(&Dot::new(B, 1)));
+
+        assert!(tracker.prune(&mut detached));
+        assert!(detached.contains(&Dot::new(B, 1)));
+        assert!(detached.contains(&Dot::new(B, 3)));
+        assert!(!detached.prune(tracker.stable()));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::vtime::{VTime, Dot};
 use smallvec::alloc::collections::BTreeSet;
-use crate::crdt::convergent::Convergent;
+use crate::crdt::convergent::{Convergent, Prune};
 use serde::{Serialize, Deserialize};
 use crate::PID;
 
@@ -30,6 +30,19 @@ impl DottedVersion {
         self.0.contains(dot) || self.1.contains(dot)
     }
 
+    /// Puts a given `Dot` into a current dotted version vector. If it's not a direct successor of
+    /// already observed dots of the same replica, it's stored as a detached dot. Returns false if
+    /// that dot has been already observed.
+    pub fn add(&mut self, dot: Dot) -> bool {
+        if self.contains(&dot) {
+            false
+        } else {
+            self.1.insert(dot);
+            self.compress();
+            true
+        }
+    }
+
     pub fn inc_by(&mut self, key: PID, delta: u64) -> Dot {
         // we don't need to update dot cloud (self.1) as this function should only be called for
         // key that represent current replica and that entry is always up-to-date and should never
@@ -59,4 +72,16 @@ impl Convergent for DottedVersion {
         }
         vec_changed || cloud_changed
     }
-}
\ No newline at end of file
+}
+
+impl Prune for DottedVersion {
+    fn prune(&mut self, timestamp: &VTime) -> bool {
+        // every dot from a stable timestamp has been observed by all replicas (including this
+        // one), so detached dots covered by it can be folded into a vector clock
+        let changed = self.0.merge(timestamp);
+        let len = self.1.len();
+        let vtime = &self.0;
+        self.1.retain(|d| !vtime.contains(d));
+        changed || self.1.len() != len
+    }
+}
@@ -72,6 +72,10 @@ impl<'de> Deserialize<'de> for HybridTime {
             fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
                 write!(f, "Instant")
             }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(v)
+            }
         }
 
         let ticks = deserializer.deserialize_u64(InstantVisitor)?;
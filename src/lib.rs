@@ -12,6 +12,7 @@ pub mod hlc;
 pub mod dotted_version;
 pub mod paxos;
 pub mod membership;
+pub mod stream;
 
 pub type Result<T> = anyhow::Result<T>;
 
@@ -27,10 +27,20 @@ impl MTime {
         e.merge(time)
     }
 
+    pub fn remove(&mut self, id: &PID) -> Option<VTime> {
+        self.0.remove(id)
+    }
+
+    pub fn contains(&self, id: &PID) -> bool {
+        self.0.contains_key(id)
+    }
+
     pub fn min(&self) -> VTime {
-        self.0.iter().fold(VTime::default(), |acc, (_, time)| {
-            acc.min(time)
-        })
+        let mut times = self.0.values();
+        match times.next() {
+            None => VTime::default(),
+            Some(first) => times.fold(first.clone(), |acc, time| acc.min(time)),
+        }
     }
 
     pub fn max(&self) -> VTime {
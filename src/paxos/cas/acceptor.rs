@@ -0,0 +1,102 @@
+use std::marker::PhantomData;
+use crate::Result;
+use crate::paxos::cas::{Key, Ballot};
+use crate::paxos::cas::message::Payload;
+use crate::paxos::cas::storage::Storage;
+
+/// CASPaxos acceptor of many independent registers. It doesn't interpret values in any way,
+/// only guards them with ballots.
+#[derive(Debug)]
+pub struct Acceptor<T, S> {
+    storage: S,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Clone, S: Storage<T>> Acceptor<T, S> {
+    pub fn new(storage: S) -> Self {
+        Acceptor { storage, _marker: PhantomData }
+    }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    /// Promises not to accept proposals with ballots lower than a given one, replying with
+    /// the last accepted value. Rejects ballots which are not higher than an already promised one.
+    pub fn prepare(&mut self, key: Key, ballot: Ballot) -> Result<Payload<T>> {
+        let mut state = self.storage.load(&key)?;
+        if ballot <= state.promised {
+            return Ok(Payload::Reject { key, ballot, promised: state.promised });
+        }
+        state.promised = ballot;
+        self.storage.store(&key, &state)?;
+        Ok(Payload::Promise { key, ballot, accepted: state.accepted, value: state.value })
+    }
+
+    /// Accepts a new value, unless a higher ballot has been promised in the meantime.
+    pub fn accept(&mut self, key: Key, ballot: Ballot, value: T) -> Result<Payload<T>> {
+        let mut state = self.storage.load(&key)?;
+        if ballot < state.promised {
+            return Ok(Payload::Reject { key, ballot, promised: state.promised });
+        }
+        state.promised = ballot;
+        state.accepted = ballot;
+        state.value = Some(value);
+        self.storage.store(&key, &state)?;
+        Ok(Payload::Accepted { key, ballot })
+    }
+
+    /// Accepts a value sent in a fast round, unless a higher ballot has been promised or another
+    /// value has already been accepted within the same fast round.
+    pub fn fast_accept(&mut self, key: Key, ballot: Ballot, value: T) -> Result<Payload<T>> {
+        let state = self.storage.load(&key)?;
+        if state.promised != ballot && state.promised.same_round(&ballot) {
+            return Ok(Payload::Reject { key, ballot, promised: state.promised });
+        }
+        self.accept(key, ballot, value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::cas::Ballot;
+    use crate::paxos::cas::acceptor::Acceptor;
+    use crate::paxos::cas::message::Payload;
+    use crate::paxos::cas::storage::{MemStorage, Storage};
+
+    #[test]
+    fn acceptor_promise_and_accept() {
+        let mut a = Acceptor::new(MemStorage::default());
+        let key = b"k".to_vec();
+        let (b1, b2) = (Ballot::new(1, 1), Ballot::new(1, 2));
+
+        assert_eq!(a.prepare(key.clone(), b1).unwrap(), Payload::Promise { key: key.clone(), ballot: b1, accepted: Ballot::default(), value: None });
+        assert_eq!(a.accept(key.clone(), b1, 10).unwrap(), Payload::Accepted { key: key.clone(), ballot: b1 });
+        assert_eq!(a.prepare(key.clone(), b1).unwrap(), Payload::Reject { key: key.clone(), ballot: b1, promised: b1 });
+
+        // higher ballot learns about the accepted value and blocks the lower one
+        assert_eq!(a.prepare(key.clone(), b2).unwrap(), Payload::Promise { key: key.clone(), ballot: b2, accepted: b1, value: Some(10) });
+        assert_eq!(a.accept(key.clone(), b1, 20).unwrap(), Payload::Reject { key: key.clone(), ballot: b1, promised: b2 });
+        assert_eq!(a.storage().load(&key).unwrap().value, Some(10));
+        assert_eq!(a.storage().load(b"other").unwrap().value, None);
+    }
+
+    #[test]
+    fn acceptor_fast_accept() {
+        let mut a = Acceptor::new(MemStorage::default());
+        let key = b"k".to_vec();
+        let b1 = Ballot::new(1, 1);
+        a.accept(key.clone(), b1, 10).unwrap();
+
+        // only the first value within a fast round is accepted
+        let (f1, f2) = (b1.next_fast(1), b1.next_fast(2));
+        assert_eq!(a.fast_accept(key.clone(), f2, 20).unwrap(), Payload::Accepted { key: key.clone(), ballot: f2 });
+        assert_eq!(a.fast_accept(key.clone(), f1, 30).unwrap(), Payload::Reject { key: key.clone(), ballot: f1, promised: f2 });
+        assert_eq!(a.fast_accept(key.clone(), f2.next_fast(3), 40).unwrap(), Payload::Accepted { key: key.clone(), ballot: f2.next_fast(3) });
+
+        // fast rounds precede any higher classic ballot
+        let b2 = Ballot::new(1, 2);
+        assert!(f2.next_fast(3) < b2);
+        assert_eq!(a.prepare(key.clone(), b2).unwrap(), Payload::Promise { key: key.clone(), ballot: b2, accepted: f2.next_fast(3), value: Some(40) });
+        assert_eq!(a.fast_accept(key.clone(), b2.next_fast(1), 50).unwrap(), Payload::Accepted { key: key.clone(), ballot: b2.next_fast(1) });
+        assert_eq!(a.fast_accept(key.clone(), f1, 60).unwrap(), Payload::Reject { key: key.clone(), ballot: f1, promised: b2.next_fast(1) });
+    }
+}
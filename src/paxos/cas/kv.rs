@@ -0,0 +1,401 @@
+use std::collections::{HashMap, VecDeque};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::cas::{Key, Ballot, Config};
+use crate::paxos::cas::acceptor::Acceptor;
+use crate::paxos::cas::message::{Message, Payload};
+use crate::paxos::cas::proposer::{Proposer, Change};
+use crate::paxos::cas::storage::Storage;
+
+/// A linearizable key-value store, where every key is an independent CASPaxos register. Every
+/// node is both a proposer and an acceptor. Changes of different keys run concurrently, while
+/// changes of the same key requested on a single node are executed one after another.
+///
+/// Messages addressed to a current node are handled right away.
+///
+/// With a fast quorum configured, a change starts with a fast round whenever the local acceptor
+/// has accepted a value, which can be followed by one: a value of a classic ballot, or a value
+/// of a fast round won by this node. Other changes start with a classic round.
+pub struct KvStore<T, S> {
+    config: Config,
+    acceptor: Acceptor<T, S>,
+    /// Changes in progress, at most one per key, together with their request identifiers.
+    active: HashMap<Key, (u64, Proposer<T>)>,
+    /// Changes waiting for a change of the same key to finish.
+    queued: HashMap<Key, VecDeque<(u64, Change<T>)>>,
+    /// Ballots of the last successful changes of every key made by this node.
+    won: HashMap<Key, Ballot>,
+    next_request: u64,
+    rng: StdRng,
+    outbox: Vec<Message<T>>,
+    results: Vec<(u64, Result<T>)>,
+}
+
+impl<T: Clone, S: Storage<T>> KvStore<T, S> {
+    pub fn new(config: Config, storage: S) -> Result<Self> {
+        config.validate()?;
+        Ok(KvStore {
+            config,
+            acceptor: Acceptor::new(storage),
+            active: HashMap::new(),
+            queued: HashMap::new(),
+            won: HashMap::new(),
+            next_request: 0,
+            rng: StdRng::from_entropy(),
+            outbox: Vec::new(),
+            results: Vec::new(),
+        })
+    }
+
+    pub fn id(&self) -> PeerId { self.config.id }
+
+    pub fn acceptor(&self) -> &Acceptor<T, S> { &self.acceptor }
+
+    /// Requests a change of a value under a given key, returning an identifier of this request.
+    /// Result of a change - a new value of a register - is returned by `take_results` once
+    /// it has been accepted by a quorum of acceptors.
+    pub fn change<F>(&mut self, key: Key, f: F) -> Result<u64>
+        where F: Fn(Option<T>) -> T + 'static {
+        self.next_request += 1;
+        let request = self.next_request;
+        if self.active.contains_key(&key) {
+            self.queued.entry(key).or_default().push_back((request, Box::new(f)));
+        } else {
+            self.start(request, key, Box::new(f))?;
+        }
+        Ok(request)
+    }
+
+    /// Advances logical clock of this node by a single tick.
+    pub fn tick(&mut self) -> Result<()> {
+        let keys: Vec<_> = self.active.keys().cloned().collect();
+        for key in keys {
+            let outcome = match self.active.get_mut(&key) {
+                Some((_, proposer)) => proposer.tick(&self.config, &mut self.rng),
+                None => continue,
+            };
+            self.flush(&key)?;
+            if let Some(result) = outcome {
+                self.finish(&key, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a message received from another node.
+    pub fn step(&mut self, msg: Message<T>) -> Result<()> {
+        self.handle(msg.from, msg.payload)
+    }
+
+    /// Returns all messages produced by this node since the last call, which should be sent to
+    /// their recipients.
+    pub fn take_messages(&mut self) -> Vec<Message<T>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Returns results of all changes finished since the last call, together with identifiers of
+    /// their requests.
+    pub fn take_results(&mut self) -> Vec<(u64, Result<T>)> {
+        std::mem::take(&mut self.results)
+    }
+
+    fn start(&mut self, request: u64, key: Key, change: Change<T>) -> Result<()> {
+        // local acceptor has seen every ballot of this node, even the ones used before restart
+        let state = self.acceptor.storage().load(&key)?;
+        let mut proposer = Proposer::new(key.clone(), state.promised.round, change);
+        let fast = self.config.fast_quorum.is_some()
+            && state.accepted == state.promised
+            && (!state.accepted.is_fast() || self.won.get(&key) == Some(&state.accepted));
+        if fast {
+            proposer.start_fast(&self.config, state.accepted, state.value);
+        } else {
+            proposer.start(&self.config);
+        }
+        self.active.insert(key.clone(), (request, proposer));
+        self.flush(&key)
+    }
+
+    fn finish(&mut self, key: &Key, result: Result<T>) -> Result<()> {
+        if let Some((request, proposer)) = self.active.remove(key) {
+            if result.is_ok() {
+                self.won.insert(key.clone(), proposer.ballot());
+            } else {
+                self.won.remove(key);
+            }
+            self.results.push((request, result));
+        }
+        let next = self.queued.get_mut(key).and_then(|q| q.pop_front());
+        if self.queued.get(key).map(|q| q.is_empty()).unwrap_or(false) {
+            self.queued.remove(key);
+        }
+        match next {
+            Some((request, change)) => self.start(request, key.clone(), change),
+            None => Ok(()),
+        }
+    }
+
+    fn handle(&mut self, from: PeerId, payload: Payload<T>) -> Result<()> {
+        match payload {
+            Payload::Prepare { key, ballot } => {
+                let reply = self.acceptor.prepare(key, ballot)?;
+                self.send(from, reply)
+            },
+            Payload::Accept { key, ballot, value } => {
+                let reply = self.acceptor.accept(key, ballot, value)?;
+                self.send(from, reply)
+            },
+            Payload::FastAccept { key, ballot, value } => {
+                let reply = self.acceptor.fast_accept(key, ballot, value)?;
+                self.send(from, reply)
+            },
+            response => {
+                let key = response.key().clone();
+                let outcome = match self.active.get_mut(&key) {
+                    Some((_, proposer)) => proposer.step(&self.config, from, response, &mut self.rng),
+                    None => return Ok(()),
+                };
+                self.flush(&key)?;
+                match outcome {
+                    Some(result) => self.finish(&key, result),
+                    None => Ok(()),
+                }
+            },
+        }
+    }
+
+    /// Sends messages produced by a proposer of a given key.
+    fn flush(&mut self, key: &Key) -> Result<()> {
+        let messages = match self.active.get_mut(key) {
+            Some((_, proposer)) => proposer.take_messages(),
+            None => return Ok(()),
+        };
+        for msg in messages {
+            self.send(msg.to, msg.payload)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload<T>) -> Result<()> {
+        if to == self.config.id {
+            self.handle(to, payload)
+        } else {
+            self.outbox.push(Message { from: self.config.id, to, payload });
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Result;
+    use crate::paxos::PeerId;
+    use crate::paxos::cas::Config;
+    use crate::paxos::cas::kv::KvStore;
+    use crate::paxos::cas::message::{Message, Payload};
+    use crate::paxos::cas::storage::{MemStorage, Storage};
+    use crate::paxos::sim::{Network, Process, Envelope};
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    type Store = KvStore<u64, MemStorage<u64>>;
+
+    impl<T> Envelope for Message<T> {
+        fn from(&self) -> PeerId { self.from }
+        fn to(&self) -> PeerId { self.to }
+    }
+
+    impl Process for Store {
+        type Message = Message<u64>;
+
+        fn step(&mut self, msg: Self::Message) -> Result<()> { KvStore::step(self, msg) }
+
+        fn tick(&mut self) -> Result<()> { KvStore::tick(self) }
+
+        fn take_messages(&mut self) -> Vec<Self::Message> { KvStore::take_messages(self) }
+    }
+
+    fn cluster(ids: &[PeerId]) -> Network<Store> {
+        cluster_with(ids, None)
+    }
+
+    fn cluster_with(ids: &[PeerId], fast_quorum: Option<usize>) -> Network<Store> {
+        Network::new(ids.iter().map(|&id| {
+            let mut config = Config::new(id, ids.to_vec());
+            config.max_attempts = 20;
+            config.fast_quorum = fast_quorum;
+            (id, KvStore::new(config, MemStorage::default()).unwrap())
+        }))
+    }
+
+    fn incr(value: Option<u64>) -> u64 {
+        value.unwrap_or(0) + 1
+    }
+
+    /// Ticks and delivers messages until a given node returns results of all its requests.
+    fn wait(net: &mut Network<Store>, id: PeerId, count: usize) -> Vec<(u64, Result<u64>)> {
+        let mut results = Vec::new();
+        for _ in 0..1000 {
+            net.deliver();
+            results.extend(net.node_mut(id).take_results());
+            if results.len() >= count {
+                break;
+            }
+            net.tick_all();
+        }
+        results
+    }
+
+    /// Delivers messages sent by a given node, checking if all of them belong to a fast round.
+    fn sent_fast(net: &mut Network<Store>, id: PeerId) -> bool {
+        let messages = net.node_mut(id).take_messages();
+        let fast = !messages.is_empty() && messages.iter().all(|msg| matches!(msg.payload, Payload::FastAccept { .. }));
+        for msg in messages {
+            net.node_mut(msg.to).step(msg).unwrap();
+        }
+        fast
+    }
+
+    fn value(net: &Network<Store>, id: PeerId, key: &[u8]) -> Option<u64> {
+        net.node(id).acceptor().storage().load(key).unwrap().value
+    }
+
+    #[test]
+    fn cas_change_register() {
+        let mut net = cluster(&[A, B, C]);
+        let key = b"a".to_vec();
+        let request = net.node_mut(A).change(key.clone(), incr).unwrap();
+        net.deliver();
+        let results = net.node_mut(A).take_results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, request);
+        assert_eq!(results[0].1.as_ref().unwrap(), &1);
+
+        // another proposer learns about the current value
+        net.node_mut(B).change(key.clone(), |v| v.unwrap() * 10).unwrap();
+        net.deliver();
+        assert_eq!(net.node_mut(B).take_results()[0].1.as_ref().unwrap(), &10);
+        assert_eq!(value(&net, C, &key), Some(10));
+        assert_eq!(value(&net, A, b"b"), None);
+    }
+
+    #[test]
+    fn cas_single_node() {
+        let mut a = Store::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        a.change(b"a".to_vec(), incr).unwrap();
+        a.change(b"a".to_vec(), incr).unwrap();
+        let results: Vec<_> = a.take_results().into_iter().map(|(_, r)| r.unwrap()).collect();
+        assert_eq!(results, vec![1, 2]);
+        assert!(a.take_messages().is_empty());
+        assert!(Store::new(Config::new(A, vec![B]), MemStorage::default()).is_err());
+    }
+
+    #[test]
+    fn cas_concurrent_changes() {
+        let mut net = cluster(&[A, B, C]);
+        let key = b"a".to_vec();
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        net.node_mut(B).change(key.clone(), incr).unwrap();
+        net.node_mut(C).change(b"b".to_vec(), incr).unwrap();
+
+        let mut succeeded: Vec<_> = wait(&mut net, A, 2).into_iter()
+            .chain(wait(&mut net, B, 1))
+            .filter_map(|(_, r)| r.ok())
+            .collect();
+        succeeded.sort_unstable();
+        succeeded.dedup();
+        // every successful change has observed all the previous ones
+        let last = *succeeded.last().unwrap();
+        assert!(last >= succeeded.len() as u64);
+        net.node_mut(C).change(key.clone(), |v| v.unwrap()).unwrap();
+        let current = wait(&mut net, C, 2).into_iter().find(|(id, _)| *id == 2).unwrap().1.unwrap();
+        // changes which have failed with unknown outcome might have been applied as well
+        assert!(current >= last && current <= 3);
+    }
+
+    #[test]
+    fn cas_requires_majority() {
+        let mut net = cluster(&[A, B, C]);
+        let key = b"a".to_vec();
+        net.isolate(B);
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        assert_eq!(wait(&mut net, A, 1)[0].1.as_ref().unwrap(), &1);
+
+        net.isolate(C);
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        assert!(wait(&mut net, A, 1)[0].1.is_err());
+
+        // restarted node continues with higher ballots
+        net.heal();
+        let storage = net.node(A).acceptor().storage().clone();
+        *net.node_mut(A) = Store::new(Config::new(A, vec![A, B, C]), storage).unwrap();
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        assert_eq!(wait(&mut net, A, 1)[0].1.as_ref().unwrap(), &2);
+    }
+
+    #[test]
+    fn cas_fast_path() {
+        let mut net = cluster_with(&[A, B, C], Some(3));
+        let key = b"a".to_vec();
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        // value is sent right away, without a prepare phase
+        assert!(sent_fast(&mut net, A));
+        net.deliver();
+        assert_eq!(net.node_mut(A).take_results()[0].1.as_ref().unwrap(), &1);
+
+        // node which has won a fast round can follow it with another one
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        assert!(sent_fast(&mut net, A));
+        net.deliver();
+        assert_eq!(net.node_mut(A).take_results()[0].1.as_ref().unwrap(), &2);
+
+        // while other nodes use a classic round
+        net.node_mut(B).change(key.clone(), incr).unwrap();
+        assert!(!sent_fast(&mut net, B));
+        net.deliver();
+        assert_eq!(net.node_mut(B).take_results()[0].1.as_ref().unwrap(), &3);
+        assert_eq!(value(&net, C, &key), Some(3));
+    }
+
+    #[test]
+    fn cas_fast_path_recovery() {
+        let mut net = cluster_with(&[A, B, C], Some(3));
+        let key = b"a".to_vec();
+        // without a fast quorum, a value accepted by the rest of acceptors is recovered
+        // by a classic round and not applied again
+        net.isolate(C);
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        assert_eq!(wait(&mut net, A, 1)[0].1.as_ref().unwrap(), &1);
+        net.heal();
+
+        // concurrent fast rounds collide
+        net.node_mut(A).change(key.clone(), incr).unwrap();
+        net.node_mut(B).change(key.clone(), incr).unwrap();
+        net.node_mut(C).change(key.clone(), incr).unwrap();
+        let mut succeeded: Vec<_> = wait(&mut net, A, 1).into_iter()
+            .chain(wait(&mut net, B, 1))
+            .chain(wait(&mut net, C, 1))
+            .filter_map(|(_, r)| r.ok())
+            .collect();
+        succeeded.sort_unstable();
+        let count = succeeded.len();
+        succeeded.dedup();
+        assert_eq!(succeeded.len(), count);
+        net.node_mut(A).change(key.clone(), |v| v.unwrap()).unwrap();
+        let current = *wait(&mut net, A, 1)[0].1.as_ref().unwrap();
+        assert!(current >= succeeded.last().cloned().unwrap_or(1) && current <= 4);
+    }
+
+    #[test]
+    fn cas_fast_quorum_validation() {
+        let mut config = Config::new(A, vec![A, B, C]);
+        config.fast_quorum = Some(2);
+        assert!(Store::new(config.clone(), MemStorage::default()).is_err());
+        config.fast_quorum = Some(3);
+        assert!(Store::new(config, MemStorage::default()).is_ok());
+    }
+}
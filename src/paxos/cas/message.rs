@@ -0,0 +1,41 @@
+use serde::{Serialize, Deserialize};
+use crate::paxos::PeerId;
+use crate::paxos::cas::{Key, Ballot};
+
+/// A message exchanged between CASPaxos proposers and acceptors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message<T> {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub payload: Payload<T>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload<T> {
+    /// Asks an acceptor to promise, that it won't accept any proposal with a lower ballot.
+    Prepare { key: Key, ballot: Ballot },
+    /// Acceptor's promise together with the last value it has accepted.
+    Promise { key: Key, ballot: Ballot, accepted: Ballot, value: Option<T> },
+    /// Asks an acceptor to accept a new value of a register.
+    Accept { key: Key, ballot: Ballot, value: T },
+    Accepted { key: Key, ballot: Ballot },
+    /// Asks an acceptor to accept a new value of a register in a fast round, without a preceding
+    /// promise. Acceptor accepts at most one value within a single fast round.
+    FastAccept { key: Key, ballot: Ballot, value: T },
+    /// Acceptor has already promised a higher ballot.
+    Reject { key: Key, ballot: Ballot, promised: Ballot },
+}
+
+impl<T> Payload<T> {
+    /// Key of a register, which this message refers to.
+    pub fn key(&self) -> &Key {
+        match self {
+            Payload::Prepare { key, .. } => key,
+            Payload::Promise { key, .. } => key,
+            Payload::Accept { key, .. } => key,
+            Payload::Accepted { key, .. } => key,
+            Payload::FastAccept { key, .. } => key,
+            Payload::Reject { key, .. } => key,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+pub mod message;
+pub mod storage;
+pub mod acceptor;
+pub mod proposer;
+pub mod kv;
+
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::quorum::{Quorum, QuorumSystem};
+
+/// Key of an independent CASPaxos register.
+pub type Key = Vec<u8>;
+
+/// Ballot number of a CASPaxos proposal. Ballots are totally ordered by their round first and
+/// an identifier of a proposer using it next, so that no two proposers ever use the same ballot.
+///
+/// Every classic ballot can be followed by fast rounds, in which values are sent by proposers
+/// directly to acceptors without running the prepare phase. Fast rounds are ordered right after
+/// a ballot they follow, before any higher classic ballot. Multiple proposers can send values
+/// within the same fast round - their ballots differ only by `fast_proposer`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub proposer: PeerId,
+    /// Number of a fast round following a classic ballot `(round, proposer)`, 0 for classic ballots.
+    pub fast: u64,
+    /// Proposer of a value sent within a fast round.
+    pub fast_proposer: PeerId,
+}
+
+impl Ballot {
+    pub fn new(round: u64, proposer: PeerId) -> Self {
+        Ballot { round, proposer, fast: 0, fast_proposer: 0 }
+    }
+
+    pub fn is_fast(&self) -> bool { self.fast != 0 }
+
+    /// Returns a ballot of a given proposer in a fast round immediately following this one.
+    pub fn next_fast(&self, proposer: PeerId) -> Self {
+        Ballot { round: self.round, proposer: self.proposer, fast: self.fast + 1, fast_proposer: proposer }
+    }
+
+    /// Checks if both ballots belong to the same round, which is true for ballots of different
+    /// proposers within the same fast round.
+    pub fn same_round(&self, other: &Ballot) -> bool {
+        self.round == other.round && self.proposer == other.proposer && self.fast == other.fast
+    }
+}
+
+const DEFAULT_TIMEOUT: u64 = 10;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Configuration of a CASPaxos node. Timeouts are expressed in number of ticks.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Identifier of a current node.
+    pub id: PeerId,
+    /// Identifiers of all acceptors, including a current node.
+    pub acceptors: Vec<PeerId>,
+    /// Quorum system of acceptors, majority by default.
+    pub quorum: Quorum,
+    /// Number of acceptors forming a fast quorum. If set, proposers try to change registers in
+    /// a single round trip by sending new values directly to acceptors, falling back to a classic
+    /// round on collision. Fast quorums must intersect with each other and every phase 1 quorum.
+    pub fast_quorum: Option<usize>,
+    /// Number of ticks a proposer waits for responses of a quorum of acceptors, before it
+    /// retries with a higher ballot.
+    pub timeout: u64,
+    /// Maximum number of attempts of a single change, after which it fails.
+    pub max_attempts: u32,
+}
+
+impl Config {
+    pub fn new(id: PeerId, acceptors: Vec<PeerId>) -> Self {
+        Config {
+            id,
+            acceptors,
+            quorum: Quorum::Majority,
+            fast_quorum: None,
+            timeout: DEFAULT_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.acceptors.contains(&self.id) {
+            // ballots used by a proposer are recovered from its local acceptor after restart
+            Err(anyhow::anyhow!("CASPaxos node {} is not one of the acceptors {:?}", self.id, self.acceptors))
+        } else if self.timeout == 0 || self.max_attempts == 0 {
+            Err(anyhow::anyhow!("CASPaxos timeout and max attempts must be positive"))
+        } else if let Some(size) = self.fast_quorum {
+            self.quorum.validate(&self.acceptor_set())?;
+            self.quorum.validate_fast(&self.acceptor_set(), size)
+        } else {
+            self.quorum.validate(&self.acceptor_set())
+        }
+    }
+
+    /// Checks if given acceptors form a quorum of promises.
+    pub fn is_phase1_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase1_quorum(&self.acceptor_set(), nodes)
+    }
+
+    /// Checks if given acceptors form a quorum, which is enough for a value to be accepted.
+    pub fn is_phase2_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase2_quorum(&self.acceptor_set(), nodes)
+    }
+
+    /// Checks if given acceptors form a fast quorum.
+    pub fn is_fast_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        let count = self.acceptors.iter().filter(|id| nodes.contains(id)).count();
+        count >= self.fast_quorum.unwrap_or(self.acceptors.len())
+    }
+
+    fn acceptor_set(&self) -> BTreeSet<PeerId> {
+        self.acceptors.iter().cloned().collect()
+    }
+}
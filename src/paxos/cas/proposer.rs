@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, BTreeSet};
+use rand::Rng;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::cas::{Key, Ballot, Config};
+use crate::paxos::cas::message::{Message, Payload};
+
+/// A change function, computing a new value of a register from its current one.
+pub type Change<T> = Box<dyn Fn(Option<T>) -> T>;
+
+enum Phase<T> {
+    /// Collecting promises, together with values accepted by each acceptor.
+    Prepare(BTreeMap<PeerId, (Ballot, Option<T>)>),
+    /// Collecting acknowledgements of a new value.
+    Accept(T, BTreeSet<PeerId>),
+    /// Collecting acknowledgements of a new value sent within a fast round.
+    FastAccept(T, BTreeSet<PeerId>),
+    /// Waiting a given number of ticks before the next attempt.
+    Backoff(u64),
+}
+
+/// A single CASPaxos change of a register. Every attempt consists of two phases: first
+/// a proposer gets promises from a phase 1 quorum of acceptors and learns a current value of
+/// a register, then it applies a change function to it and asks acceptors to accept the result.
+///
+/// If so many acceptors have already promised a higher ballot, that there cannot be a quorum,
+/// or there's no quorum within a timeout, an attempt is retried with a higher ballot after
+/// a random backoff. Once a new value has been sent to acceptors, a change is not retried
+/// anymore: even if it was not accepted by a quorum, it could still be picked up by another
+/// proposer, so retrying would apply it twice. Such change fails with its outcome unknown.
+///
+/// If a proposer knows a value of a register, which was accepted in a ballot that cannot be
+/// followed by anything but a fast round, it can skip the first phase: a new value is sent right
+/// away to acceptors, and the change succeeds once it's accepted by a fast quorum. If values of
+/// other proposers collide with it, a classic round is used to recover: a value which might have
+/// been accepted by a fast quorum is recognized among promises and accepted again. When it's
+/// a value of this proposer, its change succeeds without being applied again. If a higher ballot
+/// than the fast round has been accepted in the meantime, a value of this proposer might have
+/// been picked up by another one, so such change fails with its outcome unknown as well.
+pub struct Proposer<T> {
+    key: Key,
+    ballot: Ballot,
+    change: Change<T>,
+    phase: Phase<T>,
+    /// Acceptors which have rejected a current ballot.
+    rejected: BTreeSet<PeerId>,
+    /// The highest round promised by acceptors, as reported by rejections. Next attempt uses
+    /// a higher one.
+    max_seen_round: u64,
+    /// Ballot used by this proposer in a fast round, if any.
+    fast: Option<Ballot>,
+    attempts: u32,
+    elapsed: u64,
+    outbox: Vec<Message<T>>,
+}
+
+impl<T: Clone> Proposer<T> {
+
+    /// Creates a new proposer, which will use ballots with rounds higher than a given one.
+    pub fn new(key: Key, round: u64, change: Change<T>) -> Self {
+        Proposer {
+            key,
+            ballot: Ballot::new(round, 0),
+            change,
+            phase: Phase::Backoff(0),
+            rejected: BTreeSet::new(),
+            max_seen_round: round,
+            fast: None,
+            attempts: 0,
+            elapsed: 0,
+            outbox: Vec::new(),
+        }
+    }
+
+    pub fn key(&self) -> &Key { &self.key }
+
+    /// Ballot of a current attempt.
+    pub fn ballot(&self) -> Ballot { self.ballot }
+
+    /// Starts a new attempt with a higher ballot.
+    pub fn start(&mut self, config: &Config) {
+        self.attempts += 1;
+        self.elapsed = 0;
+        self.ballot = Ballot::new(self.ballot.round.max(self.max_seen_round) + 1, config.id);
+        self.phase = Phase::Prepare(BTreeMap::new());
+        self.rejected.clear();
+        for &to in config.acceptors.iter() {
+            self.send(config.id, to, Payload::Prepare { key: self.key.clone(), ballot: self.ballot });
+        }
+    }
+
+    /// Starts a fast round following a given ballot, in which a register had a given value.
+    /// Ballot must not be followed by any other than fast ballots, which is true for classic
+    /// ballots and fast ballots of values known to be accepted by a fast quorum.
+    pub fn start_fast(&mut self, config: &Config, base: Ballot, current: Option<T>) {
+        self.attempts += 1;
+        self.elapsed = 0;
+        self.ballot = base.next_fast(config.id);
+        self.fast = Some(self.ballot);
+        self.rejected.clear();
+        let value = (self.change)(current);
+        for &to in config.acceptors.iter() {
+            self.send(config.id, to, Payload::FastAccept { key: self.key.clone(), ballot: self.ballot, value: value.clone() });
+        }
+        self.phase = Phase::FastAccept(value, BTreeSet::new());
+    }
+
+    /// Handles a response of an acceptor. Returns a new value of a register once it has been
+    /// accepted by a quorum, or an error if a change has failed.
+    pub fn step<R: Rng>(&mut self, config: &Config, from: PeerId, payload: Payload<T>, rng: &mut R) -> Option<Result<T>> {
+        match payload {
+            Payload::Promise { ballot, accepted, value, .. } if ballot == self.ballot => {
+                let promises = match &mut self.phase {
+                    Phase::Prepare(promises) => promises,
+                    _ => return None,
+                };
+                promises.insert(from, (accepted, value));
+                if config.is_phase1_quorum(&promises.keys().cloned().collect()) {
+                    let (current, own) = match self.recover(config) {
+                        Ok(recovered) => recovered,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let value = match current {
+                        Some(value) if own => value,
+                        current => (self.change)(current),
+                    };
+                    for &to in config.acceptors.iter() {
+                        self.send(config.id, to, Payload::Accept { key: self.key.clone(), ballot: self.ballot, value: value.clone() });
+                    }
+                    self.phase = Phase::Accept(value, BTreeSet::new());
+                }
+                None
+            },
+            Payload::Accepted { ballot, .. } if ballot == self.ballot => {
+                let done = match &mut self.phase {
+                    Phase::Accept(value, accepted) => {
+                        accepted.insert(from);
+                        Some(value.clone()).filter(|_| config.is_phase2_quorum(accepted))
+                    },
+                    Phase::FastAccept(value, accepted) => {
+                        accepted.insert(from);
+                        Some(value.clone()).filter(|_| config.is_fast_quorum(accepted))
+                    },
+                    _ => None,
+                };
+                done.map(Ok)
+            },
+            Payload::Reject { ballot, promised, .. } if ballot == self.ballot => {
+                // a current ballot is kept, as other acceptors may still accept it
+                self.max_seen_round = self.max_seen_round.max(promised.round);
+                self.rejected.insert(from);
+                let remaining = config.acceptors.iter()
+                    .filter(|id| !self.rejected.contains(id))
+                    .cloned()
+                    .collect();
+                let possible = match self.phase {
+                    Phase::Accept(..) => config.is_phase2_quorum(&remaining),
+                    Phase::FastAccept(..) => config.is_fast_quorum(&remaining),
+                    _ => config.is_phase1_quorum(&remaining),
+                };
+                if !possible {
+                    self.retry(config, rng)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Advances a logical clock of this proposer, starting a next attempt once a backoff has
+    /// passed, or retrying if a current attempt has timed out.
+    pub fn tick<R: Rng>(&mut self, config: &Config, rng: &mut R) -> Option<Result<T>> {
+        self.elapsed += 1;
+        match &mut self.phase {
+            Phase::Backoff(remaining) => {
+                *remaining = remaining.saturating_sub(1);
+                if *remaining == 0 {
+                    self.start(config);
+                }
+                None
+            },
+            _ if self.elapsed >= config.timeout => self.retry(config, rng),
+            _ => None,
+        }
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message<T>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn retry<R: Rng>(&mut self, config: &Config, rng: &mut R) -> Option<Result<T>> {
+        match self.phase {
+            Phase::Backoff(_) => return None,
+            Phase::Accept(..) => return Some(Err(anyhow::anyhow!("CASPaxos change of key {:?} was preempted before it was accepted by a quorum, its outcome is unknown", self.key))),
+            // value sent in a fast round is recovered by the next classic one
+            Phase::Prepare(_) | Phase::FastAccept(..) => {},
+        }
+        if self.attempts >= config.max_attempts {
+            return Some(Err(anyhow::anyhow!("CASPaxos change of key {:?} failed after {} attempts", self.key, self.attempts)));
+        }
+        // random backoff makes it unlikely for competing proposers to keep preempting each other
+        self.phase = Phase::Backoff(rng.gen_range(1, config.timeout + 1));
+        None
+    }
+
+    /// Returns a current value of a register based on promises of a phase 1 quorum, and whether
+    /// it's a value sent by this proposer in a fast round. Fails if a value sent in a fast round
+    /// might have already been picked up by another proposer.
+    fn recover(&self, config: &Config) -> Result<(Option<T>, bool)> {
+        let promises = match &self.phase {
+            Phase::Prepare(promises) => promises,
+            _ => return Ok((None, false)),
+        };
+        let highest = promises.values().map(|(accepted, _)| *accepted).max().unwrap_or_default();
+        if let Some(fast) = self.fast.filter(|fast| highest > *fast && !highest.same_round(fast)) {
+            return Err(anyhow::anyhow!("CASPaxos change of key {:?} sent in a fast round {:?} was followed by a higher ballot {:?}, its outcome is unknown", self.key, fast, highest));
+        }
+        if !highest.is_fast() {
+            // value accepted with the highest ballot is the current one
+            let current = promises.values().find(|(accepted, _)| *accepted == highest);
+            return Ok((current.and_then(|(_, value)| value.clone()), false));
+        }
+        // values of different proposers within the same fast round are concurrent, but at most
+        // one of them might have been accepted by a fast quorum, in which case it must have been
+        // accepted by all but `n - fast_quorum` of the acceptors which have responded
+        let mut votes: BTreeMap<Ballot, (usize, &Option<T>)> = BTreeMap::new();
+        for (accepted, value) in promises.values().filter(|(accepted, _)| accepted.same_round(&highest)) {
+            votes.entry(*accepted).or_insert((0, value)).0 += 1;
+        }
+        let n = config.acceptors.len();
+        let threshold = promises.len().saturating_sub(n - config.fast_quorum.unwrap_or(n));
+        let (ballot, (_, value)) = votes.iter()
+            .find(|(_, (count, _))| *count >= threshold)
+            // if none could have been chosen, any of them can be picked
+            .or_else(|| votes.iter().max_by_key(|(_, (count, _))| *count))
+            .expect("Defect: Proposer::recover - there's a value accepted in the highest fast round");
+        Ok(((*value).clone(), self.fast == Some(*ballot)))
+    }
+
+    fn send(&mut self, from: PeerId, to: PeerId, payload: Payload<T>) {
+        self.outbox.push(Message { from, to, payload });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::paxos::PeerId;
+    use crate::paxos::cas::{Ballot, Config};
+    use crate::paxos::cas::message::Payload;
+    use crate::paxos::cas::proposer::Proposer;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    #[test]
+    fn proposer_ignores_minority_rejection() {
+        let config = Config::new(A, vec![A, B, C]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let key = b"k".to_vec();
+        let mut proposer = Proposer::new(key.clone(), 0, Box::new(|v: Option<u64>| v.unwrap_or(0) + 1));
+        proposer.start(&config);
+        let ballot = proposer.ballot();
+
+        for &from in [A, B].iter() {
+            let promise = Payload::Promise { key: key.clone(), ballot, accepted: Ballot::default(), value: None };
+            assert!(proposer.step(&config, from, promise, &mut rng).is_none());
+        }
+        // C has promised a higher ballot in the meantime
+        let reject = Payload::Reject { key: key.clone(), ballot, promised: Ballot::new(5, C) };
+        assert!(proposer.step(&config, C, reject, &mut rng).is_none());
+        assert_eq!(proposer.ballot(), ballot);
+
+        let accepted = Payload::Accepted { key: key.clone(), ballot };
+        assert!(proposer.step(&config, A, accepted.clone(), &mut rng).is_none());
+        assert_eq!(proposer.step(&config, B, accepted, &mut rng).unwrap().unwrap(), 1);
+
+        // next attempt uses a ballot higher than the rejected one
+        proposer.start(&config);
+        assert_eq!(proposer.ballot(), Ballot::new(6, A));
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::Result;
+use crate::paxos::cas::{Key, Ballot};
+
+/// Durable state of a single register, as seen by an acceptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptorState<T> {
+    /// The highest ballot this acceptor has promised.
+    pub promised: Ballot,
+    /// Ballot of the last accepted value.
+    pub accepted: Ballot,
+    pub value: Option<T>,
+}
+
+impl<T> Default for AcceptorState<T> {
+    fn default() -> Self {
+        AcceptorState { promised: Ballot::default(), accepted: Ballot::default(), value: None }
+    }
+}
+
+/// Persistent storage of acceptor states. Acceptor replies only after its state has been stored,
+/// so implementations must not return before a state is durable.
+pub trait Storage<T> {
+    /// Returns a state of a given register, or a default one if it has never been stored.
+    fn load(&self, key: &[u8]) -> Result<AcceptorState<T>>;
+
+    fn store(&mut self, key: &[u8], state: &AcceptorState<T>) -> Result<()>;
+}
+
+/// In-memory `Storage`, mostly useful for testing.
+#[derive(Debug, Clone)]
+pub struct MemStorage<T> {
+    states: HashMap<Key, AcceptorState<T>>,
+}
+
+impl<T> Default for MemStorage<T> {
+    fn default() -> Self {
+        MemStorage { states: HashMap::new() }
+    }
+}
+
+impl<T: Clone> Storage<T> for MemStorage<T> {
+    fn load(&self, key: &[u8]) -> Result<AcceptorState<T>> {
+        Ok(self.states.get(key).cloned().unwrap_or_default())
+    }
+
+    fn store(&mut self, key: &[u8], state: &AcceptorState<T>) -> Result<()> {
+        self.states.insert(key.to_vec(), state.clone());
+        Ok(())
+    }
+}
+
+/// Persistent `Storage` backed by a sled tree, flushed to disk on every modification.
+#[derive(Debug)]
+pub struct SledStorage<T> {
+    tree: sled::Tree,
+    _marker: PhantomData<T>,
+}
+
+impl<T> SledStorage<T> {
+    /// Opens a storage inside of a tree with a given name.
+    pub fn open(db: &sled::Db, name: &str) -> Result<Self> {
+        let tree = db.open_tree(name)?;
+        Ok(SledStorage { tree, _marker: PhantomData })
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storage<T> for SledStorage<T> {
+    fn load(&self, key: &[u8]) -> Result<AcceptorState<T>> {
+        match self.tree.get(key)? {
+            None => Ok(AcceptorState::default()),
+            Some(bytes) => Ok(serde_cbor::from_slice(bytes.as_ref())?),
+        }
+    }
+
+    fn store(&mut self, key: &[u8], state: &AcceptorState<T>) -> Result<()> {
+        self.tree.insert(key, serde_cbor::to_vec(state)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::cas::Ballot;
+    use crate::paxos::cas::storage::{SledStorage, Storage, AcceptorState};
+
+    #[test]
+    fn sled_storage_recover() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AcceptorState { promised: Ballot::new(2, 1), accepted: Ballot::new(1, 1), value: Some(10u32) };
+        {
+            let mut s = SledStorage::open(&db, "cas").unwrap();
+            assert_eq!(s.load(b"a").unwrap(), AcceptorState::default());
+            s.store(b"a", &state).unwrap();
+        }
+        let s: SledStorage<u32> = SledStorage::open(&db, "cas").unwrap();
+        assert_eq!(s.load(b"a").unwrap(), state);
+        assert_eq!(s.load(b"b").unwrap(), AcceptorState::default());
+    }
+}
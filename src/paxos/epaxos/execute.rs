@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::FromIterator;
+use crate::paxos::PeerId;
+use crate::paxos::epaxos::{InstanceId, Instance, Status};
+
+/// Set of executed instances. Slots of every replica are executed mostly in order, so they are
+/// kept as a prefix of executed slots together with executed slots following it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Executed {
+    replicas: BTreeMap<PeerId, (u64, BTreeSet<u64>)>,
+}
+
+impl Executed {
+    pub fn contains(&self, id: &InstanceId) -> bool {
+        match self.replicas.get(&id.replica) {
+            Some((next, following)) => id.slot < *next || following.contains(&id.slot),
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, id: InstanceId) {
+        let (next, following) = self.replicas.entry(id.replica).or_default();
+        if id.slot > *next {
+            following.insert(id.slot);
+        } else if id.slot == *next {
+            *next += 1;
+            while following.remove(next) {
+                *next += 1;
+            }
+        }
+    }
+}
+
+impl FromIterator<InstanceId> for Executed {
+    fn from_iter<I: IntoIterator<Item = InstanceId>>(iter: I) -> Self {
+        let mut executed = Executed::default();
+        for id in iter {
+            executed.insert(id);
+        }
+        executed
+    }
+}
+
+/// Returns committed instances, which can be executed after the already executed ones, in order
+/// of their execution.
+///
+/// Committed instances together with their dependencies form a graph, which is split into
+/// strongly connected components. Components are executed in the reverse topological order,
+/// so that dependencies of every instance are executed before it, while instances within a single
+/// component - depending on each other - are executed in order of their sequence numbers. Since
+/// all replicas commit the same attributes, all of them execute interfering commands in the same
+/// order. A component can be executed only once all instances it depends on are committed.
+/// Executed instances don't need to be present in `instances`, so they can be dropped.
+pub fn execution_order<C>(instances: &BTreeMap<InstanceId, Instance<C>>, executed: &Executed) -> Vec<InstanceId> {
+    let mut tarjan = Tarjan { instances, executed, next_index: 0, index: BTreeMap::new(), stack: Vec::new(), on_stack: BTreeSet::new(), done: BTreeSet::new(), order: Vec::new() };
+    for (&id, instance) in instances.iter() {
+        if instance.status == Status::Committed && !executed.contains(&id) && !tarjan.index.contains_key(&id) {
+            tarjan.visit(id);
+        }
+    }
+    tarjan.order
+}
+
+/// Iterative Tarjan's algorithm over committed instances, which are not executed yet.
+struct Tarjan<'a, C> {
+    instances: &'a BTreeMap<InstanceId, Instance<C>>,
+    executed: &'a Executed,
+    next_index: usize,
+    /// Indices of visited instances, together with the lowest index reachable from them.
+    index: BTreeMap<InstanceId, (usize, usize)>,
+    stack: Vec<InstanceId>,
+    on_stack: BTreeSet<InstanceId>,
+    /// Instances already put in the execution order.
+    done: BTreeSet<InstanceId>,
+    order: Vec<InstanceId>,
+}
+
+impl<'a, C> Tarjan<'a, C> {
+    fn visit(&mut self, root: InstanceId) {
+        let mut path = vec![(root, self.deps(root))];
+        self.push(root);
+        while let Some((id, deps)) = path.last_mut() {
+            let id = *id;
+            if let Some(dep) = deps.pop() {
+                match self.index.get(&dep) {
+                    None => {
+                        path.push((dep, self.deps(dep)));
+                        self.push(dep);
+                    },
+                    Some(&(index, _)) if self.on_stack.contains(&dep) => self.lower(id, index),
+                    Some(_) => {},
+                }
+            } else {
+                path.pop();
+                let (index, low) = self.index[&id];
+                if let Some((parent, _)) = path.last() {
+                    self.lower(*parent, low);
+                }
+                if index == low {
+                    self.component(id);
+                }
+            }
+        }
+    }
+
+    /// Returns dependencies of a given instance, which are committed but not executed yet.
+    fn deps(&self, id: InstanceId) -> Vec<InstanceId> {
+        self.instances[&id].deps.iter()
+            .filter(|dep| !self.executed.contains(dep))
+            .filter(|dep| self.instances.get(dep).map(|i| i.status == Status::Committed).unwrap_or(false))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&mut self, id: InstanceId) {
+        self.index.insert(id, (self.next_index, self.next_index));
+        self.next_index += 1;
+        self.stack.push(id);
+        self.on_stack.insert(id);
+    }
+
+    fn lower(&mut self, id: InstanceId, low: usize) {
+        if let Some((_, current)) = self.index.get_mut(&id) {
+            *current = (*current).min(low);
+        }
+    }
+
+    /// Pops a strongly connected component rooted at a given instance, appending it to
+    /// the execution order unless it depends on anything, which cannot be executed yet.
+    fn component(&mut self, root: InstanceId) {
+        let mut component = BTreeSet::new();
+        while let Some(id) = self.stack.pop() {
+            self.on_stack.remove(&id);
+            component.insert(id);
+            if id == root {
+                break;
+            }
+        }
+        let ready = component.iter()
+            .flat_map(|id| self.instances[id].deps.iter())
+            .all(|dep| self.executed.contains(dep) || self.done.contains(dep) || component.contains(dep));
+        if ready {
+            let mut ids: Vec<_> = component.into_iter().collect();
+            ids.sort_by_key(|id| (self.instances[id].seq, *id));
+            self.done.extend(ids.iter().cloned());
+            self.order.extend(ids);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use crate::paxos::epaxos::{InstanceId, Instance, Status};
+    use crate::paxos::epaxos::execute::{execution_order, Executed};
+
+    fn instance(status: Status, seq: u64, deps: &[InstanceId]) -> Instance<()> {
+        Instance { status, seq, command: Some(()), deps: deps.iter().cloned().collect(), ..Instance::default() }
+    }
+
+    #[test]
+    fn execution_order_of_components() {
+        let (a, b, c, d) = (InstanceId::new(1, 0), InstanceId::new(2, 0), InstanceId::new(3, 0), InstanceId::new(1, 1));
+        let mut instances = BTreeMap::new();
+        // b and c depend on each other, while d depends on both of them
+        instances.insert(a, instance(Status::Committed, 1, &[]));
+        instances.insert(b, instance(Status::Committed, 3, &[a, c]));
+        instances.insert(c, instance(Status::Committed, 2, &[b]));
+        instances.insert(d, instance(Status::Committed, 4, &[b]));
+        assert_eq!(execution_order(&instances, &Executed::default()), vec![a, c, b, d]);
+
+        let executed = vec![a, c, b].into_iter().collect();
+        assert_eq!(execution_order(&instances, &executed), vec![d]);
+    }
+
+    #[test]
+    fn execution_order_waits_for_commit() {
+        let (a, b, c, d) = (InstanceId::new(1, 0), InstanceId::new(2, 0), InstanceId::new(3, 0), InstanceId::new(3, 1));
+        let mut instances = BTreeMap::new();
+        instances.insert(a, instance(Status::Accepted, 1, &[]));
+        instances.insert(b, instance(Status::Committed, 2, &[a]));
+        instances.insert(c, instance(Status::Committed, 3, &[b]));
+        // dependency, which is not even known yet
+        instances.insert(d, instance(Status::Committed, 1, &[InstanceId::new(4, 0)]));
+        assert!(execution_order(&instances, &Executed::default()).is_empty());
+
+        instances.insert(a, instance(Status::Committed, 1, &[c]));
+        assert_eq!(execution_order(&instances, &Executed::default()), vec![a, b, c]);
+    }
+
+    #[test]
+    fn executed_prefix() {
+        let mut executed: Executed = vec![InstanceId::new(1, 0), InstanceId::new(1, 2), InstanceId::new(2, 1)].into_iter().collect();
+        assert!(executed.contains(&InstanceId::new(1, 2)));
+        assert!(!executed.contains(&InstanceId::new(1, 1)));
+        assert!(!executed.contains(&InstanceId::new(2, 0)));
+        assert_eq!(executed.replicas[&1], (1, vec![2].into_iter().collect()));
+
+        executed.insert(InstanceId::new(1, 1));
+        executed.insert(InstanceId::new(1, 1));
+        assert!(executed.contains(&InstanceId::new(1, 1)));
+        assert_eq!(executed.replicas[&1], (3, Default::default()));
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::paxos::PeerId;
+use crate::paxos::epaxos::{InstanceId, Ballot, Instance};
+
+/// A message exchanged between EPaxos replicas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message<C> {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub payload: Payload<C>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload<C> {
+    /// Asks a replica to pre-accept a command, extending its attributes with interfering
+    /// instances known to a recipient.
+    PreAccept { id: InstanceId, ballot: Ballot, command: C, seq: u64, deps: BTreeSet<InstanceId> },
+    /// Attributes of a command after they have been extended by a sender.
+    PreAcceptOk { id: InstanceId, ballot: Ballot, seq: u64, deps: BTreeSet<InstanceId> },
+    /// Asks a replica to accept final attributes of a command on a slow path.
+    Accept { id: InstanceId, ballot: Ballot, command: Option<C>, seq: u64, deps: BTreeSet<InstanceId> },
+    AcceptOk { id: InstanceId, ballot: Ballot },
+    /// Informs replicas, that a command has been committed with given attributes.
+    Commit { id: InstanceId, command: Option<C>, seq: u64, deps: BTreeSet<InstanceId> },
+    /// Asks a replica to promise a higher ballot of an instance, which is being recovered.
+    Prepare { id: InstanceId, ballot: Ballot },
+    /// Replica's promise together with its state of an instance, `None` if it doesn't know
+    /// its command.
+    PrepareOk { id: InstanceId, ballot: Ballot, instance: Option<Instance<C>> },
+    /// Request of a given ballot has been rejected, because a recipient has already promised
+    /// a higher one.
+    Nack { id: InstanceId, ballot: Ballot, promised: Ballot },
+}
@@ -0,0 +1,129 @@
+pub mod message;
+pub mod storage;
+pub mod execute;
+pub mod node;
+
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::paxos::PeerId;
+
+/// A command of a replicated state machine. Commands which don't interfere commute - they can
+/// be executed in any order with the same outcome - so EPaxos orders only interfering ones.
+pub trait Command: Clone {
+    /// Key of data accessed by commands, e.g. a key of a key-value store.
+    type Key: Ord + Clone;
+
+    /// Returns keys of all data accessed by this command. Commands interfere - they must be
+    /// executed in the same order on every replica - if they access at least one common key.
+    fn keys(&self) -> Vec<Self::Key>;
+}
+
+/// Identifier of an instance: every replica leads its own sequence of instances, each of which
+/// decides a single command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct InstanceId {
+    pub replica: PeerId,
+    pub slot: u64,
+}
+
+impl InstanceId {
+    pub fn new(replica: PeerId, slot: u64) -> Self {
+        InstanceId { replica, slot }
+    }
+}
+
+/// Ballot of a single instance. Every instance starts with a ballot `(0, replica)` of a replica
+/// owning it, while higher ballots are used by replicas recovering it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub replica: PeerId,
+}
+
+impl Ballot {
+    pub fn new(round: u64, replica: PeerId) -> Self {
+        Ballot { round, replica }
+    }
+
+    /// Returns an initial ballot of a given instance.
+    pub fn initial(id: InstanceId) -> Self {
+        Ballot::new(0, id.replica)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Status {
+    /// Replica has promised a ballot of an instance, but doesn't know its command yet.
+    Unknown,
+    PreAccepted,
+    Accepted,
+    Committed,
+}
+
+/// State of a single instance, as seen by a replica. A command is decided together with its
+/// attributes: a sequence number and dependencies - instances of interfering commands, which
+/// have been seen by replicas before this one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instance<C> {
+    /// The highest ballot this replica has promised.
+    pub ballot: Ballot,
+    /// Ballot, in which a current command and attributes have been pre-accepted or accepted.
+    pub voted: Ballot,
+    pub status: Status,
+    /// Command of this instance, `None` for a no-op committed by a recovery, which hasn't found
+    /// any command.
+    pub command: Option<C>,
+    pub seq: u64,
+    pub deps: BTreeSet<InstanceId>,
+}
+
+impl<C> Default for Instance<C> {
+    fn default() -> Self {
+        Instance {
+            ballot: Ballot::default(),
+            voted: Ballot::default(),
+            status: Status::Unknown,
+            command: None,
+            seq: 0,
+            deps: BTreeSet::new(),
+        }
+    }
+}
+
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// Configuration of an EPaxos `Node`. All timeouts are expressed in number of ticks.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Identifier of a current replica.
+    pub id: PeerId,
+    /// Identifiers of all replicas, including a current one.
+    pub peers: Vec<PeerId>,
+    /// Number of ticks a replica waits for responses, before it resends its requests or falls
+    /// back to a slow path. Instances of other replicas, which are not committed within a time
+    /// randomized within `[timeout, 2 * timeout)` range, are recovered.
+    pub timeout: u64,
+}
+
+impl Config {
+    pub fn new(id: PeerId, peers: Vec<PeerId>) -> Self {
+        Config { id, peers, timeout: DEFAULT_TIMEOUT }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.peers.contains(&self.id) {
+            Err(anyhow::anyhow!("EPaxos replica {} is not one of the peers {:?}", self.id, self.peers))
+        } else if self.timeout == 0 {
+            Err(anyhow::anyhow!("EPaxos timeout must be positive"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks if a given number of replicas forms a majority, which is enough for a command to
+    /// be committed on a slow path.
+    pub fn is_slow_quorum(&self, count: usize) -> bool {
+        count > self.peers.len() / 2
+    }
+}
@@ -0,0 +1,850 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::epaxos::{Config, Command, InstanceId, Ballot, Instance, Status};
+use crate::paxos::epaxos::execute::{execution_order, Executed};
+use crate::paxos::epaxos::message::{Message, Payload};
+use crate::paxos::epaxos::storage::Storage;
+
+/// Sequence number and dependencies of a command.
+type Attributes = (u64, BTreeSet<InstanceId>);
+
+enum Phase<C> {
+    /// Collecting attributes of a command extended by replicas, starting from given ones.
+    PreAccept { command: C, attributes: Attributes, replies: BTreeMap<PeerId, Attributes> },
+    /// Collecting acknowledgements of final attributes of a command.
+    Accept { command: Option<C>, attributes: Attributes, accepted: BTreeSet<PeerId> },
+    /// Recovering an instance, collecting its states known to replicas.
+    Prepare { replies: BTreeMap<PeerId, Option<Instance<C>>> },
+}
+
+/// An instance led by a current replica.
+struct Leading<C> {
+    ballot: Ballot,
+    phase: Phase<C>,
+    elapsed: u64,
+}
+
+/// Instances of commands accessing a single key, which new commands accessing it depend on.
+#[derive(Default)]
+struct Conflicts {
+    /// Instances known to this replica, which are not executed yet.
+    pending: BTreeSet<InstanceId>,
+    /// Instance executed last. All instances executed before it are ordered before it by their
+    /// dependencies, so depending on it is enough to be ordered after all of them.
+    executed: Option<InstanceId>,
+}
+
+/// A single replica of EPaxos - a leaderless state machine replication protocol. Every replica
+/// leads instances of commands proposed by itself, and only interfering commands are ordered.
+///
+/// A replica proposes a command by sending it to other replicas together with its attributes:
+/// interfering instances it knows about and a sequence number higher than theirs. Every replica
+/// extends attributes with interfering instances it knows about. If all replicas have replied
+/// with unchanged attributes, there was no conflict and a command is committed on a fast path
+/// after a single round trip. Otherwise, a union of attributes from a majority of replicas is
+/// accepted by a majority on a slow path. Requiring all replicas for a fast path - rather than
+/// a smaller fast quorum of the original protocol - keeps recovery simple, at the expense of
+/// falling back to a slow path after a timeout while any replica is unavailable.
+///
+/// Instances of other replicas, which are not committed within a timeout, are recovered with
+/// a higher ballot: a command which might have been committed is committed with the same
+/// attributes, while instances with no known command are committed as no-ops. Committed
+/// commands are executed in order given by their dependencies, see `execution_order`.
+///
+/// Instances are indexed by keys of their commands, so attributes of a new command are computed
+/// only from instances accessing the same keys. Executed instances are dropped from memory and read
+/// from a storage only to answer replicas, which are lagging behind.
+pub struct Node<C: Command, S> {
+    config: Config,
+    storage: S,
+    /// Instances known to this replica, which are not executed yet.
+    instances: BTreeMap<InstanceId, Instance<C>>,
+    conflicts: BTreeMap<C::Key, Conflicts>,
+    /// Slot of the next instance proposed by this replica.
+    next_slot: u64,
+    leading: BTreeMap<InstanceId, Leading<C>>,
+    /// Remaining ticks before recovering instances, which are not committed yet.
+    timers: BTreeMap<InstanceId, u64>,
+    executed: Executed,
+    rng: StdRng,
+    /// Messages addressed to a current replica, handled once a current one is done.
+    local: VecDeque<Payload<C>>,
+    outbox: Vec<Message<C>>,
+    output: Vec<(InstanceId, C)>,
+}
+
+impl<C: Command, S: Storage<C>> Node<C, S> {
+
+    /// Creates a new replica, restoring instances from a given storage. All committed commands
+    /// are executed again, so that an application can rebuild its state from `take_executed`.
+    pub fn new(config: Config, storage: S) -> Result<Self> {
+        config.validate()?;
+        let instances = storage.instances()?;
+        let next_slot = instances.keys()
+            .filter(|id| id.replica == config.id)
+            .map(|id| id.slot + 1)
+            .max()
+            .unwrap_or(0);
+        let mut node = Node {
+            config,
+            storage,
+            instances: BTreeMap::new(),
+            conflicts: BTreeMap::new(),
+            next_slot,
+            leading: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            executed: Executed::default(),
+            rng: StdRng::from_entropy(),
+            local: VecDeque::new(),
+            outbox: Vec::new(),
+            output: Vec::new(),
+        };
+        let ids: Vec<_> = instances.iter()
+            .flat_map(|(id, instance)| std::iter::once(id).chain(instance.deps.iter()))
+            .cloned()
+            .collect();
+        for (id, instance) in instances {
+            node.index(id, &instance);
+            node.instances.insert(id, instance);
+        }
+        for id in ids {
+            node.watch(id);
+        }
+        node.execute();
+        Ok(node)
+    }
+
+    pub fn id(&self) -> PeerId { self.config.id }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    /// Returns a status of a given instance, as seen by this replica.
+    pub fn status(&self, id: InstanceId) -> Option<Status> {
+        if self.executed.contains(&id) {
+            return Some(Status::Committed);
+        }
+        self.instances.get(&id).map(|instance| instance.status)
+    }
+
+    /// Proposes a command in a new instance led by this replica, returning its identifier.
+    /// A command is executed once it's committed, unless this replica is preempted by another
+    /// one recovering its instance before any replica has learned about a command.
+    pub fn propose(&mut self, command: C) -> Result<InstanceId> {
+        let id = InstanceId::new(self.config.id, self.next_slot);
+        self.next_slot += 1;
+        let attributes = self.attributes(id, &command, (0, BTreeSet::new()));
+        self.pre_accept(id, Ballot::initial(id), command, attributes);
+        self.process_local()?;
+        Ok(id)
+    }
+
+    /// Advances logical clock of this replica by a single tick.
+    pub fn tick(&mut self) -> Result<()> {
+        let timeout = self.config.timeout;
+        let mut expired = Vec::new();
+        for (&id, leading) in self.leading.iter_mut() {
+            leading.elapsed += 1;
+            if leading.elapsed >= timeout {
+                leading.elapsed = 0;
+                expired.push(id);
+            }
+        }
+        for id in expired {
+            if !self.try_slow_path(id, true) {
+                self.resend(id);
+            }
+        }
+        let mut recover = Vec::new();
+        for (&id, remaining) in self.timers.iter_mut() {
+            *remaining = remaining.saturating_sub(1);
+            if *remaining == 0 {
+                recover.push(id);
+            }
+        }
+        for id in recover {
+            self.recover(id);
+        }
+        self.process_local()
+    }
+
+    /// Handles a message received from another replica.
+    pub fn step(&mut self, msg: Message<C>) -> Result<()> {
+        self.handle(msg.from, msg.payload)?;
+        self.process_local()
+    }
+
+    /// Returns all messages produced by this replica since the last call, which should be sent
+    /// to their recipients.
+    pub fn take_messages(&mut self) -> Vec<Message<C>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Returns commands executed since the last call, in order of their execution.
+    pub fn take_executed(&mut self) -> Vec<(InstanceId, C)> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn handle(&mut self, from: PeerId, payload: Payload<C>) -> Result<()> {
+        match payload {
+            Payload::PreAccept { id, ballot, command, seq, deps } => self.handle_pre_accept(from, id, ballot, command, (seq, deps)),
+            Payload::PreAcceptOk { id, ballot, seq, deps } => {
+                if let Some(Phase::PreAccept { replies, .. }) = self.phase_mut(id, ballot) {
+                    replies.insert(from, (seq, deps));
+                    self.try_fast_path(id);
+                }
+                Ok(())
+            },
+            Payload::Accept { id, ballot, command, seq, deps } => self.handle_accept(from, id, ballot, command, (seq, deps)),
+            Payload::AcceptOk { id, ballot } => {
+                if let Some(Phase::Accept { accepted, .. }) = self.phase_mut(id, ballot) {
+                    accepted.insert(from);
+                    self.try_commit(id);
+                }
+                Ok(())
+            },
+            Payload::Commit { id, command, seq, deps } => self.commit(id, command, (seq, deps)),
+            Payload::Prepare { id, ballot } => self.handle_prepare(from, id, ballot),
+            Payload::PrepareOk { id, ballot, instance } => {
+                if let Some(Phase::Prepare { replies }) = self.phase_mut(id, ballot) {
+                    replies.insert(from, instance);
+                    let count = replies.len();
+                    if self.config.is_slow_quorum(count) {
+                        self.finish_recovery(id);
+                    }
+                }
+                Ok(())
+            },
+            Payload::Nack { id, ballot, promised } => self.handle_nack(id, ballot, promised),
+        }
+    }
+
+    fn handle_pre_accept(&mut self, from: PeerId, id: InstanceId, ballot: Ballot, command: C, attributes: Attributes) -> Result<()> {
+        let instance = self.instance(id)?;
+        if instance.status == Status::Committed {
+            self.send(from, Payload::Commit { id, command: instance.command, seq: instance.seq, deps: instance.deps });
+            return Ok(());
+        }
+        if ballot < instance.ballot {
+            self.send(from, Payload::Nack { id, ballot, promised: instance.ballot });
+            return Ok(());
+        }
+        if instance.status >= Status::Accepted && ballot <= instance.voted {
+            // a delayed pre-accept must not override attributes accepted in the same ballot
+            return Ok(());
+        }
+        let (seq, deps) = self.attributes(id, &command, attributes);
+        let instance = Instance { ballot, voted: ballot, status: Status::PreAccepted, command: Some(command), seq, deps: deps.clone() };
+        self.store(id, instance)?;
+        self.send(from, Payload::PreAcceptOk { id, ballot, seq, deps });
+        Ok(())
+    }
+
+    fn handle_accept(&mut self, from: PeerId, id: InstanceId, ballot: Ballot, command: Option<C>, (seq, deps): Attributes) -> Result<()> {
+        let instance = self.instance(id)?;
+        if instance.status == Status::Committed {
+            self.send(from, Payload::Commit { id, command: instance.command, seq: instance.seq, deps: instance.deps });
+            return Ok(());
+        }
+        if ballot < instance.ballot {
+            self.send(from, Payload::Nack { id, ballot, promised: instance.ballot });
+            return Ok(());
+        }
+        self.store(id, Instance { ballot, voted: ballot, status: Status::Accepted, command, seq, deps })?;
+        self.send(from, Payload::AcceptOk { id, ballot });
+        Ok(())
+    }
+
+    fn handle_prepare(&mut self, from: PeerId, id: InstanceId, ballot: Ballot) -> Result<()> {
+        let mut instance = self.instance(id)?;
+        if instance.status == Status::Committed {
+            self.send(from, Payload::Commit { id, command: instance.command, seq: instance.seq, deps: instance.deps });
+            return Ok(());
+        }
+        if ballot <= instance.ballot {
+            self.send(from, Payload::Nack { id, ballot, promised: instance.ballot });
+            return Ok(());
+        }
+        instance.ballot = ballot;
+        self.store(id, instance.clone())?;
+        let instance = Some(instance).filter(|instance| instance.status != Status::Unknown);
+        self.send(from, Payload::PrepareOk { id, ballot, instance });
+        Ok(())
+    }
+
+    fn handle_nack(&mut self, id: InstanceId, ballot: Ballot, promised: Ballot) -> Result<()> {
+        if self.leading.get(&id).map(|leading| leading.ballot == ballot).unwrap_or(false) {
+            log::info!("EPaxos replica {} preempted in instance {:?} by {:?}", self.config.id, id, promised);
+            self.leading.remove(&id);
+        }
+        let mut instance = self.instance(id)?;
+        if instance.status != Status::Committed && promised > instance.ballot {
+            // no lower ballot can succeed anymore, so a local replica can reject them as well
+            instance.ballot = promised;
+            self.store(id, instance)?;
+        }
+        self.watch(id);
+        Ok(())
+    }
+
+    /// Starts collecting attributes of a command in a given ballot.
+    fn pre_accept(&mut self, id: InstanceId, ballot: Ballot, command: C, attributes: Attributes) {
+        let (seq, deps) = attributes.clone();
+        let phase = Phase::PreAccept { command: command.clone(), attributes, replies: BTreeMap::new() };
+        self.lead(id, ballot, phase);
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::PreAccept { id, ballot, command: command.clone(), seq, deps: deps.clone() });
+        }
+    }
+
+    /// Commits a command on a fast path, if all replicas have replied with unchanged attributes
+    /// in an initial ballot, or falls back to a slow path when that's no longer possible.
+    fn try_fast_path(&mut self, id: InstanceId) {
+        let fast = match self.leading.get(&id) {
+            Some(Leading { ballot, phase: Phase::PreAccept { command, attributes, replies }, .. }) => {
+                let all = replies.len() == self.config.peers.len();
+                if all && *ballot == Ballot::initial(id) && replies.values().all(|a| a == attributes) {
+                    Some((command.clone(), attributes.clone()))
+                } else {
+                    None
+                }
+            },
+            _ => return,
+        };
+        match fast {
+            Some((command, (seq, deps))) => {
+                self.leading.remove(&id);
+                self.broadcast_commit(id, Some(command), seq, deps);
+            },
+            None => {
+                self.try_slow_path(id, false);
+            },
+        }
+    }
+
+    /// Starts a slow path with a union of attributes collected from a majority of replicas,
+    /// once a fast path is not possible or a given instance has timed out. Returns true if a slow
+    /// path has been started.
+    fn try_slow_path(&mut self, id: InstanceId, timed_out: bool) -> bool {
+        let (ballot, command, attributes) = match self.leading.get(&id) {
+            Some(Leading { ballot, phase: Phase::PreAccept { command, attributes, replies }, .. }) => {
+                let fast = *ballot == Ballot::initial(id) && replies.values().all(|a| a == attributes);
+                let all = replies.len() == self.config.peers.len();
+                if !self.config.is_slow_quorum(replies.len()) || (fast && !all && !timed_out) {
+                    return false;
+                }
+                let seq = replies.values().map(|(seq, _)| *seq).max().unwrap_or(attributes.0);
+                let deps = replies.values().flat_map(|(_, deps)| deps.iter()).cloned().collect();
+                (*ballot, command.clone(), (seq, deps))
+            },
+            _ => return false,
+        };
+        self.accept(id, ballot, Some(command), attributes);
+        true
+    }
+
+    /// Asks replicas to accept final attributes of a command in a given ballot.
+    fn accept(&mut self, id: InstanceId, ballot: Ballot, command: Option<C>, attributes: Attributes) {
+        let (seq, deps) = attributes.clone();
+        let phase = Phase::Accept { command: command.clone(), attributes, accepted: BTreeSet::new() };
+        self.lead(id, ballot, phase);
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::Accept { id, ballot, command: command.clone(), seq, deps: deps.clone() });
+        }
+    }
+
+    fn try_commit(&mut self, id: InstanceId) {
+        let committed = match self.leading.get(&id) {
+            Some(Leading { phase: Phase::Accept { accepted, .. }, .. }) => self.config.is_slow_quorum(accepted.len()),
+            _ => false,
+        };
+        if committed {
+            if let Some(Leading { phase: Phase::Accept { command, attributes: (seq, deps), .. }, .. }) = self.leading.remove(&id) {
+                self.broadcast_commit(id, command, seq, deps);
+            }
+        }
+    }
+
+    fn broadcast_commit(&mut self, id: InstanceId, command: Option<C>, seq: u64, deps: BTreeSet<InstanceId>) {
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::Commit { id, command: command.clone(), seq, deps: deps.clone() });
+        }
+    }
+
+    /// Starts recovery of an instance with a ballot higher than any other this replica has seen.
+    fn recover(&mut self, id: InstanceId) {
+        if self.is_committed(id) {
+            self.timers.remove(&id);
+            return;
+        }
+        let promised = self.instances.get(&id).map(|instance| instance.ballot).unwrap_or_default();
+        let ballot = Ballot::new(promised.round + 1, self.config.id);
+        log::info!("EPaxos replica {} recovers instance {:?} with ballot {:?}", self.config.id, id, ballot);
+        self.lead(id, ballot, Phase::Prepare { replies: BTreeMap::new() });
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::Prepare { id, ballot });
+        }
+    }
+
+    /// Continues recovery of an instance based on its states known to a majority of replicas.
+    fn finish_recovery(&mut self, id: InstanceId) {
+        let (ballot, replies) = match self.leading.remove(&id) {
+            Some(Leading { ballot, phase: Phase::Prepare { replies }, .. }) => (ballot, replies),
+            _ => return,
+        };
+        let known: Vec<_> = replies.values().flatten().collect();
+        if let Some(committed) = known.iter().find(|instance| instance.status == Status::Committed) {
+            let (command, seq, deps) = (committed.command.clone(), committed.seq, committed.deps.clone());
+            return self.broadcast_commit(id, command, seq, deps);
+        }
+        // attributes accepted in the highest ballot might have been committed on a slow path
+        if let Some(accepted) = known.iter().filter(|i| i.status == Status::Accepted).max_by_key(|i| i.voted) {
+            let attributes = (accepted.seq, accepted.deps.clone());
+            return self.accept(id, ballot, accepted.command.clone(), attributes);
+        }
+        let pre_accepted: Vec<_> = known.iter().filter(|i| i.status == Status::PreAccepted).collect();
+        let first = match pre_accepted.first() {
+            Some(first) => first,
+            // no replica knows a command, so it cannot have been committed
+            None => return self.accept(id, ballot, None, (0, BTreeSet::new())),
+        };
+        let command = first.command.clone()
+            .expect("Defect: Node::finish_recovery - pre-accepted instance has a command");
+        let initial = Ballot::initial(id);
+        let identical = pre_accepted.len() == replies.len() && pre_accepted.iter()
+            .all(|i| i.voted == initial && i.seq == first.seq && i.deps == first.deps);
+        if identical {
+            // command might have been committed on a fast path
+            let attributes = (first.seq, first.deps.clone());
+            self.accept(id, ballot, Some(command), attributes)
+        } else {
+            let seq = pre_accepted.iter().map(|i| i.seq).max().unwrap_or(0);
+            let deps = pre_accepted.iter().flat_map(|i| i.deps.iter()).cloned().collect();
+            self.pre_accept(id, ballot, command, (seq, deps))
+        }
+    }
+
+    /// Stores a committed command and executes all commands, which are ready.
+    fn commit(&mut self, id: InstanceId, command: Option<C>, (seq, deps): Attributes) -> Result<()> {
+        let mut instance = self.instance(id)?;
+        if instance.status == Status::Committed {
+            return Ok(());
+        }
+        instance.status = Status::Committed;
+        instance.command = command;
+        instance.seq = seq;
+        instance.deps = deps.clone();
+        self.store(id, instance)?;
+        self.leading.remove(&id);
+        for dep in deps {
+            self.watch(dep);
+        }
+        self.execute();
+        Ok(())
+    }
+
+    /// Executes all commands, which are ready, dropping their instances from memory.
+    fn execute(&mut self) {
+        for id in execution_order(&self.instances, &self.executed) {
+            self.executed.insert(id);
+            let instance = self.instances.remove(&id)
+                .expect("Defect: Node::execute - executed instance is known");
+            if let Some(command) = instance.command {
+                for key in command.keys() {
+                    if let Some(conflicts) = self.conflicts.get_mut(&key) {
+                        conflicts.pending.remove(&id);
+                        conflicts.executed = Some(id);
+                    }
+                }
+                self.output.push((id, command));
+            }
+        }
+    }
+
+    /// Extends attributes of a command with interfering instances known to this replica: all
+    /// instances accessing the same keys, which are not executed yet, and the last executed one.
+    /// Executed instances cannot depend on a new command, so its sequence number needs to be higher
+    /// only than sequence numbers of instances, which are not executed yet.
+    fn attributes(&self, id: InstanceId, command: &C, (mut seq, mut deps): Attributes) -> Attributes {
+        for key in command.keys() {
+            let conflicts = match self.conflicts.get(&key) {
+                Some(conflicts) => conflicts,
+                None => continue,
+            };
+            deps.extend(conflicts.executed);
+            for &other in conflicts.pending.iter().filter(|&&other| other != id) {
+                deps.insert(other);
+                seq = seq.max(self.instances[&other].seq + 1);
+            }
+        }
+        (seq, deps)
+    }
+
+    /// Returns a state of a given instance, reading it from a storage if it's already executed.
+    fn instance(&self, id: InstanceId) -> Result<Instance<C>> {
+        if let Some(instance) = self.instances.get(&id) {
+            Ok(instance.clone())
+        } else if self.executed.contains(&id) {
+            self.storage.instance(id)?
+                .ok_or_else(|| anyhow::anyhow!("EPaxos instance {:?} is executed, but missing in storage", id))
+        } else {
+            Ok(Instance::default())
+        }
+    }
+
+    fn store(&mut self, id: InstanceId, instance: Instance<C>) -> Result<()> {
+        self.storage.store(id, &instance)?;
+        if instance.status == Status::Committed {
+            self.timers.remove(&id);
+        }
+        self.index(id, &instance);
+        self.instances.insert(id, instance);
+        self.watch(id);
+        Ok(())
+    }
+
+    /// Indexes a new state of a given instance, which is not executed yet, by keys of its command.
+    /// A command of an instance changes only when it's recovered as a no-op.
+    fn index(&mut self, id: InstanceId, instance: &Instance<C>) {
+        if let Some(command) = self.instances.get(&id).and_then(|previous| previous.command.as_ref()) {
+            for key in command.keys() {
+                if let Some(conflicts) = self.conflicts.get_mut(&key) {
+                    conflicts.pending.remove(&id);
+                    if conflicts.pending.is_empty() && conflicts.executed.is_none() {
+                        self.conflicts.remove(&key);
+                    }
+                }
+            }
+        }
+        if let Some(command) = &instance.command {
+            for key in command.keys() {
+                self.conflicts.entry(key).or_default().pending.insert(id);
+            }
+        }
+    }
+
+    fn is_committed(&self, id: InstanceId) -> bool {
+        self.executed.contains(&id) || self.instances.get(&id).map(|i| i.status == Status::Committed).unwrap_or(false)
+    }
+
+    /// Starts a recovery timer of a given instance, unless it's committed or led by this replica.
+    fn watch(&mut self, id: InstanceId) {
+        if self.is_committed(id) || self.leading.contains_key(&id) || self.timers.contains_key(&id) {
+            return;
+        }
+        let timeout = self.config.timeout;
+        self.timers.insert(id, self.rng.gen_range(timeout, 2 * timeout));
+    }
+
+    fn lead(&mut self, id: InstanceId, ballot: Ballot, phase: Phase<C>) {
+        self.timers.remove(&id);
+        self.leading.insert(id, Leading { ballot, phase, elapsed: 0 });
+    }
+
+    fn phase_mut(&mut self, id: InstanceId, ballot: Ballot) -> Option<&mut Phase<C>> {
+        self.leading.get_mut(&id)
+            .filter(|leading| leading.ballot == ballot)
+            .map(|leading| &mut leading.phase)
+    }
+
+    /// Resends requests of a current phase to replicas, which haven't responded yet.
+    fn resend(&mut self, id: InstanceId) {
+        let leading = match self.leading.get(&id) {
+            Some(leading) => leading,
+            None => return,
+        };
+        let ballot = leading.ballot;
+        let (responded, payload): (Vec<PeerId>, _) = match &leading.phase {
+            Phase::PreAccept { command, attributes: (seq, deps), replies } => {
+                (replies.keys().cloned().collect(), Payload::PreAccept { id, ballot, command: command.clone(), seq: *seq, deps: deps.clone() })
+            },
+            Phase::Accept { command, attributes: (seq, deps), accepted } => {
+                (accepted.iter().cloned().collect(), Payload::Accept { id, ballot, command: command.clone(), seq: *seq, deps: deps.clone() })
+            },
+            Phase::Prepare { replies } => (replies.keys().cloned().collect(), Payload::Prepare { id, ballot }),
+        };
+        for to in self.config.peers.clone() {
+            if !responded.contains(&to) {
+                self.send(to, payload.clone());
+            }
+        }
+    }
+
+    fn process_local(&mut self) -> Result<()> {
+        while let Some(payload) = self.local.pop_front() {
+            self.handle(self.config.id, payload)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload<C>) {
+        if to == self.config.id {
+            self.local.push_back(payload);
+        } else {
+            self.outbox.push(Message { from: self.config.id, to, payload });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Result;
+    use crate::paxos::PeerId;
+    use crate::paxos::epaxos::{Config, Command, InstanceId, Ballot, Status};
+    use crate::paxos::epaxos::message::{Message, Payload};
+    use crate::paxos::epaxos::node::Node;
+    use crate::paxos::epaxos::storage::MemStorage;
+    use crate::paxos::sim::{Network, Process, Envelope};
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    /// Writes a value under a given key. Writes of the same key interfere.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Put(u8, u64);
+
+    impl Command for Put {
+        type Key = u8;
+
+        fn keys(&self) -> Vec<u8> { vec![self.0] }
+    }
+
+    type Replica = Node<Put, MemStorage<Put>>;
+
+    impl<T> Envelope for Message<T> {
+        fn from(&self) -> PeerId { self.from }
+        fn to(&self) -> PeerId { self.to }
+    }
+
+    impl Process for Replica {
+        type Message = Message<Put>;
+
+        fn step(&mut self, msg: Self::Message) -> Result<()> { Node::step(self, msg) }
+
+        fn tick(&mut self) -> Result<()> { Node::tick(self) }
+
+        fn take_messages(&mut self) -> Vec<Self::Message> { Node::take_messages(self) }
+    }
+
+    fn cluster(ids: &[PeerId]) -> Network<Replica> {
+        Network::new(ids.iter().map(|&id| (id, Node::new(Config::new(id, ids.to_vec()), MemStorage::default()).unwrap())))
+    }
+
+    fn executed(net: &mut Network<Replica>, id: PeerId) -> Vec<Put> {
+        net.node_mut(id).take_executed().into_iter().map(|(_, command)| command).collect()
+    }
+
+    /// Delivers messages until there are no more of them, returning their payloads.
+    fn deliver_all(net: &mut Network<Replica>) -> Vec<Payload<Put>> {
+        let mut payloads = Vec::new();
+        loop {
+            let messages: Vec<_> = [A, B, C].iter().flat_map(|&id| net.node_mut(id).take_messages()).collect();
+            if messages.is_empty() {
+                return payloads;
+            }
+            for msg in messages {
+                payloads.push(msg.payload.clone());
+                net.node_mut(msg.to).step(msg).unwrap();
+            }
+        }
+    }
+
+    fn is_accept(payload: &Payload<Put>) -> bool {
+        matches!(payload, Payload::Accept { .. })
+    }
+
+    #[test]
+    fn epaxos_single_replica() {
+        let mut a = Replica::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        assert_eq!(a.propose(Put(1, 10)).unwrap(), InstanceId::new(A, 0));
+        assert_eq!(a.propose(Put(1, 20)).unwrap(), InstanceId::new(A, 1));
+        assert_eq!(a.status(InstanceId::new(A, 1)), Some(Status::Committed));
+        assert!(a.take_messages().is_empty());
+        let commands: Vec<_> = a.take_executed().into_iter().map(|(_, c)| c).collect();
+        assert_eq!(commands, vec![Put(1, 10), Put(1, 20)]);
+        assert!(Replica::new(Config::new(A, vec![B]), MemStorage::default()).is_err());
+    }
+
+    #[test]
+    fn epaxos_fast_path() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).propose(Put(1, 10)).unwrap();
+        net.node_mut(B).propose(Put(2, 20)).unwrap();
+        // commands commute, so both of them are committed after a single round trip
+        assert!(!deliver_all(&mut net).iter().any(is_accept));
+        for &id in &[A, B, C] {
+            let mut commands = executed(&mut net, id);
+            commands.sort_by_key(|put| put.0);
+            assert_eq!(commands, vec![Put(1, 10), Put(2, 20)]);
+        }
+    }
+
+    #[test]
+    fn epaxos_slow_path_on_conflict() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).propose(Put(1, 10)).unwrap();
+        net.node_mut(B).propose(Put(1, 20)).unwrap();
+        net.node_mut(C).propose(Put(2, 30)).unwrap();
+        assert!(deliver_all(&mut net).iter().any(is_accept));
+        // interfering commands are executed in the same order by all replicas
+        let order = executed(&mut net, A);
+        assert_eq!(order.len(), 3);
+        for &id in &[B, C] {
+            let other = executed(&mut net, id);
+            assert_eq!(other.iter().filter(|put| put.0 == 1).collect::<Vec<_>>(), order.iter().filter(|put| put.0 == 1).collect::<Vec<_>>());
+        }
+
+        // once all replicas know about previous commands, there's no conflict anymore
+        net.node_mut(C).propose(Put(1, 40)).unwrap();
+        assert!(!deliver_all(&mut net).iter().any(is_accept));
+        assert_eq!(executed(&mut net, A), vec![Put(1, 40)]);
+    }
+
+    #[test]
+    fn epaxos_slow_path_without_replica() {
+        let mut net = cluster(&[A, B, C]);
+        net.isolate(C);
+        net.node_mut(A).propose(Put(1, 10)).unwrap();
+        net.deliver();
+        assert!(executed(&mut net, A).is_empty());
+        for _ in 0..Config::new(A, vec![A]).timeout {
+            net.node_mut(A).tick().unwrap();
+        }
+        net.deliver();
+        assert_eq!(executed(&mut net, A), vec![Put(1, 10)]);
+        assert_eq!(executed(&mut net, B), vec![Put(1, 10)]);
+    }
+
+    #[test]
+    fn epaxos_stale_pre_accept() {
+        let mut net = cluster(&[A, B, C]);
+        let id = net.node_mut(A).propose(Put(1, 10)).unwrap();
+        let (stale, messages): (Vec<_>, Vec<_>) = net.node_mut(A).take_messages()
+            .into_iter()
+            .partition(|msg| msg.to == C);
+        for msg in messages {
+            net.node_mut(B).step(msg).unwrap();
+        }
+        for msg in net.node_mut(B).take_messages() {
+            net.node_mut(A).step(msg).unwrap();
+        }
+        // without a reply from C, A falls back to the slow path
+        for _ in 0..Config::new(A, vec![A]).timeout {
+            net.node_mut(A).tick().unwrap();
+        }
+        let mut accepts = net.node_mut(A).take_messages();
+        assert!(accepts.iter().all(|msg| is_accept(&msg.payload)));
+        for msg in accepts.drain(..) {
+            net.node_mut(msg.to).step(msg).unwrap();
+        }
+        assert_eq!(net.node(C).status(id), Some(Status::Accepted));
+
+        // pre-accept delivered after the accept is ignored
+        let replies = net.node_mut(C).take_messages();
+        for msg in stale {
+            net.node_mut(C).step(msg).unwrap();
+        }
+        assert_eq!(net.node(C).status(id), Some(Status::Accepted));
+        assert!(net.node_mut(C).take_messages().is_empty());
+
+        for msg in replies {
+            net.node_mut(A).step(msg).unwrap();
+        }
+        deliver_all(&mut net);
+        for &id in &[A, B, C] {
+            assert_eq!(executed(&mut net, id), vec![Put(1, 10)]);
+        }
+    }
+
+    #[test]
+    fn epaxos_recovery() {
+        let mut net = cluster(&[A, B, C]);
+        // command reaches only a single replica before its leader fails
+        let id = net.node_mut(A).propose(Put(1, 10)).unwrap();
+        let messages = net.node_mut(A).take_messages();
+        net.isolate(A);
+        for msg in messages.into_iter().filter(|msg| msg.to == B) {
+            net.node_mut(B).step(msg).unwrap();
+        }
+        // interfering command of another replica depends on it
+        net.node_mut(C).propose(Put(1, 20)).unwrap();
+        let mut result = Vec::new();
+        for _ in 0..100 {
+            net.deliver();
+            result.extend(executed(&mut net, C));
+            if result.len() == 2 {
+                break;
+            }
+            net.tick_all();
+        }
+        let mut order = result.clone();
+        order.sort_by_key(|put| put.1);
+        assert_eq!(order, vec![Put(1, 10), Put(1, 20)]);
+        assert_eq!(net.node(B).status(id), Some(Status::Committed));
+
+        // instance, which no replica knows a command of, is committed as a no-op
+        let (missing, id) = (InstanceId::new(A, 1), InstanceId::new(A, 2));
+        let payload = Payload::Commit { id, command: Some(Put(2, 30)), seq: 0, deps: vec![missing].into_iter().collect() };
+        net.node_mut(B).step(Message { from: A, to: B, payload }).unwrap();
+        for _ in 0..100 {
+            net.deliver();
+            if net.node(B).status(missing) == Some(Status::Committed) {
+                break;
+            }
+            net.tick_all();
+        }
+        result.push(Put(2, 30));
+        assert_eq!(executed(&mut net, B), result);
+        assert_eq!(net.node(C).status(missing), Some(Status::Committed));
+    }
+
+    #[test]
+    fn epaxos_prunes_executed() {
+        let mut net = cluster(&[A, B, C]);
+        let first = net.node_mut(A).propose(Put(1, 10)).unwrap();
+        deliver_all(&mut net);
+        let last = net.node_mut(A).propose(Put(1, 20)).unwrap();
+        deliver_all(&mut net);
+        for &id in &[A, B, C] {
+            assert!(net.node(id).instances.is_empty());
+            assert_eq!(net.node(id).status(first), Some(Status::Committed));
+        }
+
+        // a new command depends only on the last executed interfering one
+        let id = net.node_mut(B).propose(Put(1, 30)).unwrap();
+        let deps = deliver_all(&mut net).into_iter().find_map(|payload| match payload {
+            Payload::Commit { id: committed, deps, .. } if committed == id => Some(deps),
+            _ => None,
+        });
+        assert_eq!(deps, Some(vec![last].into_iter().collect()));
+
+        // executed instance is read from a storage to answer a replica lagging behind
+        let ballot = Ballot::new(1, C);
+        net.node_mut(A).step(Message { from: C, to: A, payload: Payload::Prepare { id: first, ballot } }).unwrap();
+        match net.node_mut(A).take_messages().pop().map(|msg| msg.payload) {
+            Some(Payload::Commit { id, command, .. }) => assert_eq!((id, command), (first, Some(Put(1, 10)))),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn epaxos_restart() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).propose(Put(1, 10)).unwrap();
+        net.node_mut(C).propose(Put(1, 20)).unwrap();
+        net.deliver();
+        let order = executed(&mut net, C);
+        assert_eq!(order.len(), 2);
+
+        // restarted replica executes committed commands again and continues with new slots
+        let storage = net.node(C).storage().clone();
+        *net.node_mut(C) = Node::new(Config::new(C, vec![A, B, C]), storage).unwrap();
+        assert_eq!(executed(&mut net, C), order);
+        assert_eq!(net.node_mut(C).propose(Put(1, 30)).unwrap(), InstanceId::new(C, 1));
+        net.deliver();
+        assert_eq!(executed(&mut net, A).last(), Some(&Put(1, 30)));
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::Result;
+use crate::paxos::epaxos::{InstanceId, Instance};
+
+/// Durable storage of instances known to an EPaxos replica. All changes must be durable once
+/// a method modifying the storage returns.
+pub trait Storage<C> {
+    /// Returns all instances stored so far.
+    fn instances(&self) -> Result<BTreeMap<InstanceId, Instance<C>>>;
+
+    /// Returns a stored state of a given instance.
+    fn instance(&self, id: InstanceId) -> Result<Option<Instance<C>>>;
+
+    /// Stores a state of a given instance, replacing the previous one.
+    fn store(&mut self, id: InstanceId, instance: &Instance<C>) -> Result<()>;
+}
+
+/// In-memory `Storage` implementation, used mostly for testing.
+#[derive(Debug, Clone)]
+pub struct MemStorage<C> {
+    instances: BTreeMap<InstanceId, Instance<C>>,
+}
+
+impl<C> Default for MemStorage<C> {
+    fn default() -> Self {
+        MemStorage { instances: BTreeMap::new() }
+    }
+}
+
+impl<C: Clone> Storage<C> for MemStorage<C> {
+    fn instances(&self) -> Result<BTreeMap<InstanceId, Instance<C>>> {
+        Ok(self.instances.clone())
+    }
+
+    fn instance(&self, id: InstanceId) -> Result<Option<Instance<C>>> {
+        Ok(self.instances.get(&id).cloned())
+    }
+
+    fn store(&mut self, id: InstanceId, instance: &Instance<C>) -> Result<()> {
+        self.instances.insert(id, instance.clone());
+        Ok(())
+    }
+}
+
+/// Persistent EPaxos `Storage` backed by a sled tree, where instances are keyed by a replica
+/// and a slot. Every modification is flushed to disk before returning.
+#[derive(Debug)]
+pub struct SledStorage<C> {
+    tree: sled::Tree,
+    _marker: PhantomData<C>,
+}
+
+impl<C> SledStorage<C> {
+    /// Opens a storage inside of a given database, recovering its state if it was already
+    /// created before.
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree("epaxos_instances")?;
+        Ok(SledStorage { tree, _marker: PhantomData })
+    }
+}
+
+impl<C: Serialize + DeserializeOwned> Storage<C> for SledStorage<C> {
+    fn instances(&self) -> Result<BTreeMap<InstanceId, Instance<C>>> {
+        let mut result = BTreeMap::new();
+        for entry in self.tree.iter() {
+            let (key, bytes) = entry?;
+            result.insert(decode_id(key.as_ref())?, serde_cbor::from_slice(bytes.as_ref())?);
+        }
+        Ok(result)
+    }
+
+    fn instance(&self, id: InstanceId) -> Result<Option<Instance<C>>> {
+        match self.tree.get(encode_id(id))? {
+            None => Ok(None),
+            Some(bytes) => Ok(Some(serde_cbor::from_slice(bytes.as_ref())?)),
+        }
+    }
+
+    fn store(&mut self, id: InstanceId, instance: &Instance<C>) -> Result<()> {
+        self.tree.insert(encode_id(id), serde_cbor::to_vec(instance)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+fn encode_id(id: InstanceId) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&id.replica.to_be_bytes());
+    key[8..].copy_from_slice(&id.slot.to_be_bytes());
+    key
+}
+
+fn decode_id(key: &[u8]) -> Result<InstanceId> {
+    if key.len() != 16 {
+        return Err(anyhow::anyhow!("SledStorage: invalid instance key {:?}", key));
+    }
+    Ok(InstanceId::new(u64::from_be_bytes(key[..8].try_into()?), u64::from_be_bytes(key[8..].try_into()?)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::epaxos::{InstanceId, Instance, Ballot, Status};
+    use crate::paxos::epaxos::storage::{SledStorage, Storage};
+
+    #[test]
+    fn sled_storage_recover() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (a, b) = (InstanceId::new(1, 0), InstanceId::new(2, 7));
+        let committed = Instance {
+            ballot: Ballot::new(1, 2),
+            voted: Ballot::new(1, 2),
+            status: Status::Committed,
+            command: Some(10u32),
+            seq: 2,
+            deps: vec![a].into_iter().collect(),
+        };
+        {
+            let mut s = SledStorage::open(&db).unwrap();
+            s.store(a, &Instance { status: Status::PreAccepted, command: Some(1), ..Instance::default() }).unwrap();
+            s.store(b, &Instance::default()).unwrap();
+            s.store(b, &committed).unwrap();
+        }
+        let s: SledStorage<u32> = SledStorage::open(&db).unwrap();
+        let instances = s.instances().unwrap();
+        assert_eq!(instances.keys().cloned().collect::<Vec<_>>(), vec![a, b]);
+        assert_eq!(instances[&a].command, Some(1));
+        assert_eq!(instances[&b], committed);
+        assert_eq!(s.instance(b).unwrap(), Some(committed));
+        assert_eq!(s.instance(InstanceId::new(1, 1)).unwrap(), None);
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::matchmaker::{Round, Slot, Value};
+use crate::paxos::matchmaker::message::{Message, Payload};
+use crate::paxos::matchmaker::storage::AcceptorStorage;
+
+/// Matchmaker Paxos acceptor. It behaves like a MultiPaxos acceptor, the only difference is that
+/// it may participate only in some of the rounds. Its promise and votes are kept in a durable
+/// storage, and stored before replying. Votes in slots compacted by a proposer are discarded.
+#[derive(Debug, Clone)]
+pub struct Acceptor<S> {
+    id: PeerId,
+    storage: S,
+    /// The highest round this acceptor has participated in.
+    promised: Round,
+    /// Votes cast in slots lower than this one have been discarded.
+    compacted: Slot,
+    outbox: Vec<Message>,
+}
+
+impl<S: AcceptorStorage> Acceptor<S> {
+
+    /// Creates a new acceptor, restoring its promise from a given storage.
+    pub fn new(id: PeerId, storage: S) -> Result<Self> {
+        let promised = storage.promised()?;
+        let compacted = storage.compacted()?;
+        Ok(Acceptor { id, storage, promised, compacted, outbox: Vec::new() })
+    }
+
+    pub fn id(&self) -> PeerId { self.id }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    /// Returns the latest vote cast in every slot.
+    pub fn votes(&self) -> Result<BTreeMap<Slot, (Round, Value)>> { self.storage.votes() }
+
+    pub fn step(&mut self, msg: Message) -> Result<()> {
+        match msg.payload {
+            Payload::Phase1A { round } => {
+                if self.promise(msg.from, round)? {
+                    let votes = self.storage.votes()?;
+                    self.send(msg.from, Payload::Phase1B { round, compacted: self.compacted, votes });
+                }
+                Ok(())
+            },
+            Payload::Phase2A { round, slot, value, compacted } => {
+                if self.promise(msg.from, round)? {
+                    if compacted > self.compacted {
+                        self.storage.compact(compacted)?;
+                        self.compacted = compacted;
+                    }
+                    if slot >= self.compacted {
+                        self.storage.vote(slot, round, &value)?;
+                    }
+                    self.send(msg.from, Payload::Phase2B { round, slot });
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Promises not to participate in rounds lower than a given one, unless a higher round has
+    /// already been promised - in that case a sender is notified about it.
+    fn promise(&mut self, from: PeerId, round: Round) -> Result<bool> {
+        if round < self.promised {
+            self.send(from, Payload::Nack { round, higher: self.promised });
+            return Ok(false);
+        }
+        if round > self.promised {
+            self.storage.set_promised(round)?;
+            self.promised = round;
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload) {
+        self.outbox.push(Message { from: self.id, to, payload });
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::matchmaker::{Round, Configuration};
+use crate::paxos::matchmaker::message::{Message, Payload};
+use crate::paxos::matchmaker::storage::MatchmakerStorage;
+
+/// Matchmaker stores acceptor configurations of every round. Before a leader of a new round
+/// runs phase 1, it registers its configuration with a majority of matchmakers and learns
+/// configurations of all previous rounds, so that it knows which acceptors it needs to contact.
+/// Configurations are kept in a durable storage, and stored before replying.
+#[derive(Debug, Clone)]
+pub struct Matchmaker<S> {
+    id: PeerId,
+    storage: S,
+    configs: BTreeMap<Round, Configuration>,
+    /// Configurations of rounds lower than this one have been garbage collected.
+    gc: Round,
+    outbox: Vec<Message>,
+}
+
+impl<S: MatchmakerStorage> Matchmaker<S> {
+
+    /// Creates a new matchmaker, restoring configurations from a given storage.
+    pub fn new(id: PeerId, storage: S) -> Result<Self> {
+        let (gc, configs) = storage.configs()?;
+        Ok(Matchmaker { id, storage, configs, gc, outbox: Vec::new() })
+    }
+
+    pub fn id(&self) -> PeerId { self.id }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    /// Configurations stored by this matchmaker.
+    pub fn configs(&self) -> &BTreeMap<Round, Configuration> { &self.configs }
+
+    pub fn step(&mut self, msg: Message) -> Result<()> {
+        match msg.payload {
+            Payload::MatchA { round, config } => self.handle_match(msg.from, round, config),
+            Payload::GarbageA { round } => self.handle_garbage(msg.from, round),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn handle_match(&mut self, from: PeerId, round: Round, config: Configuration) -> Result<()> {
+        let highest = self.configs.keys().next_back().cloned().unwrap_or(self.gc);
+        let matched = self.configs.get(&round) == Some(&config);
+        if !matched && (round <= highest || round < self.gc) {
+            // once a higher round has been matched, lower rounds cannot be used anymore
+            self.send(from, Payload::Nack { round, higher: highest });
+            return Ok(());
+        }
+        if !matched {
+            self.storage.store_config(round, &config)?;
+            self.configs.insert(round, config);
+        }
+        let prior = self.configs.range(..round).map(|(&r, c)| (r, c.clone())).collect();
+        self.send(from, Payload::MatchB { round, gc: self.gc, prior });
+        Ok(())
+    }
+
+    fn handle_garbage(&mut self, from: PeerId, round: Round) -> Result<()> {
+        if round > self.gc {
+            self.storage.collect(round)?;
+            self.gc = round;
+            self.configs = self.configs.split_off(&round);
+        }
+        self.send(from, Payload::GarbageB { round });
+        Ok(())
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload) {
+        self.outbox.push(Message { from: self.id, to, payload });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::PeerId;
+    use crate::paxos::matchmaker::{Round, Configuration};
+    use crate::paxos::matchmaker::matchmaking::Matchmaker;
+    use crate::paxos::matchmaker::message::{Message, Payload};
+    use crate::paxos::matchmaker::storage::MemStorage;
+
+    const M: PeerId = 10;
+    const P: PeerId = 20;
+
+    fn request(m: &mut Matchmaker<MemStorage>, payload: Payload) -> Payload {
+        m.step(Message { from: P, to: M, payload }).unwrap();
+        m.take_messages().pop().unwrap().payload
+    }
+
+    #[test]
+    fn matchmaker_returns_prior_configs() {
+        let mut m = Matchmaker::new(M, MemStorage::default()).unwrap();
+        let (r1, r2, r3) = (Round::new(1, P), Round::new(2, P), Round::new(3, P));
+        let (c1, c2) = (Configuration::new(vec![1, 2, 3]), Configuration::new(vec![4, 5, 6]));
+
+        assert_eq!(request(&mut m, Payload::MatchA { round: r1, config: c1.clone() }),
+                   Payload::MatchB { round: r1, gc: Round::default(), prior: Default::default() });
+        let reply = request(&mut m, Payload::MatchA { round: r2, config: c2.clone() });
+        assert_eq!(reply, Payload::MatchB { round: r2, gc: Round::default(), prior: vec![(r1, c1.clone())].into_iter().collect() });
+        // retransmitted request is answered again, while lower rounds are rejected
+        assert_eq!(request(&mut m, Payload::MatchA { round: r2, config: c2.clone() }), reply);
+        let lower = Round::new(1, P + 1);
+        assert_eq!(request(&mut m, Payload::MatchA { round: lower, config: c1.clone() }), Payload::Nack { round: lower, higher: r2 });
+
+        assert_eq!(request(&mut m, Payload::GarbageA { round: r2 }), Payload::GarbageB { round: r2 });
+        assert_eq!(m.configs().keys().cloned().collect::<Vec<_>>(), vec![r2]);
+        assert_eq!(request(&mut m, Payload::MatchA { round: r3, config: c2.clone() }),
+                   Payload::MatchB { round: r3, gc: r2, prior: vec![(r2, c2.clone())].into_iter().collect() });
+
+        // a restarted matchmaker still rejects rounds lower than the ones it has matched
+        let mut m = Matchmaker::new(M, m.storage().clone()).unwrap();
+        assert_eq!(m.configs().keys().cloned().collect::<Vec<_>>(), vec![r2, r3]);
+        assert_eq!(request(&mut m, Payload::MatchA { round: r2, config: c1 }), Payload::Nack { round: r2, higher: r3 });
+        assert_eq!(request(&mut m, Payload::MatchA { round: r3, config: c2.clone() }),
+                   Payload::MatchB { round: r3, gc: r2, prior: vec![(r2, c2)].into_iter().collect() });
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::paxos::PeerId;
+use crate::paxos::matchmaker::{Round, Slot, Value, Configuration};
+
+/// A message exchanged between Matchmaker Paxos proposers, matchmakers and acceptors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload {
+    /// Asks a matchmaker to store a configuration of a given round.
+    MatchA { round: Round, config: Configuration },
+    /// Configurations of all rounds lower than a requested one, which have not been garbage
+    /// collected. `gc` is a round below which configurations have been garbage collected.
+    MatchB { round: Round, gc: Round, prior: BTreeMap<Round, Configuration> },
+    /// Asks a matchmaker to remove configurations of rounds lower than a given one.
+    GarbageA { round: Round },
+    GarbageB { round: Round },
+    /// Phase 1 request of a given round.
+    Phase1A { round: Round },
+    /// Acceptor's promise together with the latest votes it has cast in every slot, starting
+    /// from `compacted`. Slots lower than it have been chosen and compacted.
+    Phase1B { round: Round, compacted: Slot, votes: BTreeMap<Slot, (Round, Value)> },
+    /// Asks an acceptor to vote for a value in a given slot. Slots lower than `compacted` have
+    /// been compacted by a proposer, so acceptors can discard votes cast in them.
+    Phase2A { round: Round, slot: Slot, value: Value, compacted: Slot },
+    Phase2B { round: Round, slot: Slot },
+    /// Request of a given round has been rejected, because a recipient has already seen
+    /// a higher one.
+    Nack { round: Round, higher: Round },
+}
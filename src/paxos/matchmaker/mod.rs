@@ -0,0 +1,101 @@
+pub mod message;
+pub mod matchmaking;
+pub mod acceptor;
+pub mod proposer;
+pub mod storage;
+
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::quorum::{Quorum, QuorumSystem};
+
+/// Position of a command in a replicated log.
+pub type Slot = u64;
+
+/// Round of Matchmaker Paxos. Rounds are totally ordered by their number first and an identifier
+/// of a proposer leading them, so that every round has a single leader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Round {
+    pub number: u64,
+    pub leader: PeerId,
+}
+
+impl Round {
+    pub fn new(number: u64, leader: PeerId) -> Self {
+        Round { number, leader }
+    }
+}
+
+/// A value proposed for a single slot of a log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Value {
+    /// Fills a slot, for which no command was chosen.
+    Noop,
+    Command(Vec<u8>),
+}
+
+/// Set of acceptors used within a single round, together with their quorum system. Unlike in
+/// classic Paxos, every round can use a different set of acceptors - the leader of a round learns
+/// configurations of all previous rounds from matchmakers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Configuration {
+    pub acceptors: BTreeSet<PeerId>,
+    pub quorum: Quorum,
+}
+
+impl Configuration {
+    /// Creates a configuration of given acceptors using majority quorums.
+    pub fn new<I: IntoIterator<Item=PeerId>>(acceptors: I) -> Self {
+        Configuration { acceptors: acceptors.into_iter().collect(), quorum: Quorum::Majority }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.quorum.validate(&self.acceptors)
+    }
+
+    /// Checks if given nodes contain a phase 1 quorum of acceptors of this configuration.
+    pub fn is_phase1_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase1_quorum(&self.acceptors, nodes)
+    }
+
+    /// Checks if given nodes contain a phase 2 quorum of acceptors of this configuration.
+    pub fn is_phase2_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase2_quorum(&self.acceptors, nodes)
+    }
+}
+
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// Configuration of a Matchmaker Paxos proposer. Timeouts are expressed in number of ticks.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Identifier of a current node.
+    pub id: PeerId,
+    /// Identifiers of all matchmakers. In order to tolerate `f` failures, there must be `2f + 1`
+    /// of them.
+    pub matchmakers: Vec<PeerId>,
+    /// Number of ticks after which unanswered requests are send again.
+    pub timeout: u64,
+}
+
+impl Config {
+    pub fn new(id: PeerId, matchmakers: Vec<PeerId>) -> Self {
+        Config { id, matchmakers, timeout: DEFAULT_TIMEOUT }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.matchmakers.is_empty() {
+            Err(anyhow::anyhow!("Matchmaker Paxos requires at least one matchmaker"))
+        } else if self.timeout == 0 {
+            Err(anyhow::anyhow!("Matchmaker Paxos timeout must be positive"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Number of matchmakers forming a majority.
+    pub fn quorum(&self) -> usize {
+        self.matchmakers.len() / 2 + 1
+    }
+}
@@ -0,0 +1,687 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::matchmaker::{Config, Round, Slot, Value, Configuration};
+use crate::paxos::matchmaker::message::{Message, Payload};
+
+type Votes = BTreeMap<Slot, (Round, Value)>;
+
+/// A round which is being established by a proposer.
+struct Election {
+    round: Round,
+    config: Configuration,
+    stage: Stage,
+}
+
+enum Stage {
+    /// Collecting configurations of previous rounds from matchmakers, together with their
+    /// garbage collection watermarks.
+    Matchmaking(BTreeMap<PeerId, (Round, BTreeMap<Round, Configuration>)>),
+    /// Collecting votes from acceptors of all previous configurations, together with slots
+    /// their logs have been compacted up to.
+    Phase1 {
+        prior: BTreeMap<Round, Configuration>,
+        votes: BTreeMap<PeerId, (Slot, Votes)>,
+    },
+}
+
+/// A value proposed in a current round, which has not been chosen yet.
+struct Proposal {
+    value: Value,
+    votes: BTreeSet<PeerId>,
+}
+
+/// Progress of garbage collecting configurations of rounds lower than a current one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Gc {
+    /// Waiting for all slots lower than a given one to be chosen in a current round.
+    Waiting(Slot),
+    /// Waiting for matchmakers to acknowledge garbage collection.
+    Collecting(BTreeSet<PeerId>),
+    Done,
+}
+
+/// Matchmaker MultiPaxos proposer. Once it becomes a leader of a round, it keeps proposing
+/// commands in consecutive slots of a replicated log, skipping phase 1 for all of them.
+///
+/// A new round is started with `start`, which can be used both to take over leadership and to
+/// change the set of acceptors. First, a configuration of a new round is registered with
+/// a majority of matchmakers, which in return send configurations of all previous rounds. Then
+/// phase 1 is executed against acceptors of each of these configurations, and all values they
+/// may have chosen are proposed again in a new round. When a current leader changes acceptors,
+/// phase 1 is skipped: it already knows all values proposed in its previous round, and it keeps
+/// proposing commands there until a new configuration is registered, so the log doesn't stop.
+///
+/// Once all values from previous rounds are chosen in a new round, configurations of previous
+/// rounds are garbage collected and acceptors which are not part of a current configuration
+/// can be shut down. Every new configuration receives a copy of a log, and a proposer keeps all
+/// values it has seen chosen, so that it can copy them without running phase 1. Once an
+/// application has stored its state up to a given slot, e.g. in a snapshot, it should `compact`
+/// a log: chosen values below that slot are discarded by a proposer, and by acceptors together
+/// with the next proposal they receive. A proposer taking over a compacted log returns entries
+/// from `take_committed` starting at the first slot, which has not been compacted, so its
+/// application has to restore the earlier state on its own.
+///
+/// Rounds of a proposer are not persisted, so a restarted proposer must use an id, which has not
+/// been used before.
+///
+/// Choosing a leader is left up to an application. When a leader is preempted by a higher round,
+/// it stops leading and drops all commands which have not been chosen yet.
+pub struct Proposer {
+    config: Config,
+    /// The highest round observed so far.
+    highest: Round,
+    /// Round this proposer leads in phase 2, together with its configuration.
+    active: Option<(Round, Configuration)>,
+    election: Option<Election>,
+    proposals: BTreeMap<Slot, Proposal>,
+    next_slot: Slot,
+    /// Commands proposed while there's no active round.
+    queue: VecDeque<Vec<u8>>,
+    /// Values chosen in slots, which have not been compacted yet.
+    chosen: BTreeMap<Slot, Value>,
+    /// Values chosen in slots lower than this one have been compacted.
+    compacted: Slot,
+    /// The next slot to be returned by `take_committed`.
+    committed: Slot,
+    ready: Vec<(Slot, Value)>,
+    gc: Gc,
+    elapsed: u64,
+    outbox: Vec<Message>,
+}
+
+impl Proposer {
+    pub fn new(config: Config) -> Result<Self> {
+        config.validate()?;
+        Ok(Proposer {
+            config,
+            highest: Round::default(),
+            active: None,
+            election: None,
+            proposals: BTreeMap::new(),
+            next_slot: 0,
+            queue: VecDeque::new(),
+            chosen: BTreeMap::new(),
+            compacted: 0,
+            committed: 0,
+            ready: Vec::new(),
+            gc: Gc::Done,
+            elapsed: 0,
+            outbox: Vec::new(),
+        })
+    }
+
+    pub fn id(&self) -> PeerId { self.config.id }
+
+    /// Round led by this proposer, if any.
+    pub fn round(&self) -> Option<Round> {
+        self.active.as_ref().map(|(round, _)| *round)
+    }
+
+    /// Configuration of acceptors used in a current round.
+    pub fn configuration(&self) -> Option<&Configuration> {
+        self.active.as_ref().map(|(_, config)| config)
+    }
+
+    pub fn is_leader(&self) -> bool { self.active.is_some() }
+
+    /// Checks if configurations of rounds preceding a current one have been garbage collected,
+    /// which means that acceptors not present in a current configuration are no longer needed.
+    pub fn is_stable(&self) -> bool {
+        self.active.is_some() && self.election.is_none() && self.gc == Gc::Done
+    }
+
+    /// Starts a new round, which will use a given set of acceptors.
+    pub fn start(&mut self, config: Configuration) -> Result<()> {
+        config.validate()?;
+        let round = Round::new(self.highest.number + 1, self.config.id);
+        self.highest = round;
+        self.elapsed = 0;
+        self.election = Some(Election { round, config: config.clone(), stage: Stage::Matchmaking(BTreeMap::new()) });
+        for to in self.config.matchmakers.clone() {
+            self.send(to, Payload::MatchA { round, config: config.clone() });
+        }
+        Ok(())
+    }
+
+    /// Proposes a command to be appended to a log. Commands proposed while a new round is being
+    /// established are queued until it's ready.
+    pub fn propose(&mut self, command: Vec<u8>) -> Result<()> {
+        if self.active.is_some() {
+            self.propose_value(Value::Command(command));
+        } else if self.election.is_some() {
+            self.queue.push_back(command);
+        } else {
+            return Err(anyhow::anyhow!("Matchmaker Paxos proposer {} is not a leader", self.config.id));
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self, msg: Message) -> Result<()> {
+        match msg.payload {
+            Payload::MatchB { round, gc, prior } => self.handle_match(msg.from, round, gc, prior),
+            Payload::Phase1B { round, compacted, votes } => self.handle_phase1(msg.from, round, compacted, votes),
+            Payload::Phase2B { round, slot } => self.handle_phase2(msg.from, round, slot),
+            Payload::GarbageB { round } => self.handle_garbage(msg.from, round),
+            Payload::Nack { round, higher } => self.handle_nack(round, higher),
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Advances a logical clock of this proposer, resending requests which were not answered
+    /// within a timeout.
+    pub fn tick(&mut self) -> Result<()> {
+        self.elapsed += 1;
+        if self.elapsed < self.config.timeout {
+            return Ok(());
+        }
+        self.elapsed = 0;
+        let mut messages = Vec::new();
+        match &self.election {
+            Some(Election { round, config, stage: Stage::Matchmaking(replies) }) => {
+                for &to in self.config.matchmakers.iter().filter(|id| !replies.contains_key(id)) {
+                    messages.push((to, Payload::MatchA { round: *round, config: config.clone() }));
+                }
+            },
+            Some(Election { round, stage: Stage::Phase1 { prior, votes }, .. }) => {
+                let acceptors: BTreeSet<_> = prior.values().flat_map(|c| c.acceptors.iter().cloned()).collect();
+                for to in acceptors.into_iter().filter(|id| !votes.contains_key(id)) {
+                    messages.push((to, Payload::Phase1A { round: *round }));
+                }
+            },
+            None => {},
+        }
+        if let Some((round, config)) = &self.active {
+            for (&slot, proposal) in self.proposals.iter() {
+                for &to in config.acceptors.difference(&proposal.votes) {
+                    messages.push((to, Payload::Phase2A { round: *round, slot, value: proposal.value.clone(), compacted: self.compacted }));
+                }
+            }
+            if let Gc::Collecting(acks) = &self.gc {
+                for &to in self.config.matchmakers.iter().filter(|id| !acks.contains(id)) {
+                    messages.push((to, Payload::GarbageA { round: *round }));
+                }
+            }
+        }
+        for (to, payload) in messages {
+            self.send(to, payload);
+        }
+        Ok(())
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Returns all log entries chosen since the last call, in the log order without gaps.
+    pub fn take_committed(&mut self) -> Vec<(Slot, Value)> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Discards values chosen in slots lower than a given one. Only slots, which have already
+    /// been returned from `take_committed`, can be compacted.
+    pub fn compact(&mut self, slot: Slot) -> Result<()> {
+        if slot > self.committed {
+            return Err(anyhow::anyhow!("Matchmaker Paxos proposer {} cannot compact a log up to slot {}, which is not committed yet", self.config.id, slot));
+        }
+        self.compact_to(slot);
+        Ok(())
+    }
+
+    fn handle_match(&mut self, from: PeerId, round: Round, gc: Round, prior: BTreeMap<Round, Configuration>) {
+        let quorum = self.config.quorum();
+        let replies = match &mut self.election {
+            Some(Election { round: r, stage: Stage::Matchmaking(replies), .. }) if *r == round => replies,
+            _ => return,
+        };
+        replies.insert(from, (gc, prior));
+        if replies.len() < quorum {
+            return;
+        }
+        // configurations below any of garbage collection watermarks are no longer needed
+        let watermark = replies.values().map(|(gc, _)| *gc).max().unwrap_or_default();
+        let prior: BTreeMap<_, _> = replies.values()
+            .flat_map(|(_, prior)| prior.iter().map(|(&r, c)| (r, c.clone())))
+            .filter(|(r, _)| *r >= watermark)
+            .collect();
+
+        let bypass = match &self.active {
+            // a leader knows everything proposed in its own round, which already covers all
+            // previous ones, as long as no one else has tried to start a round in the meantime
+            Some((active, _)) => prior.keys().all(|r| r <= active),
+            None => false,
+        };
+        if bypass {
+            let election = self.election.take()
+                .expect("Defect: Proposer::handle_match - election is in progress");
+            let values: BTreeMap<_, _> = (self.compacted..self.next_slot)
+                .map(|slot| {
+                    let value = match self.proposals.get(&slot) {
+                        Some(proposal) => proposal.value.clone(),
+                        None => self.chosen.get(&slot).cloned()
+                            .expect("Defect: Proposer::handle_match - every slot is either chosen or proposed"),
+                    };
+                    (slot, value)
+                })
+                .collect();
+            self.activate(election.round, election.config, values);
+        } else {
+            // previous round cannot be continued, there might be newer values unknown to us
+            self.active = None;
+            self.proposals.clear();
+            let acceptors: BTreeSet<_> = prior.values().flat_map(|c| c.acceptors.iter().cloned()).collect();
+            if let Some(election) = &mut self.election {
+                election.stage = Stage::Phase1 { prior, votes: BTreeMap::new() };
+            }
+            for to in acceptors {
+                self.send(to, Payload::Phase1A { round });
+            }
+            self.check_phase1();
+        }
+    }
+
+    fn handle_phase1(&mut self, from: PeerId, round: Round, compacted: Slot, received: Votes) {
+        match &mut self.election {
+            Some(Election { round: r, stage: Stage::Phase1 { votes, .. }, .. }) if *r == round => {
+                votes.insert(from, (compacted, received));
+            },
+            _ => return,
+        }
+        self.check_phase1();
+    }
+
+    /// Completes phase 1 once a phase 1 quorum of acceptors of every previous configuration has
+    /// responded.
+    fn check_phase1(&mut self) {
+        let complete = match &self.election {
+            Some(Election { stage: Stage::Phase1 { prior, votes }, .. }) => {
+                let responded: BTreeSet<_> = votes.keys().cloned().collect();
+                prior.values().all(|config| config.is_phase1_quorum(&responded))
+            },
+            _ => false,
+        };
+        if !complete {
+            return;
+        }
+        let election = self.election.take()
+            .expect("Defect: Proposer::check_phase1 - election is in progress");
+        let votes = match election.stage {
+            Stage::Phase1 { votes, .. } => votes,
+            Stage::Matchmaking(_) => panic!("Defect: Proposer::check_phase1 - phase 1 is complete"),
+        };
+        // slots compacted by any acceptor are known to be chosen, so they're not proposed again
+        let compacted = votes.values().map(|(compacted, _)| *compacted).max().unwrap_or(0);
+        self.compact_to(compacted);
+        // in every slot, a value voted for in the highest round might have been chosen
+        let mut safe: BTreeMap<Slot, (Round, Value)> = BTreeMap::new();
+        for (&slot, (round, value)) in votes.values().flat_map(|(_, votes)| votes.range(self.compacted..)) {
+            let newer = safe.get(&slot).map(|(r, _)| round > r).unwrap_or(true);
+            if newer {
+                safe.insert(slot, (*round, value.clone()));
+            }
+        }
+        let end = safe.keys().next_back().map(|slot| slot + 1).unwrap_or(self.compacted);
+        // gaps are filled with no-ops, so that the log can be executed
+        let values = (self.compacted..end)
+            .map(|slot| (slot, safe.remove(&slot).map(|(_, v)| v).unwrap_or(Value::Noop)))
+            .collect();
+        self.activate(election.round, election.config, values);
+    }
+
+    /// Starts phase 2 of a given round by proposing values which were or might have been chosen
+    /// in previous rounds.
+    fn activate(&mut self, round: Round, config: Configuration, mut values: BTreeMap<Slot, Value>) {
+        if values.is_empty() && self.compacted > 0 {
+            // acceptors of a new configuration must learn where a log has been compacted before
+            // previous configurations are garbage collected, a no-op carries it to them
+            values.insert(self.compacted, Value::Noop);
+        }
+        let end = values.keys().next_back().map(|slot| slot + 1).unwrap_or(0);
+        self.gc = if end == 0 { Gc::Done } else { Gc::Waiting(end) };
+        self.active = Some((round, config));
+        self.proposals.clear();
+        self.next_slot = end;
+        for (slot, value) in values {
+            self.propose_at(slot, value);
+        }
+        while let Some(command) = self.queue.pop_front() {
+            self.propose_value(Value::Command(command));
+        }
+    }
+
+    fn propose_value(&mut self, value: Value) {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.propose_at(slot, value);
+    }
+
+    fn propose_at(&mut self, slot: Slot, value: Value) {
+        let (round, acceptors) = match &self.active {
+            Some((round, config)) => (*round, config.acceptors.clone()),
+            None => return,
+        };
+        self.proposals.insert(slot, Proposal { value: value.clone(), votes: BTreeSet::new() });
+        for to in acceptors {
+            self.send(to, Payload::Phase2A { round, slot, value: value.clone(), compacted: self.compacted });
+        }
+    }
+
+    fn handle_phase2(&mut self, from: PeerId, round: Round, slot: Slot) {
+        let config = match &self.active {
+            Some((r, config)) if *r == round => config,
+            _ => return,
+        };
+        let proposal = match self.proposals.get_mut(&slot) {
+            Some(proposal) => proposal,
+            None => return,
+        };
+        proposal.votes.insert(from);
+        if !config.is_phase2_quorum(&proposal.votes) {
+            return;
+        }
+        let proposal = self.proposals.remove(&slot)
+            .expect("Defect: Proposer::handle_phase2 - proposal has been found");
+        self.chosen.entry(slot).or_insert(proposal.value);
+        while let Some(value) = self.chosen.get(&self.committed) {
+            self.ready.push((self.committed, value.clone()));
+            self.committed += 1;
+        }
+        self.check_gc(round);
+    }
+
+    fn compact_to(&mut self, slot: Slot) {
+        if slot > self.compacted {
+            self.compacted = slot;
+            self.chosen = self.chosen.split_off(&slot);
+            self.committed = self.committed.max(slot);
+        }
+    }
+
+    /// Starts garbage collection once all values from previous rounds are chosen in a current one.
+    fn check_gc(&mut self, round: Round) {
+        let end = match self.gc {
+            Gc::Waiting(end) => end,
+            _ => return,
+        };
+        if self.proposals.range(..end).next().is_none() {
+            self.gc = Gc::Collecting(BTreeSet::new());
+            for to in self.config.matchmakers.clone() {
+                self.send(to, Payload::GarbageA { round });
+            }
+        }
+    }
+
+    fn handle_garbage(&mut self, from: PeerId, round: Round) {
+        if self.round() != Some(round) {
+            return;
+        }
+        let quorum = self.config.quorum();
+        if let Gc::Collecting(acks) = &mut self.gc {
+            acks.insert(from);
+            if acks.len() >= quorum {
+                self.gc = Gc::Done;
+            }
+        }
+    }
+
+    fn handle_nack(&mut self, round: Round, higher: Round) {
+        self.highest = self.highest.max(higher);
+        let election = self.election.as_ref().map(|e| e.round);
+        if higher > round && (self.round() == Some(round) || election == Some(round)) {
+            log::info!("Matchmaker Paxos proposer {} preempted in round {:?} by {:?}", self.config.id, round, higher);
+            self.active = None;
+            self.election = None;
+            self.proposals.clear();
+            self.queue.clear();
+        }
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload) {
+        self.outbox.push(Message { from: self.config.id, to, payload });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Result;
+    use crate::paxos::PeerId;
+    use crate::paxos::matchmaker::{Config, Configuration, Slot, Value};
+    use crate::paxos::matchmaker::acceptor::Acceptor;
+    use crate::paxos::matchmaker::matchmaking::Matchmaker;
+    use crate::paxos::matchmaker::message::Message;
+    use crate::paxos::matchmaker::proposer::Proposer;
+    use crate::paxos::matchmaker::storage::MemStorage;
+    use crate::paxos::sim::{Network, Process, Envelope};
+
+    const P: PeerId = 1;
+    const Q: PeerId = 2;
+    const MATCHMAKERS: [PeerId; 3] = [11, 12, 13];
+
+    enum Node {
+        Proposer(Box<Proposer>),
+        Matchmaker(Matchmaker<MemStorage>),
+        Acceptor(Acceptor<MemStorage>),
+    }
+
+    impl Envelope for Message {
+        fn from(&self) -> PeerId { self.from }
+        fn to(&self) -> PeerId { self.to }
+    }
+
+    impl Process for Node {
+        type Message = Message;
+
+        fn step(&mut self, msg: Message) -> Result<()> {
+            match self {
+                Node::Proposer(p) => p.step(msg),
+                Node::Matchmaker(m) => m.step(msg),
+                Node::Acceptor(a) => a.step(msg),
+            }
+        }
+
+        fn tick(&mut self) -> Result<()> {
+            match self {
+                Node::Proposer(p) => p.tick(),
+                _ => Ok(()),
+            }
+        }
+
+        fn take_messages(&mut self) -> Vec<Message> {
+            match self {
+                Node::Proposer(p) => p.take_messages(),
+                Node::Matchmaker(m) => m.take_messages(),
+                Node::Acceptor(a) => a.take_messages(),
+            }
+        }
+    }
+
+    /// Creates a network of two proposers, all matchmakers and acceptors with given ids.
+    fn network(acceptors: &[PeerId]) -> Network<Node> {
+        let proposers = [P, Q].iter()
+            .map(|&id| (id, Node::Proposer(Box::new(Proposer::new(Config::new(id, MATCHMAKERS.to_vec())).unwrap()))));
+        let matchmakers = MATCHMAKERS.iter().map(|&id| (id, Node::Matchmaker(Matchmaker::new(id, MemStorage::default()).unwrap())));
+        let acceptors = acceptors.iter().map(|&id| (id, Node::Acceptor(Acceptor::new(id, MemStorage::default()).unwrap())));
+        Network::new(proposers.chain(matchmakers).chain(acceptors))
+    }
+
+    fn proposer(net: &mut Network<Node>, id: PeerId) -> &mut Proposer {
+        match net.node_mut(id) {
+            Node::Proposer(p) => p,
+            _ => panic!("node {} is not a proposer", id),
+        }
+    }
+
+    fn matchmaker(net: &Network<Node>, id: PeerId) -> &Matchmaker<MemStorage> {
+        match net.node(id) {
+            Node::Matchmaker(m) => m,
+            _ => panic!("node {} is not a matchmaker", id),
+        }
+    }
+
+    fn acceptor(net: &Network<Node>, id: PeerId) -> &Acceptor<MemStorage> {
+        match net.node(id) {
+            Node::Acceptor(a) => a,
+            _ => panic!("node {} is not an acceptor", id),
+        }
+    }
+
+    fn voted_slots(net: &Network<Node>, id: PeerId) -> Vec<Slot> {
+        acceptor(net, id).votes().unwrap().keys().cloned().collect()
+    }
+
+    /// Replaces an acceptor with a new one, which recovers its state from the old one's storage.
+    fn restart_acceptor(net: &mut Network<Node>, id: PeerId) {
+        let storage = match net.node(id) {
+            Node::Acceptor(a) => a.storage().clone(),
+            _ => panic!("node {} is not an acceptor", id),
+        };
+        *net.node_mut(id) = Node::Acceptor(Acceptor::new(id, storage).unwrap());
+    }
+
+    fn committed(net: &mut Network<Node>, id: PeerId) -> Vec<Value> {
+        proposer(net, id).take_committed().into_iter().map(|(_, value)| value).collect()
+    }
+
+    fn command(c: u8) -> Value {
+        Value::Command(vec![c])
+    }
+
+    #[test]
+    fn matchmaker_replicate_log() {
+        let mut net = network(&[21, 22, 23]);
+        assert!(proposer(&mut net, P).propose(vec![0]).is_err());
+        proposer(&mut net, P).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        proposer(&mut net, P).propose(vec![1]).unwrap();
+        net.deliver();
+        assert!(proposer(&mut net, P).is_stable());
+
+        net.isolate(23);
+        proposer(&mut net, P).propose(vec![2]).unwrap();
+        proposer(&mut net, P).propose(vec![3]).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, P), vec![command(1), command(2), command(3)]);
+    }
+
+    #[test]
+    fn matchmaker_reconfigure_without_stopping() {
+        let mut net = network(&[21, 22, 23, 24, 25, 26]);
+        proposer(&mut net, P).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        proposer(&mut net, P).propose(vec![1]).unwrap();
+        net.deliver();
+        let old = proposer(&mut net, P).round().unwrap();
+
+        // commands proposed during reconfiguration are chosen by the old configuration
+        proposer(&mut net, P).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        proposer(&mut net, P).propose(vec![2]).unwrap();
+        assert_eq!(proposer(&mut net, P).round(), Some(old));
+        net.deliver();
+        let new = proposer(&mut net, P).round().unwrap();
+        assert!(new > old);
+        assert!(proposer(&mut net, P).is_stable());
+        for &id in MATCHMAKERS.iter() {
+            assert_eq!(matchmaker(&net, id).configs().keys().cloned().collect::<Vec<_>>(), vec![new]);
+        }
+
+        // old acceptors can be shut down
+        for id in 21..=23 {
+            net.isolate(id);
+        }
+        proposer(&mut net, P).propose(vec![3]).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, P), vec![command(1), command(2), command(3)]);
+
+        // another proposer learns the whole log from the new configuration, once it retries
+        // with a round higher than the ones it was unaware of
+        proposer(&mut net, Q).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        net.deliver();
+        assert!(!proposer(&mut net, Q).is_leader());
+        proposer(&mut net, Q).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, Q), vec![command(1), command(2), command(3)]);
+    }
+
+    #[test]
+    fn matchmaker_restarted_acceptors_keep_votes() {
+        let mut net = network(&[21, 22, 23]);
+        proposer(&mut net, P).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        net.deliver();
+        net.isolate(23);
+        proposer(&mut net, P).propose(vec![1]).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, P), vec![command(1)]);
+
+        // a value chosen by acceptors, which have restarted since, is recovered by a new leader
+        restart_acceptor(&mut net, 21);
+        restart_acceptor(&mut net, 22);
+        net.isolate(P);
+        proposer(&mut net, Q).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, Q), vec![command(1)]);
+    }
+
+    #[test]
+    fn matchmaker_compaction() {
+        let mut net = network(&[21, 22, 23, 24, 25, 26]);
+        proposer(&mut net, P).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        for c in 1..=3 {
+            proposer(&mut net, P).propose(vec![c]).unwrap();
+        }
+        net.deliver();
+        assert_eq!(committed(&mut net, P), vec![command(1), command(2), command(3)]);
+        assert!(proposer(&mut net, P).compact(4).is_err()); // not committed yet
+
+        // acceptors discard compacted votes together with the next proposal
+        proposer(&mut net, P).compact(2).unwrap();
+        proposer(&mut net, P).propose(vec![4]).unwrap();
+        net.deliver();
+        assert_eq!(voted_slots(&net, 21), vec![2, 3]);
+
+        // a new configuration receives only the part of a log, which has not been compacted
+        proposer(&mut net, P).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        net.deliver();
+        assert!(proposer(&mut net, P).is_stable());
+        assert_eq!(voted_slots(&net, 24), vec![2, 3]);
+
+        // another proposer takes over starting from the first slot, which has not been compacted
+        net.isolate(P);
+        proposer(&mut net, Q).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        net.deliver();
+        proposer(&mut net, Q).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        proposer(&mut net, Q).propose(vec![5]).unwrap();
+        net.deliver();
+        let log = proposer(&mut net, Q).take_committed();
+        assert_eq!(log, vec![(2, command(3)), (3, command(4)), (4, command(5))]);
+    }
+
+    #[test]
+    fn matchmaker_takeover_recovers_values() {
+        let mut net = network(&[21, 22, 23, 24, 25, 26]);
+        proposer(&mut net, P).start(Configuration::new(vec![21, 22, 23])).unwrap();
+        net.deliver();
+        proposer(&mut net, P).propose(vec![1]).unwrap();
+        net.deliver();
+        // a value accepted only by a minority is not chosen yet
+        net.isolate(22);
+        net.isolate(23);
+        proposer(&mut net, P).propose(vec![2]).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, P), vec![command(1)]);
+
+        net.heal();
+        net.isolate(P);
+        net.isolate(23);
+        proposer(&mut net, Q).start(Configuration::new(vec![24, 25, 26])).unwrap();
+        proposer(&mut net, Q).propose(vec![3]).unwrap();
+        net.deliver();
+        assert_eq!(committed(&mut net, Q), vec![command(1), command(2), command(3)]);
+        assert!(proposer(&mut net, Q).is_stable());
+
+        // the old leader is preempted
+        net.heal();
+        proposer(&mut net, P).propose(vec![4]).unwrap();
+        net.deliver();
+        assert!(!proposer(&mut net, P).is_leader());
+        assert!(proposer(&mut net, P).start(Configuration::default()).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use crate::Result;
+use crate::paxos::matchmaker::{Round, Slot, Value, Configuration};
+
+/// Durable state of a Matchmaker Paxos `Acceptor`: its promise and votes. All changes must be
+/// durable once a method modifying the storage returns, as an acceptor replies right after.
+pub trait AcceptorStorage {
+    /// Returns the highest round this acceptor has participated in.
+    fn promised(&self) -> Result<Round>;
+
+    fn set_promised(&mut self, round: Round) -> Result<()>;
+
+    /// Returns the latest votes cast in every slot.
+    fn votes(&self) -> Result<BTreeMap<Slot, (Round, Value)>>;
+
+    /// Records a vote for a value in a given slot, replacing the previous one.
+    fn vote(&mut self, slot: Slot, round: Round, value: &Value) -> Result<()>;
+
+    /// Returns a slot, below which votes have been discarded.
+    fn compacted(&self) -> Result<Slot>;
+
+    /// Discards votes cast in all slots lower than a given one.
+    fn compact(&mut self, slot: Slot) -> Result<()>;
+}
+
+/// Durable state of a `Matchmaker`: configurations of all rounds, which have not been garbage
+/// collected yet. All changes must be durable once a method modifying the storage returns.
+pub trait MatchmakerStorage {
+    /// Returns stored configurations together with a round, below which configurations have been
+    /// garbage collected.
+    fn configs(&self) -> Result<(Round, BTreeMap<Round, Configuration>)>;
+
+    fn store_config(&mut self, round: Round, config: &Configuration) -> Result<()>;
+
+    /// Removes configurations of all rounds lower than a given one.
+    fn collect(&mut self, round: Round) -> Result<()>;
+}
+
+/// In-memory storage of both acceptors and matchmakers, used mostly for testing.
+#[derive(Debug, Clone, Default)]
+pub struct MemStorage {
+    promised: Round,
+    votes: BTreeMap<Slot, (Round, Value)>,
+    compacted: Slot,
+    gc: Round,
+    configs: BTreeMap<Round, Configuration>,
+}
+
+impl AcceptorStorage for MemStorage {
+    fn promised(&self) -> Result<Round> {
+        Ok(self.promised)
+    }
+
+    fn set_promised(&mut self, round: Round) -> Result<()> {
+        self.promised = round;
+        Ok(())
+    }
+
+    fn votes(&self) -> Result<BTreeMap<Slot, (Round, Value)>> {
+        Ok(self.votes.clone())
+    }
+
+    fn vote(&mut self, slot: Slot, round: Round, value: &Value) -> Result<()> {
+        self.votes.insert(slot, (round, value.clone()));
+        Ok(())
+    }
+
+    fn compacted(&self) -> Result<Slot> {
+        Ok(self.compacted)
+    }
+
+    fn compact(&mut self, slot: Slot) -> Result<()> {
+        self.compacted = self.compacted.max(slot);
+        self.votes = self.votes.split_off(&slot);
+        Ok(())
+    }
+}
+
+impl MatchmakerStorage for MemStorage {
+    fn configs(&self) -> Result<(Round, BTreeMap<Round, Configuration>)> {
+        Ok((self.gc, self.configs.clone()))
+    }
+
+    fn store_config(&mut self, round: Round, config: &Configuration) -> Result<()> {
+        self.configs.insert(round, config.clone());
+        Ok(())
+    }
+
+    fn collect(&mut self, round: Round) -> Result<()> {
+        self.gc = self.gc.max(round);
+        self.configs = self.configs.split_off(&round);
+        Ok(())
+    }
+}
+
+const PROMISED_KEY: &[u8] = b"promised";
+const GC_KEY: &[u8] = b"gc";
+const COMPACTED_KEY: &[u8] = b"compacted";
+
+/// Persistent storage of both acceptors and matchmakers backed by sled. Votes are keyed by their
+/// slots and configurations by their rounds, while a promised round, a compaction watermark and
+/// a garbage collection watermark are stored in a metadata tree. Every modification is flushed to disk before
+/// returning.
+#[derive(Debug)]
+pub struct SledStorage {
+    votes: sled::Tree,
+    configs: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl SledStorage {
+
+    /// Opens a Matchmaker Paxos storage inside of a given database, recovering its state if it
+    /// was already created before.
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let votes = db.open_tree("matchmaker_paxos_votes")?;
+        let configs = db.open_tree("matchmaker_paxos_configs")?;
+        let meta = db.open_tree("matchmaker_paxos_meta")?;
+        Ok(SledStorage { votes, configs, meta })
+    }
+
+    fn round(&self, key: &[u8]) -> Result<Round> {
+        match self.meta.get(key)? {
+            None => Ok(Round::default()),
+            Some(bytes) => Ok(serde_cbor::from_slice(bytes.as_ref())?),
+        }
+    }
+
+    fn set_round(&mut self, key: &[u8], round: Round) -> Result<()> {
+        self.meta.insert(key, serde_cbor::to_vec(&round)?)?;
+        self.meta.flush()?;
+        Ok(())
+    }
+}
+
+impl AcceptorStorage for SledStorage {
+    fn promised(&self) -> Result<Round> {
+        self.round(PROMISED_KEY)
+    }
+
+    fn set_promised(&mut self, round: Round) -> Result<()> {
+        self.set_round(PROMISED_KEY, round)
+    }
+
+    fn votes(&self) -> Result<BTreeMap<Slot, (Round, Value)>> {
+        let mut result = BTreeMap::new();
+        for entry in self.votes.iter() {
+            let (key, bytes) = entry?;
+            result.insert(decode_slot(key.as_ref())?, serde_cbor::from_slice(bytes.as_ref())?);
+        }
+        Ok(result)
+    }
+
+    fn vote(&mut self, slot: Slot, round: Round, value: &Value) -> Result<()> {
+        self.votes.insert(slot.to_be_bytes(), serde_cbor::to_vec(&(round, value))?)?;
+        self.votes.flush()?;
+        Ok(())
+    }
+
+    fn compacted(&self) -> Result<Slot> {
+        match self.meta.get(COMPACTED_KEY)? {
+            None => Ok(0),
+            Some(bytes) => decode_slot(bytes.as_ref()),
+        }
+    }
+
+    fn compact(&mut self, slot: Slot) -> Result<()> {
+        // a watermark goes first, so that a crash in between leaves only unneeded votes
+        if slot > self.compacted()? {
+            self.meta.insert(COMPACTED_KEY, &slot.to_be_bytes())?;
+            self.meta.flush()?;
+        }
+        for key in self.votes.range(..slot.to_be_bytes()).keys() {
+            self.votes.remove(key?)?;
+        }
+        self.votes.flush()?;
+        Ok(())
+    }
+}
+
+impl MatchmakerStorage for SledStorage {
+    fn configs(&self) -> Result<(Round, BTreeMap<Round, Configuration>)> {
+        let mut configs = BTreeMap::new();
+        for entry in self.configs.iter() {
+            let (_, bytes) = entry?;
+            let (round, config) = serde_cbor::from_slice(bytes.as_ref())?;
+            configs.insert(round, config);
+        }
+        Ok((self.round(GC_KEY)?, configs))
+    }
+
+    fn store_config(&mut self, round: Round, config: &Configuration) -> Result<()> {
+        self.configs.insert(encode_round(round), serde_cbor::to_vec(&(round, config))?)?;
+        self.configs.flush()?;
+        Ok(())
+    }
+
+    fn collect(&mut self, round: Round) -> Result<()> {
+        // a watermark goes first, so that a crash in between leaves only unneeded configurations
+        if round > self.round(GC_KEY)? {
+            self.set_round(GC_KEY, round)?;
+        }
+        for key in self.configs.range(..encode_round(round)).keys() {
+            self.configs.remove(key?)?;
+        }
+        self.configs.flush()?;
+        Ok(())
+    }
+}
+
+/// Encodes a round as a key, which preserves the order of rounds.
+fn encode_round(round: Round) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&round.number.to_be_bytes());
+    key[8..].copy_from_slice(&round.leader.to_be_bytes());
+    key
+}
+
+fn decode_slot(key: &[u8]) -> Result<Slot> {
+    let bytes: [u8; 8] = key.try_into()
+        .map_err(|_| anyhow::anyhow!("SledStorage: invalid slot key {:?}", key))?;
+    Ok(Slot::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::matchmaker::{Round, Value, Configuration};
+    use crate::paxos::matchmaker::storage::{SledStorage, AcceptorStorage, MatchmakerStorage};
+
+    #[test]
+    fn sled_storage_recover() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (r1, r2, r3) = (Round::new(1, 1), Round::new(2, 1), Round::new(2, 2));
+        let (c1, c2) = (Configuration::new(vec![1, 2, 3]), Configuration::new(vec![4, 5, 6]));
+        {
+            let mut s = SledStorage::open(&db).unwrap();
+            s.set_promised(r2).unwrap();
+            s.vote(0, r1, &Value::Command(vec![1])).unwrap();
+            s.vote(1, r1, &Value::Command(vec![2])).unwrap();
+            s.vote(1, r2, &Value::Noop).unwrap();
+            for &(round, config) in &[(r1, &c1), (r2, &c1), (r3, &c2)] {
+                s.store_config(round, config).unwrap();
+            }
+            s.collect(r2).unwrap();
+            s.vote(2, r2, &Value::Noop).unwrap();
+            s.compact(1).unwrap();
+        }
+        let s = SledStorage::open(&db).unwrap();
+        assert_eq!(s.promised().unwrap(), r2);
+        assert_eq!(s.compacted().unwrap(), 1);
+        assert_eq!(s.votes().unwrap().into_iter().collect::<Vec<_>>(),
+                   vec![(1, (r2, Value::Noop)), (2, (r2, Value::Noop))]);
+        assert_eq!(s.configs().unwrap(), (r2, vec![(r2, c1), (r3, c2)].into_iter().collect()));
+    }
+}
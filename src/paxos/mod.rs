@@ -1,2 +1,13 @@
-mod cas;
-mod matchmaker;
\ No newline at end of file
+//! Protocols of the Paxos family. Like Raft `Node`, none of them performs any IO on its own:
+//! time is advanced with `tick`, incoming messages are passed to `step` and outgoing ones are
+//! obtained with `take_messages`.
+
+pub mod cas;
+pub mod epaxos;
+pub mod matchmaker;
+pub mod multi;
+pub mod quorum;
+#[cfg(test)]
+mod sim;
+
+pub type PeerId = u64;
@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::paxos::PeerId;
+use crate::paxos::multi::{Ballot, Slot, Value, Entry};
+
+/// A message exchanged between Multi-Paxos nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload {
+    /// Phase 1 request of a given ballot, covering all slots starting from `from`. A single
+    /// successful phase 1 lets a leader skip it for every slot it will ever propose in.
+    Prepare { ballot: Ballot, from: Slot },
+    /// Acceptor's promise together with the latest votes it has cast in requested slots. Votes
+    /// are discarded once their slots are known to be chosen, so chosen entries from requested
+    /// slots are sent instead.
+    Promise { ballot: Ballot, votes: BTreeMap<Slot, (Ballot, Value)>, chosen: Vec<Entry> },
+    /// Asks an acceptor to vote for a value in a given slot.
+    Accept { ballot: Ballot, slot: Slot, value: Value },
+    Accepted { ballot: Ballot, slot: Slot },
+    /// Informs learners about values chosen in given slots.
+    Chosen { entries: Vec<Entry> },
+    /// Sent periodically by a leader to maintain its leadership.
+    Heartbeat { ballot: Ballot },
+    /// Lets a leader know about the first slot, which a sender doesn't know to be chosen yet,
+    /// so that it can send missing entries.
+    HeartbeatResponse { ballot: Ballot, commit: Slot },
+    /// Request of a given ballot has been rejected, because a recipient has already promised
+    /// a higher one.
+    Nack { ballot: Ballot, promised: Ballot },
+}
@@ -0,0 +1,112 @@
+pub mod message;
+pub mod storage;
+pub mod node;
+pub mod stream;
+
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::quorum::{Quorum, QuorumSystem};
+
+/// Position of a command in a replicated log. Slots start from 0.
+pub type Slot = u64;
+
+/// Ballot of Multi-Paxos. Ballots are totally ordered by their round first and an identifier
+/// of a node leading them, so that every ballot has a single leader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub leader: PeerId,
+}
+
+impl Ballot {
+    pub fn new(round: u64, leader: PeerId) -> Self {
+        Ballot { round, leader }
+    }
+}
+
+/// A value proposed for a single slot of a log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Value {
+    /// Fills a slot, for which no command was chosen.
+    Noop,
+    Command(Vec<u8>),
+}
+
+/// A single chosen entry of a replicated log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub slot: Slot,
+    pub value: Value,
+}
+
+impl Entry {
+    pub fn new(slot: Slot, value: Value) -> Self {
+        Entry { slot, value }
+    }
+}
+
+const DEFAULT_ELECTION_TIMEOUT: u64 = 10;
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 2;
+const DEFAULT_MAX_CATCH_UP_ENTRIES: u64 = 64;
+
+/// Configuration of a Multi-Paxos `Node`. All timeouts are expressed in number of ticks.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Identifier of a current node.
+    pub id: PeerId,
+    /// Identifiers of all nodes, including a current one. Every node is a proposer, an acceptor
+    /// and a learner at the same time.
+    pub peers: Vec<PeerId>,
+    /// Quorum system of acceptors, majority by default.
+    pub quorum: Quorum,
+    /// Minimal number of ticks without hearing from a leader, after which a node tries to become
+    /// a leader itself. Actual timeout is randomized within `[election_timeout, 2 * election_timeout)`
+    /// range.
+    pub election_timeout: u64,
+    /// Number of ticks between heartbeats sent by a leader, which are also used to resend
+    /// proposals not yet accepted by all acceptors.
+    pub heartbeat_interval: u64,
+    /// Maximum number of chosen entries send to a lagging node within a single message.
+    pub max_catch_up_entries: u64,
+}
+
+impl Config {
+    pub fn new(id: PeerId, peers: Vec<PeerId>) -> Self {
+        Config {
+            id,
+            peers,
+            quorum: Quorum::Majority,
+            election_timeout: DEFAULT_ELECTION_TIMEOUT,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            max_catch_up_entries: DEFAULT_MAX_CATCH_UP_ENTRIES,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.peers.contains(&self.id) {
+            Err(anyhow::anyhow!("Multi-Paxos node {} is not one of the peers {:?}", self.id, self.peers))
+        } else if self.heartbeat_interval == 0 || self.heartbeat_interval >= self.election_timeout {
+            Err(anyhow::anyhow!("Multi-Paxos heartbeat interval ({}) must be positive and lower than election timeout ({})", self.heartbeat_interval, self.election_timeout))
+        } else if self.max_catch_up_entries == 0 {
+            Err(anyhow::anyhow!("Multi-Paxos max catch up entries must be positive"))
+        } else {
+            self.quorum.validate(&self.acceptors())
+        }
+    }
+
+    /// Checks if given acceptors form a quorum of promises, which is enough to become a leader.
+    pub fn is_phase1_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase1_quorum(&self.acceptors(), nodes)
+    }
+
+    /// Checks if given acceptors form a quorum, which is enough for a value to be chosen.
+    pub fn is_phase2_quorum(&self, nodes: &BTreeSet<PeerId>) -> bool {
+        self.quorum.is_phase2_quorum(&self.acceptors(), nodes)
+    }
+
+    fn acceptors(&self) -> BTreeSet<PeerId> {
+        self.peers.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,584 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::Result;
+use crate::paxos::PeerId;
+use crate::paxos::multi::{Config, Ballot, Slot, Value, Entry};
+use crate::paxos::multi::message::{Message, Payload};
+use crate::paxos::multi::storage::Storage;
+
+type Votes = BTreeMap<Slot, (Ballot, Value)>;
+
+/// A value proposed by a leader, which has not been chosen yet.
+struct Proposal {
+    value: Value,
+    accepted: BTreeSet<PeerId>,
+}
+
+enum Role {
+    Follower,
+    /// Running phase 1, collecting promises together with votes of each acceptor.
+    Candidate(BTreeMap<PeerId, Votes>),
+    Leader {
+        proposals: BTreeMap<Slot, Proposal>,
+        /// The next slot, which a new command will be proposed in.
+        next_slot: Slot,
+    },
+}
+
+/// A single member of a Multi-Paxos cluster, acting as a proposer, an acceptor and a learner.
+///
+/// A node becomes a leader by running phase 1 with a new ballot over all slots, which it doesn't
+/// know to be chosen yet. Values which might have been chosen in any of these slots are proposed
+/// again, while gaps between them are filled with no-ops, so that a log can be executed in order.
+/// Afterwards a leader proposes new commands in consecutive slots running phase 2 only, as long as
+/// no other node starts a higher ballot. Once a value is accepted by a phase 2 quorum, it's
+/// broadcast to all learners, while lagging ones catch up by answering leader's heartbeats.
+///
+/// When a leader is preempted, it drops all proposals which have not been chosen yet.
+pub struct Node<S> {
+    config: Config,
+    storage: S,
+    /// The latest ballot this node has tried to lead.
+    ballot: Ballot,
+    /// The highest ballot promised by a local acceptor.
+    promised: Ballot,
+    role: Role,
+    leader: Option<PeerId>,
+    /// The first slot, which is not known to be chosen.
+    commit: Slot,
+    /// Chosen values which cannot be appended to a log yet, because preceding slots are unknown.
+    pending: BTreeMap<Slot, Value>,
+    election_elapsed: u64,
+    randomized_timeout: u64,
+    heartbeat_elapsed: u64,
+    rng: StdRng,
+    /// Messages addressed to a current node, handled once a current one is done.
+    local: VecDeque<Payload>,
+    outbox: Vec<Message>,
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Creates a new node, restoring the state of its acceptor and a chosen log from a given
+    /// storage. Every node starts as a follower.
+    pub fn new(config: Config, storage: S) -> Result<Self> {
+        config.validate()?;
+        let promised = storage.promised()?;
+        let commit = storage.commit()?;
+        let mut node = Node {
+            config,
+            storage,
+            ballot: Ballot::default(),
+            promised,
+            role: Role::Follower,
+            leader: None,
+            commit,
+            pending: BTreeMap::new(),
+            election_elapsed: 0,
+            randomized_timeout: 0,
+            heartbeat_elapsed: 0,
+            rng: StdRng::from_entropy(),
+            local: VecDeque::new(),
+            outbox: Vec::new(),
+        };
+        node.reset_election_timeout();
+        Ok(node)
+    }
+
+    pub fn id(&self) -> PeerId { self.config.id }
+
+    /// The latest ballot this node has tried to lead.
+    pub fn ballot(&self) -> Ballot { self.ballot }
+
+    /// The first slot, which is not known to be chosen. All slots before it are stored in a log.
+    pub fn commit(&self) -> Slot { self.commit }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    pub fn is_leader(&self) -> bool { matches!(self.role, Role::Leader { .. }) }
+
+    /// Returns an identifier of a current leader, as far as this node knows.
+    pub fn leader(&self) -> Option<PeerId> { self.leader }
+
+    /// Starts phase 1 with a ballot higher than any other this node has seen.
+    pub fn campaign(&mut self) -> Result<()> {
+        let round = self.promised.round.max(self.ballot.round) + 1;
+        self.ballot = Ballot::new(round, self.config.id);
+        self.role = Role::Candidate(BTreeMap::new());
+        self.leader = None;
+        self.reset_election_timeout();
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::Prepare { ballot: self.ballot, from: self.commit });
+        }
+        self.process_local()
+    }
+
+    /// Proposes a command to be appended to a log, returning a slot it will be chosen in, unless
+    /// a leader is preempted before that. Only a leader can propose new commands.
+    pub fn propose(&mut self, command: Vec<u8>) -> Result<Slot> {
+        let slot = match &mut self.role {
+            Role::Leader { next_slot, .. } => {
+                *next_slot += 1;
+                *next_slot - 1
+            },
+            _ => return Err(anyhow::anyhow!("Multi-Paxos node {} is not a leader (current leader: {:?})", self.config.id, self.leader)),
+        };
+        self.propose_at(slot, Value::Command(command));
+        self.process_local()?;
+        Ok(slot)
+    }
+
+    /// Advances logical clock of this node by a single tick.
+    pub fn tick(&mut self) -> Result<()> {
+        if self.is_leader() {
+            self.heartbeat_elapsed += 1;
+            if self.heartbeat_elapsed >= self.config.heartbeat_interval {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_heartbeat();
+            }
+        } else {
+            self.election_elapsed += 1;
+            if self.election_elapsed >= self.randomized_timeout {
+                return self.campaign();
+            }
+        }
+        self.process_local()
+    }
+
+    /// Handles a message received from another node.
+    pub fn step(&mut self, msg: Message) -> Result<()> {
+        self.handle(msg.from, msg.payload)?;
+        self.process_local()
+    }
+
+    /// Returns all messages produced by this node since the last call, which should be sent to
+    /// their recipients.
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn handle(&mut self, from: PeerId, payload: Payload) -> Result<()> {
+        match payload {
+            Payload::Prepare { ballot, from: slot } => {
+                if self.promise(from, ballot)? {
+                    let votes = self.storage.votes(slot)?;
+                    let chosen = if slot < self.commit { self.storage.entries(slot, self.commit)? } else { Vec::new() };
+                    self.send(from, Payload::Promise { ballot, votes, chosen });
+                }
+                Ok(())
+            },
+            Payload::Promise { ballot, votes, chosen } => self.handle_promise(from, ballot, votes, chosen),
+            Payload::Accept { ballot, slot, value } => {
+                if self.promise(from, ballot)? {
+                    // a value chosen in a slot is the only one, which can be proposed there
+                    // later on, so there's no need to keep a vote for it
+                    if slot >= self.commit {
+                        self.storage.vote(slot, ballot, &value)?;
+                    }
+                    self.send(from, Payload::Accepted { ballot, slot });
+                }
+                Ok(())
+            },
+            Payload::Accepted { ballot, slot } => self.handle_accepted(from, ballot, slot),
+            Payload::Chosen { entries } => {
+                for entry in entries {
+                    self.learn(entry.slot, entry.value)?;
+                }
+                Ok(())
+            },
+            Payload::Heartbeat { ballot } => {
+                if self.promise(from, ballot)? {
+                    self.send(from, Payload::HeartbeatResponse { ballot, commit: self.commit });
+                }
+                Ok(())
+            },
+            Payload::HeartbeatResponse { ballot, commit } => self.handle_heartbeat_response(from, ballot, commit),
+            Payload::Nack { ballot, promised } => self.handle_nack(ballot, promised),
+        }
+    }
+
+    /// Promises a given ballot, unless a higher one has been promised already. A node which
+    /// promises a ballot of another node stops leading its own one.
+    fn promise(&mut self, from: PeerId, ballot: Ballot) -> Result<bool> {
+        if ballot < self.promised {
+            self.send(from, Payload::Nack { ballot, promised: self.promised });
+            return Ok(false);
+        }
+        if ballot > self.promised {
+            self.promised = ballot;
+            self.storage.set_promised(ballot)?;
+        }
+        if ballot.leader != self.config.id {
+            if !matches!(self.role, Role::Follower) {
+                log::info!("Multi-Paxos node {} preempted in ballot {:?} by {:?}", self.config.id, self.ballot, ballot);
+                self.role = Role::Follower;
+            }
+            self.leader = Some(ballot.leader);
+            self.reset_election_timeout();
+        }
+        Ok(true)
+    }
+
+    fn handle_promise(&mut self, from: PeerId, ballot: Ballot, votes: Votes, chosen: Vec<Entry>) -> Result<()> {
+        if ballot != self.ballot {
+            return Ok(());
+        }
+        for entry in chosen {
+            self.learn(entry.slot, entry.value)?;
+        }
+        let promises = match &mut self.role {
+            Role::Candidate(promises) => promises,
+            _ => return Ok(()),
+        };
+        promises.insert(from, votes);
+        if self.config.is_phase1_quorum(&promises.keys().cloned().collect()) {
+            self.become_leader();
+        }
+        Ok(())
+    }
+
+    /// Starts phase 2 by proposing values which were or might have been chosen in previous
+    /// ballots, filling gaps between them with no-ops.
+    fn become_leader(&mut self) {
+        let promises = match std::mem::replace(&mut self.role, Role::Follower) {
+            Role::Candidate(promises) => promises,
+            _ => panic!("Defect: Node::become_leader - node is not a candidate"),
+        };
+        // in every slot, a value voted for in the highest ballot might have been chosen
+        let mut safe: BTreeMap<Slot, (Ballot, Value)> = BTreeMap::new();
+        for (&slot, (ballot, value)) in promises.values().flatten() {
+            let newer = safe.get(&slot).map(|(b, _)| ballot > b).unwrap_or(true);
+            if newer {
+                safe.insert(slot, (*ballot, value.clone()));
+            }
+        }
+        let end = safe.keys().next_back().map(|slot| slot + 1).unwrap_or(0).max(self.commit);
+        log::info!("Multi-Paxos node {} became a leader of ballot {:?}", self.config.id, self.ballot);
+        self.role = Role::Leader { proposals: BTreeMap::new(), next_slot: end };
+        self.leader = Some(self.config.id);
+        self.heartbeat_elapsed = 0;
+        for slot in self.commit..end {
+            let value = match self.pending.get(&slot) {
+                Some(value) => value.clone(),
+                None => safe.remove(&slot).map(|(_, value)| value).unwrap_or(Value::Noop),
+            };
+            self.propose_at(slot, value);
+        }
+    }
+
+    fn propose_at(&mut self, slot: Slot, value: Value) {
+        let ballot = self.ballot;
+        match &mut self.role {
+            Role::Leader { proposals, .. } => {
+                proposals.insert(slot, Proposal { value: value.clone(), accepted: BTreeSet::new() });
+            },
+            _ => return,
+        }
+        for to in self.config.peers.clone() {
+            self.send(to, Payload::Accept { ballot, slot, value: value.clone() });
+        }
+    }
+
+    fn handle_accepted(&mut self, from: PeerId, ballot: Ballot, slot: Slot) -> Result<()> {
+        if ballot != self.ballot {
+            return Ok(());
+        }
+        let proposals = match &mut self.role {
+            Role::Leader { proposals, .. } => proposals,
+            _ => return Ok(()),
+        };
+        let chosen = match proposals.get_mut(&slot) {
+            Some(proposal) => {
+                proposal.accepted.insert(from);
+                self.config.is_phase2_quorum(&proposal.accepted)
+            },
+            None => false,
+        };
+        if chosen {
+            let proposal = proposals.remove(&slot)
+                .expect("Defect: Node::handle_accepted - proposal has been found");
+            let entries = vec![Entry::new(slot, proposal.value)];
+            for to in self.config.peers.clone() {
+                self.send(to, Payload::Chosen { entries: entries.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a chosen value to a log, once all values preceding it are known.
+    fn learn(&mut self, slot: Slot, value: Value) -> Result<()> {
+        if slot < self.commit {
+            return Ok(());
+        }
+        self.pending.insert(slot, value);
+        while let Some(value) = self.pending.remove(&self.commit) {
+            self.storage.append(&Entry::new(self.commit, value))?;
+            self.commit += 1;
+        }
+        Ok(())
+    }
+
+    /// Sends heartbeats to all other nodes and resends proposals, which haven't been accepted
+    /// by all acceptors yet.
+    fn broadcast_heartbeat(&mut self) {
+        let mut messages = Vec::new();
+        if let Role::Leader { proposals, .. } = &self.role {
+            for (&slot, proposal) in proposals.iter() {
+                for &to in self.config.peers.iter().filter(|id| !proposal.accepted.contains(id)) {
+                    messages.push((to, Payload::Accept { ballot: self.ballot, slot, value: proposal.value.clone() }));
+                }
+            }
+        }
+        for &to in self.config.peers.iter().filter(|&&id| id != self.config.id) {
+            messages.push((to, Payload::Heartbeat { ballot: self.ballot }));
+        }
+        for (to, payload) in messages {
+            self.send(to, payload);
+        }
+    }
+
+    /// Sends chosen entries, which a follower is missing.
+    fn handle_heartbeat_response(&mut self, from: PeerId, ballot: Ballot, commit: Slot) -> Result<()> {
+        if ballot != self.ballot || !self.is_leader() || commit >= self.commit {
+            return Ok(());
+        }
+        let hi = self.commit.min(commit + self.config.max_catch_up_entries);
+        let entries = self.storage.entries(commit, hi)?;
+        self.send(from, Payload::Chosen { entries });
+        Ok(())
+    }
+
+    fn handle_nack(&mut self, ballot: Ballot, promised: Ballot) -> Result<()> {
+        if promised > self.promised {
+            // no lower ballot can be chosen anymore, so a local acceptor can reject them as well
+            self.promised = promised;
+            self.storage.set_promised(promised)?;
+        }
+        if ballot == self.ballot && !matches!(self.role, Role::Follower) {
+            log::info!("Multi-Paxos node {} preempted in ballot {:?} by {:?}", self.config.id, ballot, promised);
+            self.role = Role::Follower;
+            self.leader = None;
+            self.reset_election_timeout();
+        }
+        Ok(())
+    }
+
+    fn process_local(&mut self) -> Result<()> {
+        while let Some(payload) = self.local.pop_front() {
+            self.handle(self.config.id, payload)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, to: PeerId, payload: Payload) {
+        if to == self.config.id {
+            self.local.push_back(payload);
+        } else {
+            self.outbox.push(Message { from: self.config.id, to, payload });
+        }
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let timeout = self.config.election_timeout;
+        self.election_elapsed = 0;
+        self.randomized_timeout = self.rng.gen_range(timeout, 2 * timeout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Result;
+    use crate::paxos::PeerId;
+    use crate::paxos::multi::{Config, Value, Entry};
+    use crate::paxos::multi::message::{Message, Payload};
+    use crate::paxos::multi::node::Node;
+    use crate::paxos::multi::storage::{MemStorage, Storage};
+    use crate::paxos::quorum::Quorum;
+    use crate::paxos::sim::{Network, Process, Envelope};
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    impl Envelope for Message {
+        fn from(&self) -> PeerId { self.from }
+        fn to(&self) -> PeerId { self.to }
+    }
+
+    impl Process for Node<MemStorage> {
+        type Message = Message;
+
+        fn step(&mut self, msg: Message) -> Result<()> { Node::step(self, msg) }
+
+        fn tick(&mut self) -> Result<()> { Node::tick(self) }
+
+        fn take_messages(&mut self) -> Vec<Message> { Node::take_messages(self) }
+    }
+
+    fn cluster(ids: &[PeerId]) -> Network<Node<MemStorage>> {
+        Network::new(ids.iter().map(|&id| (id, Node::new(Config::new(id, ids.to_vec()), MemStorage::default()).unwrap())))
+    }
+
+    fn log(net: &Network<Node<MemStorage>>, id: PeerId) -> Vec<Value> {
+        let node = net.node(id);
+        node.storage().entries(0, node.commit()).unwrap().into_iter().map(|e| e.value).collect()
+    }
+
+    fn command(c: u8) -> Value {
+        Value::Command(vec![c])
+    }
+
+    #[test]
+    fn multi_paxos_single_node() {
+        let mut a = Node::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        assert!(a.propose(vec![1]).is_err());
+        a.campaign().unwrap();
+        assert!(a.is_leader());
+        assert_eq!(a.propose(vec![1]).unwrap(), 0);
+        assert_eq!(a.propose(vec![2]).unwrap(), 1);
+        assert_eq!(a.commit(), 2);
+        assert!(a.take_messages().is_empty());
+        assert!(Node::new(Config::new(A, vec![B]), MemStorage::default()).is_err());
+    }
+
+    #[test]
+    fn multi_paxos_leader_skips_phase1() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).campaign().unwrap();
+        net.deliver();
+        assert!(net.node(A).is_leader());
+        assert_eq!(net.node(B).leader(), Some(A));
+
+        for c in 1..=3 {
+            net.node_mut(A).propose(vec![c]).unwrap();
+        }
+        let messages = net.node_mut(A).take_messages();
+        assert!(messages.iter().all(|m| matches!(m.payload, Payload::Accept { .. })));
+        for msg in messages {
+            net.node_mut(msg.to).step(msg).unwrap();
+        }
+        net.deliver();
+        for &id in &[A, B, C] {
+            assert_eq!(log(&net, id), vec![command(1), command(2), command(3)]);
+        }
+    }
+
+    #[test]
+    fn multi_paxos_failover_fills_gaps() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).campaign().unwrap();
+        net.deliver();
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+
+        // the old leader has managed to send a value for slot 2, but not for slot 1
+        let ballot = net.node(A).ballot();
+        net.isolate(A);
+        let accept = Payload::Accept { ballot, slot: 2, value: command(3) };
+        net.node_mut(B).step(Message { from: A, to: B, payload: accept }).unwrap();
+        net.node_mut(B).campaign().unwrap();
+        net.deliver();
+        assert!(net.node(B).is_leader());
+        assert_eq!(net.node_mut(B).propose(vec![4]).unwrap(), 3);
+        net.deliver();
+        assert_eq!(log(&net, B), vec![command(1), Value::Noop, command(3), command(4)]);
+
+        // the old leader is preempted once it tries to propose again
+        net.heal();
+        net.node_mut(A).propose(vec![5]).unwrap();
+        net.deliver();
+        assert!(!net.node(A).is_leader());
+        assert_eq!(net.node(A).leader(), None);
+    }
+
+    #[test]
+    fn multi_paxos_follower_catch_up() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).campaign().unwrap();
+        net.deliver();
+        net.isolate(C);
+        for c in 1..=3 {
+            net.node_mut(A).propose(vec![c]).unwrap();
+        }
+        net.deliver();
+        assert_eq!(net.node(C).commit(), 0);
+
+        net.heal();
+        let interval = Config::new(A, vec![A]).heartbeat_interval;
+        for _ in 0..interval {
+            net.node_mut(A).tick().unwrap();
+        }
+        net.deliver();
+        assert_eq!(log(&net, C), vec![command(1), command(2), command(3)]);
+
+        // a restarted node recovers its promise and the chosen log
+        let storage = net.node(C).storage().clone();
+        *net.node_mut(C) = Node::new(Config::new(C, vec![A, B, C]), storage).unwrap();
+        net.isolate(A);
+        net.node_mut(C).campaign().unwrap();
+        net.deliver();
+        assert!(net.node(C).ballot() > net.node(A).ballot());
+        assert_eq!(net.node_mut(C).propose(vec![4]).unwrap(), 3);
+        net.deliver();
+        assert_eq!(net.node(B).storage().entries(3, 4).unwrap(), vec![Entry::new(3, command(4))]);
+    }
+
+    #[test]
+    fn multi_paxos_lagging_candidate() {
+        let mut net = cluster(&[A, B, C]);
+        net.node_mut(A).campaign().unwrap();
+        net.deliver();
+        net.isolate(C);
+        for c in 1..=3 {
+            net.node_mut(A).propose(vec![c]).unwrap();
+        }
+        net.deliver();
+        assert_eq!(net.node(B).commit(), 3);
+        assert!(net.node(B).storage().votes(0).unwrap().is_empty());
+
+        // votes for chosen slots are gone, so they are learned from chosen entries of promises
+        net.heal();
+        net.isolate(A);
+        net.node_mut(C).campaign().unwrap();
+        net.deliver();
+        assert!(net.node(C).is_leader());
+        assert_eq!(log(&net, C), vec![command(1), command(2), command(3)]);
+        assert_eq!(net.node_mut(C).propose(vec![4]).unwrap(), 3);
+        net.deliver();
+        assert_eq!(log(&net, B), vec![command(1), command(2), command(3), command(4)]);
+    }
+
+    #[test]
+    fn multi_paxos_flexible_quorums() {
+        let ids = [A, B, C, 4, 5];
+        let quorum = Quorum::Flexible { phase1: 4, phase2: 2 };
+        let mut net = Network::new(ids.iter().map(|&id| {
+            let config = Config { quorum: quorum.clone(), ..Config::new(id, ids.to_vec()) };
+            (id, Node::new(config, MemStorage::default()).unwrap())
+        }));
+        net.node_mut(A).campaign().unwrap();
+        net.deliver();
+
+        // values are chosen by just two acceptors
+        for &id in &[C, 4, 5] {
+            net.isolate(id);
+        }
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+        assert_eq!(log(&net, B), vec![command(1)]);
+
+        // but a new leader needs promises from four of them
+        net.node_mut(B).campaign().unwrap();
+        net.deliver();
+        assert!(!net.node(B).is_leader());
+        net.heal();
+        net.isolate(A);
+        net.node_mut(B).campaign().unwrap();
+        net.deliver();
+        assert!(net.node(B).is_leader());
+
+        let invalid = Config { quorum: Quorum::Flexible { phase1: 3, phase2: 2 }, ..Config::new(A, ids.to_vec()) };
+        assert!(Node::new(invalid, MemStorage::default()).is_err());
+    }
+}
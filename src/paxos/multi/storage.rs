@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use crate::Result;
+use crate::paxos::multi::{Ballot, Slot, Value, Entry};
+
+/// Durable storage used by a Multi-Paxos `Node`. It keeps the state of a local acceptor - its
+/// promise and votes - together with a prefix of a log, which is known to be chosen. Votes are
+/// kept only for slots, which are not known to be chosen yet. All changes must be durable once
+/// a method modifying the storage returns.
+pub trait Storage {
+    /// Returns the highest ballot this acceptor has promised.
+    fn promised(&self) -> Result<Ballot>;
+
+    fn set_promised(&mut self, ballot: Ballot) -> Result<()>;
+
+    /// Returns the latest votes cast in all slots starting from a given one.
+    fn votes(&self, from: Slot) -> Result<BTreeMap<Slot, (Ballot, Value)>>;
+
+    /// Records a vote for a value in a given slot, replacing the previous one.
+    fn vote(&mut self, slot: Slot, ballot: Ballot, value: &Value) -> Result<()>;
+
+    /// Appends an entry chosen in the next slot of a log, discarding a vote cast in that slot.
+    fn append(&mut self, entry: &Entry) -> Result<()>;
+
+    /// Returns chosen entries within `[lo, hi)` range of slots.
+    fn entries(&self, lo: Slot, hi: Slot) -> Result<Vec<Entry>>;
+
+    /// Returns the first slot, which is not known to be chosen.
+    fn commit(&self) -> Result<Slot>;
+}
+
+/// In-memory `Storage` implementation, used mostly for testing.
+#[derive(Debug, Clone, Default)]
+pub struct MemStorage {
+    promised: Ballot,
+    votes: BTreeMap<Slot, (Ballot, Value)>,
+    entries: Vec<Entry>,
+}
+
+impl Storage for MemStorage {
+    fn promised(&self) -> Result<Ballot> {
+        Ok(self.promised)
+    }
+
+    fn set_promised(&mut self, ballot: Ballot) -> Result<()> {
+        self.promised = ballot;
+        Ok(())
+    }
+
+    fn votes(&self, from: Slot) -> Result<BTreeMap<Slot, (Ballot, Value)>> {
+        Ok(self.votes.range(from..).map(|(&slot, vote)| (slot, vote.clone())).collect())
+    }
+
+    fn vote(&mut self, slot: Slot, ballot: Ballot, value: &Value) -> Result<()> {
+        self.votes.insert(slot, (ballot, value.clone()));
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        let expected = self.entries.len() as Slot;
+        if entry.slot != expected {
+            return Err(anyhow::anyhow!("MemStorage: expected entry at slot {} but got {}", expected, entry.slot));
+        }
+        self.entries.push(entry.clone());
+        self.votes.remove(&entry.slot);
+        Ok(())
+    }
+
+    fn entries(&self, lo: Slot, hi: Slot) -> Result<Vec<Entry>> {
+        if lo > hi || hi > self.entries.len() as Slot {
+            return Err(anyhow::anyhow!("MemStorage: entries range [{}, {}) is out of bounds", lo, hi));
+        }
+        Ok(self.entries[lo as usize..hi as usize].to_vec())
+    }
+
+    fn commit(&self) -> Result<Slot> {
+        Ok(self.entries.len() as Slot)
+    }
+}
+
+const PROMISED_KEY: &[u8] = b"promised";
+
+/// Persistent Multi-Paxos `Storage` backed by sled. Votes and chosen entries are kept in separate
+/// trees keyed by their slots, while a promised ballot is stored in a metadata tree. Every
+/// modification is flushed to disk before returning.
+#[derive(Debug)]
+pub struct SledStorage {
+    log: sled::Tree,
+    votes: sled::Tree,
+    meta: sled::Tree,
+    commit: Slot,
+}
+
+impl SledStorage {
+
+    /// Opens a Multi-Paxos storage inside of a given database, recovering its state if it was
+    /// already created before.
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let log = db.open_tree("multi_paxos_log")?;
+        let votes = db.open_tree("multi_paxos_votes")?;
+        let meta = db.open_tree("multi_paxos_meta")?;
+        let commit = match log.last()? {
+            None => 0,
+            Some((key, _)) => decode_slot(key.as_ref())? + 1,
+        };
+        Ok(SledStorage { log, votes, meta, commit })
+    }
+}
+
+impl Storage for SledStorage {
+    fn promised(&self) -> Result<Ballot> {
+        match self.meta.get(PROMISED_KEY)? {
+            None => Ok(Ballot::default()),
+            Some(bytes) => Ok(serde_cbor::from_slice(bytes.as_ref())?),
+        }
+    }
+
+    fn set_promised(&mut self, ballot: Ballot) -> Result<()> {
+        self.meta.insert(PROMISED_KEY, serde_cbor::to_vec(&ballot)?)?;
+        self.meta.flush()?;
+        Ok(())
+    }
+
+    fn votes(&self, from: Slot) -> Result<BTreeMap<Slot, (Ballot, Value)>> {
+        let mut result = BTreeMap::new();
+        for entry in self.votes.range(from.to_be_bytes()..) {
+            let (key, bytes) = entry?;
+            result.insert(decode_slot(key.as_ref())?, serde_cbor::from_slice(bytes.as_ref())?);
+        }
+        Ok(result)
+    }
+
+    fn vote(&mut self, slot: Slot, ballot: Ballot, value: &Value) -> Result<()> {
+        self.votes.insert(slot.to_be_bytes(), serde_cbor::to_vec(&(ballot, value))?)?;
+        self.votes.flush()?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        if entry.slot != self.commit {
+            return Err(anyhow::anyhow!("SledStorage: expected entry at slot {} but got {}", self.commit, entry.slot));
+        }
+        self.log.insert(entry.slot.to_be_bytes(), serde_cbor::to_vec(entry)?)?;
+        self.log.flush()?;
+        self.commit += 1;
+        // a vote is removed only after an entry is durable, so a crash in between just keeps it
+        self.votes.remove(entry.slot.to_be_bytes())?;
+        self.votes.flush()?;
+        Ok(())
+    }
+
+    fn entries(&self, lo: Slot, hi: Slot) -> Result<Vec<Entry>> {
+        if lo > hi || hi > self.commit {
+            return Err(anyhow::anyhow!("SledStorage: entries range [{}, {}) is out of bounds", lo, hi));
+        }
+        let mut result = Vec::with_capacity((hi - lo) as usize);
+        for entry in self.log.range(lo.to_be_bytes()..hi.to_be_bytes()) {
+            let (_, bytes) = entry?;
+            result.push(serde_cbor::from_slice(bytes.as_ref())?);
+        }
+        Ok(result)
+    }
+
+    fn commit(&self) -> Result<Slot> {
+        Ok(self.commit)
+    }
+}
+
+fn decode_slot(key: &[u8]) -> Result<Slot> {
+    let bytes: [u8; 8] = key.try_into()
+        .map_err(|_| anyhow::anyhow!("SledStorage: invalid slot key {:?}", key))?;
+    Ok(Slot::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::paxos::multi::{Ballot, Value, Entry};
+    use crate::paxos::multi::storage::{MemStorage, SledStorage, Storage};
+
+    #[test]
+    fn mem_storage_append() {
+        let mut s = MemStorage::default();
+        s.vote(0, Ballot::new(1, 1), &Value::Noop).unwrap();
+        s.vote(1, Ballot::new(1, 1), &Value::Command(vec![1])).unwrap();
+        s.append(&Entry::new(0, Value::Noop)).unwrap();
+        assert_eq!(s.votes(0).unwrap().keys().cloned().collect::<Vec<_>>(), vec![1]); // vote pruned
+        assert!(s.append(&Entry::new(2, Value::Noop)).is_err()); // gap in the log
+        s.append(&Entry::new(1, Value::Command(vec![1]))).unwrap();
+        assert_eq!(s.commit().unwrap(), 2);
+        assert_eq!(s.entries(1, 2).unwrap(), vec![Entry::new(1, Value::Command(vec![1]))]);
+        assert!(s.entries(1, 3).is_err());
+    }
+
+    #[test]
+    fn sled_storage_recover() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (b1, b2) = (Ballot::new(1, 1), Ballot::new(2, 1));
+        {
+            let mut s = SledStorage::open(&db).unwrap();
+            s.set_promised(b2).unwrap();
+            s.vote(0, b1, &Value::Command(vec![1])).unwrap();
+            s.vote(1, b1, &Value::Command(vec![2])).unwrap();
+            s.vote(1, b2, &Value::Noop).unwrap();
+            s.append(&Entry::new(0, Value::Command(vec![1]))).unwrap();
+        }
+        let s = SledStorage::open(&db).unwrap();
+        assert_eq!(s.promised().unwrap(), b2);
+        assert_eq!(s.commit().unwrap(), 1);
+        assert_eq!(s.entries(0, 1).unwrap(), vec![Entry::new(0, Value::Command(vec![1]))]);
+        // vote of an appended slot has been pruned
+        assert_eq!(s.votes(0).unwrap().into_iter().collect::<Vec<_>>(), vec![(1, (b2, Value::Noop))]);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Result;
+use crate::paxos::multi::{Slot, Entry};
+use crate::paxos::multi::node::Node;
+use crate::paxos::multi::storage::Storage;
+use crate::stream::CommittedLog;
+
+/// A stream of chosen log entries, starting from a requested slot. All entries are emitted in
+/// the log order without gaps, including no-ops used to fill slots abandoned by previous leaders.
+pub type CommittedStream = crate::stream::CommittedStream<Entry>;
+
+/// A sending side of a `CommittedStream`, which should be fed by a task driving a Multi-Paxos node.
+pub type Subscriber = crate::stream::Subscriber<Entry>;
+
+impl<S: Storage> CommittedLog for Node<S> {
+    type Item = Entry;
+
+    fn read_committed(&self, from: Slot) -> Result<(Vec<Entry>, Slot)> {
+        let commit = self.commit();
+        if from >= commit {
+            return Ok((Vec::new(), from));
+        }
+        Ok((self.storage().entries(from, commit)?, commit))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{FutureExt, StreamExt};
+    use crate::paxos::PeerId;
+    use crate::paxos::multi::{Config, Slot};
+    use crate::paxos::multi::node::Node;
+    use crate::paxos::multi::storage::MemStorage;
+    use crate::paxos::multi::stream::CommittedStream;
+
+    const A: PeerId = 1;
+
+    fn received(stream: &mut CommittedStream) -> Vec<Slot> {
+        let mut result = Vec::new();
+        while let Some(Some(entry)) = stream.next().now_or_never() {
+            result.push(entry.slot);
+        }
+        result
+    }
+
+    #[test]
+    fn multi_paxos_stream_from_slot() {
+        let mut node = Node::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        node.campaign().unwrap();
+        for i in 1..=3u8 {
+            node.propose(vec![i]).unwrap();
+        }
+        let (mut subscriber, mut stream) = CommittedStream::new(1);
+        assert!(subscriber.notify(&node).unwrap());
+        assert_eq!(received(&mut stream), vec![1, 2]);
+
+        node.propose(vec![4]).unwrap();
+        subscriber.notify(&node).unwrap();
+        assert_eq!(received(&mut stream), vec![3]);
+
+        drop(stream);
+        assert!(!subscriber.notify(&node).unwrap());
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::paxos::PeerId;
+
+/// A quorum system of Paxos acceptors. Phase 1 and phase 2 quorums don't need to be of the same
+/// kind: Paxos remains safe as long as every phase 1 quorum intersects with every phase 2 quorum.
+pub trait QuorumSystem {
+    /// Checks if given nodes contain a phase 1 quorum of a given set of acceptors.
+    fn is_phase1_quorum(&self, acceptors: &BTreeSet<PeerId>, nodes: &BTreeSet<PeerId>) -> bool;
+
+    /// Checks if given nodes contain a phase 2 quorum of a given set of acceptors.
+    fn is_phase2_quorum(&self, acceptors: &BTreeSet<PeerId>, nodes: &BTreeSet<PeerId>) -> bool;
+
+    /// Checks if this quorum system can be used with a given set of acceptors, which means that
+    /// all of them together form quorums of both phases, and every phase 1 quorum intersects
+    /// with every phase 2 quorum.
+    fn validate(&self, acceptors: &BTreeSet<PeerId>) -> Result<()>;
+
+    /// Returns a size of the smallest phase 1 quorum of given acceptors.
+    fn min_phase1_size(&self, acceptors: &BTreeSet<PeerId>) -> usize;
+
+    /// Checks if any `size` of given acceptors can form a fast quorum. Values accepted by a fast
+    /// quorum must be recognized by every phase 1 quorum, which requires any two fast quorums and
+    /// any phase 1 quorum to have an acceptor in common.
+    fn validate_fast(&self, acceptors: &BTreeSet<PeerId>, size: usize) -> Result<()> {
+        let n = acceptors.len();
+        if size == 0 || size > n {
+            Err(anyhow::anyhow!("Fast Paxos quorum size ({}) must be within [1, {}]", size, n))
+        } else if self.min_phase1_size(acceptors) + 2 * size <= 2 * n {
+            Err(anyhow::anyhow!("Fast Paxos quorum size ({}) is too small to intersect with phase 1 quorums of {} acceptors", size, n))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Quorum systems supported by Paxos implementations of this crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Quorum {
+    /// Majority of acceptors in both phases.
+    #[default]
+    Majority,
+    /// Flexible Paxos quorums: any `phase1` acceptors form a phase 1 quorum, while any `phase2`
+    /// acceptors form a phase 2 quorum. They always intersect as long as their sum is greater
+    /// than the number of acceptors, so phase 2 - executed for every value - can use smaller
+    /// quorums at the expense of phase 1, which is executed only when a leader changes.
+    Flexible { phase1: usize, phase2: usize },
+    /// Acceptors arranged in rows of a grid. A phase 2 quorum consists of all acceptors of any
+    /// row, while a phase 1 quorum needs at least one acceptor from every row. Phase 2 quorums
+    /// can be kept small by using many short rows.
+    Grid(Vec<Vec<PeerId>>),
+}
+
+impl QuorumSystem for Quorum {
+    fn is_phase1_quorum(&self, acceptors: &BTreeSet<PeerId>, nodes: &BTreeSet<PeerId>) -> bool {
+        let count = acceptors.intersection(nodes).count();
+        match self {
+            Quorum::Majority => count > acceptors.len() / 2,
+            Quorum::Flexible { phase1, .. } => count >= *phase1,
+            Quorum::Grid(rows) => rows.iter().all(|row| row.iter().any(|id| nodes.contains(id))),
+        }
+    }
+
+    fn is_phase2_quorum(&self, acceptors: &BTreeSet<PeerId>, nodes: &BTreeSet<PeerId>) -> bool {
+        let count = acceptors.intersection(nodes).count();
+        match self {
+            Quorum::Majority => count > acceptors.len() / 2,
+            Quorum::Flexible { phase2, .. } => count >= *phase2,
+            Quorum::Grid(rows) => rows.iter().any(|row| row.iter().all(|id| nodes.contains(id))),
+        }
+    }
+
+    fn validate(&self, acceptors: &BTreeSet<PeerId>) -> Result<()> {
+        if acceptors.is_empty() {
+            return Err(anyhow::anyhow!("Paxos quorum system requires at least one acceptor"));
+        }
+        match self {
+            Quorum::Majority => Ok(()),
+            Quorum::Flexible { phase1, phase2 } => {
+                let n = acceptors.len();
+                if *phase1 == 0 || *phase2 == 0 || *phase1 > n || *phase2 > n {
+                    Err(anyhow::anyhow!("Flexible Paxos quorum sizes ({}, {}) must be within [1, {}]", phase1, phase2, n))
+                } else if phase1 + phase2 <= n {
+                    Err(anyhow::anyhow!("Flexible Paxos quorum sizes ({}, {}) must sum up to more than {} acceptors", phase1, phase2, n))
+                } else {
+                    Ok(())
+                }
+            },
+            Quorum::Grid(rows) => {
+                let mut seen = BTreeSet::new();
+                for row in rows {
+                    if row.is_empty() {
+                        return Err(anyhow::anyhow!("Paxos grid quorum {:?} contains an empty row", rows));
+                    }
+                    for &id in row {
+                        if !seen.insert(id) {
+                            return Err(anyhow::anyhow!("Paxos grid quorum {:?} contains acceptor {} more than once", rows, id));
+                        }
+                    }
+                }
+                if &seen != acceptors {
+                    Err(anyhow::anyhow!("Paxos grid quorum {:?} doesn't match acceptors {:?}", rows, acceptors))
+                } else {
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn min_phase1_size(&self, acceptors: &BTreeSet<PeerId>) -> usize {
+        match self {
+            Quorum::Majority => acceptors.len() / 2 + 1,
+            Quorum::Flexible { phase1, .. } => *phase1,
+            Quorum::Grid(rows) => rows.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use crate::paxos::PeerId;
+    use crate::paxos::quorum::{Quorum, QuorumSystem};
+
+    fn set(ids: &[PeerId]) -> BTreeSet<PeerId> {
+        ids.iter().cloned().collect()
+    }
+
+    #[test]
+    fn quorum_majority() {
+        let acceptors = set(&[1, 2, 3, 4]);
+        let q = Quorum::Majority;
+        assert!(q.validate(&acceptors).is_ok());
+        assert!(q.validate(&set(&[])).is_err());
+        assert!(!q.is_phase1_quorum(&acceptors, &set(&[1, 2])));
+        assert!(q.is_phase2_quorum(&acceptors, &set(&[1, 2, 4])));
+        // nodes outside of acceptors don't count
+        assert!(!q.is_phase2_quorum(&acceptors, &set(&[1, 2, 5])));
+    }
+
+    #[test]
+    fn quorum_flexible() {
+        let acceptors = set(&[1, 2, 3, 4, 5]);
+        let q = Quorum::Flexible { phase1: 4, phase2: 2 };
+        assert!(q.validate(&acceptors).is_ok());
+        assert!(q.is_phase2_quorum(&acceptors, &set(&[1, 5])));
+        assert!(!q.is_phase1_quorum(&acceptors, &set(&[1, 2, 5])));
+        assert!(q.is_phase1_quorum(&acceptors, &set(&[1, 2, 3, 5])));
+
+        assert!(Quorum::Flexible { phase1: 3, phase2: 2 }.validate(&acceptors).is_err());
+        assert!(Quorum::Flexible { phase1: 6, phase2: 1 }.validate(&acceptors).is_err());
+        assert!(Quorum::Flexible { phase1: 5, phase2: 0 }.validate(&acceptors).is_err());
+    }
+
+    #[test]
+    fn quorum_fast() {
+        let acceptors = set(&[1, 2, 3, 4, 5]);
+        assert!(Quorum::Majority.validate_fast(&acceptors, 4).is_ok());
+        assert!(Quorum::Majority.validate_fast(&acceptors, 3).is_err());
+        assert!(Quorum::Majority.validate_fast(&acceptors, 6).is_err());
+        // larger phase 1 quorums allow for smaller fast ones
+        assert!(Quorum::Flexible { phase1: 5, phase2: 1 }.validate_fast(&acceptors, 3).is_ok());
+        assert!(Quorum::Flexible { phase1: 4, phase2: 2 }.validate_fast(&acceptors, 3).is_err());
+    }
+
+    #[test]
+    fn quorum_grid() {
+        let acceptors = set(&[1, 2, 3, 4, 5, 6]);
+        let q = Quorum::Grid(vec![vec![1, 2], vec![3, 4], vec![5, 6]]);
+        assert!(q.validate(&acceptors).is_ok());
+        assert!(q.is_phase2_quorum(&acceptors, &set(&[3, 4])));
+        assert!(!q.is_phase2_quorum(&acceptors, &set(&[1, 3, 5])));
+        assert!(q.is_phase1_quorum(&acceptors, &set(&[1, 3, 5])));
+        assert!(!q.is_phase1_quorum(&acceptors, &set(&[1, 2, 3, 4])));
+
+        assert!(Quorum::Grid(vec![vec![1, 2], vec![2, 3]]).validate(&set(&[1, 2, 3])).is_err());
+        assert!(Quorum::Grid(vec![vec![1, 2], vec![]]).validate(&set(&[1, 2])).is_err());
+        assert!(q.validate(&set(&[1, 2, 3, 4, 5])).is_err());
+    }
+}
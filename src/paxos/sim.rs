@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::Result;
+use crate::paxos::PeerId;
+
+/// Common interface of sans-IO protocol nodes, allowing to drive them in tests.
+pub(crate) trait Process {
+    type Message: Envelope;
+
+    fn step(&mut self, msg: Self::Message) -> Result<()>;
+
+    fn tick(&mut self) -> Result<()>;
+
+    fn take_messages(&mut self) -> Vec<Self::Message>;
+}
+
+pub(crate) trait Envelope {
+    fn from(&self) -> PeerId;
+
+    fn to(&self) -> PeerId;
+}
+
+/// Deterministic in-memory network of protocol nodes, used for testing.
+pub(crate) struct Network<P: Process> {
+    nodes: BTreeMap<PeerId, P>,
+    /// Nodes cut off from the rest of the network.
+    isolated: BTreeSet<PeerId>,
+    /// Messages sent but not yet delivered.
+    in_flight: VecDeque<P::Message>,
+}
+
+impl<P: Process> Network<P> {
+    pub fn new<I: IntoIterator<Item=(PeerId, P)>>(nodes: I) -> Self {
+        Network {
+            nodes: nodes.into_iter().collect(),
+            isolated: BTreeSet::new(),
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    pub fn node(&self, id: PeerId) -> &P {
+        self.nodes.get(&id).expect("node not found")
+    }
+
+    pub fn node_mut(&mut self, id: PeerId) -> &mut P {
+        self.nodes.get_mut(&id).expect("node not found")
+    }
+
+    pub fn tick_all(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.tick().unwrap();
+        }
+    }
+
+    /// Cuts a given node off from the rest of the network.
+    pub fn isolate(&mut self, id: PeerId) {
+        self.isolated.insert(id);
+    }
+
+    pub fn heal(&mut self) {
+        self.isolated.clear();
+    }
+
+    /// Collects messages sent by all nodes.
+    pub fn collect(&mut self) {
+        for node in self.nodes.values_mut() {
+            self.in_flight.extend(node.take_messages());
+        }
+    }
+
+    /// Delivers a single message in flight, returning false if there were none. Messages sent
+    /// from or to isolated nodes are dropped.
+    pub fn deliver_one(&mut self) -> bool {
+        let msg = match self.in_flight.pop_front() {
+            Some(msg) => msg,
+            None => return false,
+        };
+        if !self.isolated.contains(&msg.from()) && !self.isolated.contains(&msg.to()) {
+            if let Some(node) = self.nodes.get_mut(&msg.to()) {
+                node.step(msg).unwrap();
+            }
+        }
+        true
+    }
+
+    /// Delivers messages between nodes until there are no more messages in flight.
+    pub fn deliver(&mut self) {
+        self.collect();
+        while self.deliver_one() {
+            self.collect();
+        }
+    }
+}
@@ -1,33 +1,537 @@
-use serde::{Serialize, Deserialize};
+use std::collections::{HashSet, BTreeMap, VecDeque};
 use crate::Result;
-use std::collections::HashSet;
-use crate::raft::PeerId;
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::message::Payload;
+use crate::raft::node::Node;
+use crate::raft::read::PendingRead;
+use crate::raft::transfer::Transfer;
+use crate::raft::replication::Progress;
+use crate::raft::storage::{Storage, EntryKind, Snapshot};
 
+/// A role of a Raft node together with a role-specific state.
+#[derive(Debug)]
 pub enum State {
     Leader(LeaderState),
     Follower(FollowerState),
     Candidate(CandidateState),
 }
 
+#[derive(Debug, Default)]
 pub struct LeaderState {
+    pub(super) progress: BTreeMap<PeerId, Progress>,
+    /// Index of the first entry appended in a current term.
+    pub(super) term_start: LogIndex,
+    /// Sequence number of the latest heartbeat round.
+    pub(super) heartbeat_seq: u64,
+    /// Heartbeat rounds not yet acknowledged by a majority, as `(sequence number, tick)` pairs.
+    pub(super) rounds: VecDeque<(u64, u64)>,
+    /// Tick at which the leader lease expires.
+    pub(super) lease_expires: u64,
+    /// Reads waiting for a heartbeat round to be acknowledged by a majority.
+    pub(super) reads: VecDeque<PendingRead>,
+    /// Leadership transfer in progress.
+    pub(super) transfer: Option<Transfer>,
 }
 
 impl LeaderState {
-
+    /// Returns replication progress of a given follower.
+    pub fn progress(&self, peer: PeerId) -> Option<&Progress> { self.progress.get(&peer) }
 }
 
+#[derive(Debug, Default)]
 pub struct FollowerState {
-
+    /// Leader of a current term, if it's known.
+    pub leader: Option<PeerId>,
+    /// Snapshot which is being received from a leader.
+    pub(super) snapshot: Option<Snapshot>,
 }
 
 impl FollowerState {
 
 }
 
+#[derive(Debug, Default)]
 pub struct CandidateState {
-
+    votes: HashSet<PeerId>,
+    /// If true, a candidate collects pre-votes without incrementing its term yet.
+    pre_vote: bool,
 }
 
 impl CandidateState {
+    /// Nodes which granted their votes to a current candidate (including candidate itself).
+    pub fn votes(&self) -> &HashSet<PeerId> { &self.votes }
+
+    /// Checks if a candidate is still in a pre-vote phase.
+    pub fn is_pre_vote(&self) -> bool { self.pre_vote }
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Starts a new election, even if election timeout has not passed yet. Only voting members can
+    /// become candidates.
+    pub fn campaign(&mut self) -> Result<()> {
+        self.start_campaign(false)
+    }
+
+    /// Starts a new election. If it's a result of a leadership transfer, other nodes grant their
+    /// votes even if they still hear from a current leader.
+    pub(super) fn start_campaign(&mut self, transfer: bool) -> Result<()> {
+        if self.is_leader() {
+            return Ok(());
+        }
+        if !self.membership.is_voter(self.id) {
+            return Err(anyhow::anyhow!("Raft node {} is not a voter", self.id));
+        }
+        self.become_candidate()?;
+        let id = self.id;
+        if self.membership.has_quorum(|peer| peer == id) {
+            return self.become_leader();
+        }
+
+        let (last_log_index, last_log_term) = self.last_log()?;
+        let peers: Vec<_> = self.membership.all_voters().into_iter().filter(|&peer| peer != id).collect();
+        for peer in peers {
+            self.send(peer, Payload::RequestVote { last_log_index, last_log_term, transfer });
+        }
+        Ok(())
+    }
+
+    /// Starts a pre-vote phase: a node asks other voters if they would vote for it in the next term,
+    /// without incrementing its own term. A real election starts only once a majority agrees, so
+    /// that a node which cannot win an election (eg. because it has been partitioned) doesn't
+    /// inflate its term and disrupt a cluster once it reconnects.
+    fn pre_campaign(&mut self) -> Result<()> {
+        let id = self.id;
+        let mut votes = HashSet::new();
+        votes.insert(id);
+        self.state = State::Candidate(CandidateState { votes, pre_vote: true });
+        self.reset_election_timeout();
+        if self.membership.has_quorum(|peer| peer == id) {
+            return self.campaign();
+        }
+
+        let (last_log_index, last_log_term) = self.last_log()?;
+        let term = self.term + 1;
+        let peers: Vec<_> = self.membership.all_voters().into_iter().filter(|&peer| peer != id).collect();
+        for peer in peers {
+            self.send_with_term(peer, term, Payload::PreVote { last_log_index, last_log_term });
+        }
+        Ok(())
+    }
+
+    pub(super) fn tick_election(&mut self) -> Result<()> {
+        self.election_elapsed += 1;
+        if self.election_elapsed >= self.randomized_timeout && self.membership.is_voter(self.id) {
+            if self.config.pre_vote {
+                self.pre_campaign()
+            } else {
+                self.campaign()
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(super) fn tick_heartbeat(&mut self) -> Result<()> {
+        self.election_elapsed += 1;
+        if self.config.check_quorum && self.election_elapsed >= self.config.election_timeout {
+            self.election_elapsed = 0;
+            if !self.check_quorum() {
+                // leader partitioned from a majority steps down, instead of accepting proposals
+                // which can never be committed
+                return self.become_follower(self.term, None);
+            }
+        }
+        self.tick_transfer();
+        self.heartbeat_elapsed += 1;
+        if self.heartbeat_elapsed >= self.config.heartbeat_interval {
+            self.heartbeat_elapsed = 0;
+            self.broadcast_heartbeat();
+        }
+        Ok(())
+    }
+
+    /// Turns current node into a follower. If a given term is higher than the current one,
+    /// a new term is persisted and a vote from the previous term is discarded.
+    pub(super) fn become_follower(&mut self, term: Term, leader: Option<PeerId>) -> Result<()> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.persist()?;
+        }
+        self.state = State::Follower(FollowerState { leader, snapshot: None });
+        self.reset_election_timeout();
+        Ok(())
+    }
+
+    fn become_candidate(&mut self) -> Result<()> {
+        self.term += 1;
+        self.voted_for = Some(self.id);
+        self.persist()?;
+
+        let mut votes = HashSet::new();
+        votes.insert(self.id);
+        self.state = State::Candidate(CandidateState { votes, pre_vote: false });
+        self.reset_election_timeout();
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<()> {
+        let next_index = self.storage.last_index()? + 1;
+        let progress = self.membership.members().iter()
+            .filter(|&&id| id != self.id)
+            .map(|&id| (id, Progress::new(next_index)))
+            .collect();
+        self.state = State::Leader(LeaderState { progress, term_start: next_index, ..LeaderState::default() });
+        self.heartbeat_elapsed = 0;
+        self.election_elapsed = 0;
+        // a leader cannot commit entries from previous terms until it commits an entry from its
+        // own term, appending it also establishes authority right away
+        self.append_entry(EntryKind::Noop, Vec::new())?;
+        Ok(())
+    }
+
+    /// Sends a new round of heartbeats, returning its sequence number.
+    pub(super) fn broadcast_heartbeat(&mut self) -> u64 {
+        let commit = self.commit;
+        let ticks = self.ticks;
+        let election_timeout = self.config.election_timeout;
+        let (seq, heartbeats): (_, Vec<_>) = match &mut self.state {
+            State::Leader(leader) => {
+                leader.heartbeat_seq += 1;
+                // rounds older than election timeout cannot extend a lease anymore, while pending
+                // reads will be confirmed by any later round
+                while leader.rounds.front().map(|&(_, tick)| tick + election_timeout <= ticks).unwrap_or(false) {
+                    leader.rounds.pop_front();
+                }
+                leader.rounds.push_back((leader.heartbeat_seq, ticks));
+                // follower may not have replicated all committed entries yet
+                let heartbeats = leader.progress.iter()
+                    .map(|(&peer, p)| (peer, commit.min(p.match_index)))
+                    .collect();
+                (leader.heartbeat_seq, heartbeats)
+            },
+            _ => return 0,
+        };
+        for (peer, commit) in heartbeats {
+            self.send(peer, Payload::Heartbeat { commit, seq });
+        }
+        seq
+    }
+
+    /// Checks if a majority of voters has been active since the last check, resetting their
+    /// activity afterwards.
+    fn check_quorum(&mut self) -> bool {
+        let id = self.id;
+        let leader = match &mut self.state {
+            State::Leader(leader) => leader,
+            _ => return false,
+        };
+        let progress = &leader.progress;
+        let active = self.membership.has_quorum(|peer| {
+            peer == id || progress.get(&peer).map(|p| p.recent_active).unwrap_or(false)
+        });
+        for p in leader.progress.values_mut() {
+            p.recent_active = false;
+        }
+        active
+    }
+
+    /// Checks if a candidate's log is at least as up to date as ours.
+    fn is_up_to_date(&self, last_log_index: LogIndex, last_log_term: Term) -> Result<bool> {
+        let (index, term) = self.last_log()?;
+        Ok(last_log_term > term || (last_log_term == term && last_log_index >= index))
+    }
+
+    /// Checks if a current node is a leader or a follower, which has recently heard from a leader.
+    pub(super) fn has_active_leader(&self) -> bool {
+        match &self.state {
+            State::Leader(_) => true,
+            State::Follower(follower) => follower.leader.is_some() && self.election_elapsed < self.config.election_timeout,
+            State::Candidate(_) => false,
+        }
+    }
+
+    pub(super) fn handle_request_vote(&mut self, candidate: PeerId, last_log_index: LogIndex, last_log_term: Term) -> Result<()> {
+        let can_vote = match self.voted_for {
+            None => true,
+            Some(id) => id == candidate,
+        };
+        let granted = can_vote && self.is_up_to_date(last_log_index, last_log_term)?;
+        if granted {
+            self.voted_for = Some(candidate);
+            self.persist()?;
+            self.reset_election_timeout();
+        }
+        self.send(candidate, Payload::RequestVoteResponse { granted });
+        Ok(())
+    }
+
+    /// Pre-vote is granted to a candidate with a higher term and up to date log, as long as we
+    /// don't know about a leader which is still active. It doesn't change our term or vote.
+    pub(super) fn handle_pre_vote(&mut self, candidate: PeerId, term: Term, last_log_index: LogIndex, last_log_term: Term) -> Result<()> {
+        let granted = term > self.term
+            && !self.has_active_leader()
+            && self.is_up_to_date(last_log_index, last_log_term)?;
+        // granted response is sent with a candidate's term, so that it's not ignored as stale
+        let term = if granted { term } else { self.term };
+        self.send_with_term(candidate, term, Payload::PreVoteResponse { granted });
+        Ok(())
+    }
+
+    pub(super) fn handle_pre_vote_response(&mut self, from: PeerId, term: Term, granted: bool) -> Result<()> {
+        if let State::Candidate(candidate) = &mut self.state {
+            if candidate.pre_vote && granted && term == self.term + 1 && self.membership.is_voter(from) {
+                candidate.votes.insert(from);
+                if self.membership.has_quorum(|id| candidate.votes.contains(&id)) {
+                    return self.campaign();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn handle_request_vote_response(&mut self, from: PeerId, granted: bool) -> Result<()> {
+        if let State::Candidate(candidate) = &mut self.state {
+            if !candidate.pre_vote && granted && self.membership.is_voter(from) {
+                candidate.votes.insert(from);
+                if self.membership.has_quorum(|id| candidate.votes.contains(&id)) {
+                    return self.become_leader();
+                }
+            }
+        }
+        Ok(())
+    }
 
-}
\ No newline at end of file
+    pub(super) fn handle_heartbeat(&mut self, leader: PeerId, commit: LogIndex, seq: u64) -> Result<()> {
+        self.accept_leader(leader)?;
+        if commit > self.commit {
+            self.commit = commit;
+        }
+        self.send(leader, Payload::HeartbeatResponse { seq });
+        Ok(())
+    }
+
+    /// Recognizes authority of a leader of a current term.
+    pub(super) fn accept_leader(&mut self, leader: PeerId) -> Result<()> {
+        match &mut self.state {
+            State::Leader(_) => Err(anyhow::anyhow!("Raft node {} received message from another leader {} in the same term {}", self.id, leader, self.term)),
+            State::Candidate(_) => self.become_follower(self.term, Some(leader)),
+            State::Follower(follower) => {
+                follower.leader = Some(leader);
+                self.election_elapsed = 0;
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::election::State;
+    use crate::raft::message::{Message, Payload};
+    use crate::raft::node::{Node, Config};
+    use crate::raft::sim::Network;
+    use crate::raft::storage::{MemStorage, Entry, Storage};
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    fn request_vote(from: PeerId, to: PeerId, term: u64, last_log_index: u64, last_log_term: u64) -> Message {
+        Message { from, to, term, payload: Payload::RequestVote { last_log_index, last_log_term, transfer: false } }
+    }
+
+    #[test]
+    fn election_single_node() {
+        let mut a = Node::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        let timeout = a.config.election_timeout;
+        let mut ticks = 0;
+        while !a.is_leader() {
+            a.tick().unwrap();
+            ticks += 1;
+        }
+        // election timeout is randomized
+        assert!(ticks >= timeout && ticks < 2 * timeout);
+        assert_eq!(a.term(), 1);
+        assert_eq!(a.leader(), Some(A));
+    }
+
+    #[test]
+    fn election_majority_wins() {
+        let mut net = Network::new(&[A, B, C]);
+        net.node_mut(A).campaign().unwrap();
+        assert!(matches!(net.node(A).state(), State::Candidate(_)));
+
+        net.deliver();
+        assert!(net.node(A).is_leader());
+        for &id in &[B, C] {
+            assert_eq!(net.node(id).leader(), Some(A));
+            assert_eq!(net.node(id).term(), 1);
+        }
+    }
+
+    #[test]
+    fn election_timeout_triggers_campaign() {
+        let mut net = Network::new(&[A, B, C]);
+        let timeout = net.node(A).config.election_timeout;
+        for _ in 0..2 * timeout {
+            net.tick(A);
+            if matches!(net.node(A).state(), State::Candidate(_)) {
+                break;
+            }
+        }
+        net.deliver();
+        assert_eq!(net.leader(), Some(A));
+
+        // heartbeats of a leader prevent followers from starting new elections
+        for _ in 0..10 * timeout {
+            net.tick_all();
+            net.deliver();
+        }
+        assert_eq!(net.leader(), Some(A));
+        assert_eq!(net.node(B).term(), 1);
+    }
+
+    #[test]
+    fn election_vote_once_per_term() {
+        let mut storage = MemStorage::default();
+        {
+            let mut a = Node::new(Config::new(A, vec![A, B, C]), storage.clone()).unwrap();
+            a.step(request_vote(B, A, 1, 0, 0)).unwrap();
+            a.step(request_vote(C, A, 1, 0, 0)).unwrap();
+            let granted: Vec<_> = a.take_messages().into_iter()
+                .map(|m| m.payload == Payload::RequestVoteResponse { granted: true })
+                .collect();
+            assert_eq!(granted, vec![true, false]);
+            storage = a.storage().clone();
+        }
+
+        // vote is persisted and survives restart
+        let mut a = Node::new(Config::new(A, vec![A, B, C]), storage).unwrap();
+        assert_eq!(a.term(), 1);
+        a.step(request_vote(C, A, 1, 0, 0)).unwrap();
+        a.step(request_vote(B, A, 1, 0, 0)).unwrap();
+        let granted: Vec<_> = a.take_messages().into_iter()
+            .map(|m| m.payload == Payload::RequestVoteResponse { granted: true })
+            .collect();
+        assert_eq!(granted, vec![false, true]);
+    }
+
+    #[test]
+    fn election_reject_outdated_log() {
+        let mut storage = MemStorage::default();
+        storage.append(&[Entry::new(1, 1, vec![]), Entry::new(2, 2, vec![])]).unwrap();
+        let mut a = Node::new(Config::new(A, vec![A, B, C]), storage).unwrap();
+
+        a.step(request_vote(B, A, 3, 5, 1)).unwrap(); // older last term
+        a.step(request_vote(C, A, 4, 1, 2)).unwrap(); // shorter log
+        a.step(request_vote(B, A, 5, 2, 2)).unwrap(); // up to date
+        let granted: Vec<_> = a.take_messages().into_iter()
+            .map(|m| m.payload == Payload::RequestVoteResponse { granted: true })
+            .collect();
+        assert_eq!(granted, vec![false, false, true]);
+        assert_eq!(a.term(), 5);
+    }
+
+    #[test]
+    fn election_step_down_on_higher_term() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        assert!(net.node(A).is_leader());
+
+        net.node_mut(A).step(request_vote(B, A, 5, 0, 0)).unwrap();
+        assert!(!net.node(A).is_leader());
+        assert_eq!(net.node(A).term(), 5);
+
+        // stale leader learns about a newer term from a heartbeat response
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.isolate(A);
+        net.elect(B);
+        net.heal();
+        assert!(net.node(A).is_leader());
+        net.node_mut(A).broadcast_heartbeat();
+        net.deliver();
+        assert!(!net.node(A).is_leader());
+        assert_eq!(net.leader(), Some(B));
+        assert_eq!(net.node(A).term(), 2);
+    }
+
+    #[test]
+    fn election_partitioned_follower_with_pre_vote() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.pre_vote = true);
+        let timeout = net.node(A).config.election_timeout;
+        net.elect(A);
+        net.isolate(C);
+        for _ in 0..10 * timeout {
+            net.tick(C);
+            net.deliver();
+        }
+        // pre-vote never succeeded, so term has not been incremented
+        assert_eq!(net.node(C).term(), 1);
+        assert!(matches!(net.node(C).state(), State::Candidate(c) if c.is_pre_vote()));
+
+        net.heal();
+        for _ in 0..timeout {
+            net.tick(A);
+            net.deliver();
+        }
+        assert_eq!(net.leader(), Some(A));
+        assert_eq!(net.node(A).term(), 1);
+        assert_eq!(net.node(C).leader(), Some(A));
+    }
+
+    #[test]
+    fn election_pre_vote_rejected_while_leader_is_active() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.pre_vote = true);
+        let timeout = net.node(A).config.election_timeout;
+        net.elect(A);
+        // C misses heartbeats, but B still hears from a leader
+        for _ in 0..2 * timeout {
+            net.tick(C);
+        }
+        net.deliver();
+        assert_eq!(net.node(C).term(), 1);
+        assert_eq!(net.leader(), Some(A));
+
+        // a leader which has failed is replaced as usual
+        net.isolate(A);
+        for _ in 0..20 * timeout {
+            net.tick(B);
+            net.tick(C);
+            net.deliver();
+            if net.leader().is_some() {
+                break;
+            }
+        }
+        assert!(matches!(net.leader(), Some(B) | Some(C)));
+        assert!(net.node(B).term() > 1);
+    }
+
+    #[test]
+    fn election_check_quorum() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.check_quorum = true);
+        let timeout = net.node(A).config.election_timeout;
+        net.elect(A);
+        for _ in 0..2 * timeout {
+            net.tick(A);
+            net.deliver();
+        }
+        assert!(net.node(A).is_leader());
+
+        // followers hearing from a leader ignore vote requests with inflated terms
+        net.node_mut(B).step(request_vote(C, B, 5, 10, 5)).unwrap();
+        assert_eq!(net.node(B).term(), 1);
+
+        // leader cut off from a majority steps down
+        net.isolate(A);
+        for _ in 0..2 * timeout {
+            net.tick(A);
+            net.deliver();
+        }
+        assert!(!net.node(A).is_leader());
+        assert_eq!(net.node(A).term(), 1);
+    }
+}
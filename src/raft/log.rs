@@ -1,12 +1,370 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use futures::channel::{mpsc, oneshot};
+use futures::{Stream, StreamExt};
 use crate::Result;
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::message::Message;
+use crate::raft::node::Node;
+use crate::raft::snapshot::StateMachine;
+use crate::raft::storage::{Storage, Entry, EntryKind};
+use crate::raft::stream::{CommittedStream, Subscriber};
 
-#[derive(Debug)]
-pub struct ReplicatedEventLog {
+enum Command {
+    Append(Vec<u8>, oneshot::Sender<Result<LogIndex>>),
+    Subscribe(mpsc::UnboundedSender<Entry>),
+    Stream(Subscriber),
+    Read(oneshot::Sender<Result<LogIndex>>),
+    TransferLeadership(PeerId, oneshot::Sender<Result<()>>),
+}
 
+/// An asynchronous handle to a Raft `Node`, which is driven by a background task. The task ticks
+/// a node in regular intervals, steps it with messages received from `inbox` and pushes messages
+/// produced by a node into `outbox` - delivering them to other nodes is up to a caller. Committed
+/// entries are applied to a state machine, which is used to compact the log.
+#[derive(Debug, Clone)]
+pub struct ReplicatedEventLog {
+    id: PeerId,
+    commands: mpsc::UnboundedSender<Command>,
 }
 
 impl ReplicatedEventLog {
-    pub async fn append<B: AsRef<[u8]>>(&mut self, buf: B) -> Result<()> {
-        unimplemented!()
+
+    /// Spawns a new tokio task driving a given `node`, which is ticked every `tick` interval.
+    pub fn spawn<S, M>(node: Node<S>, state_machine: M, tick: Duration, inbox: mpsc::UnboundedReceiver<Message>, outbox: mpsc::UnboundedSender<Message>) -> Self
+        where S: Storage + Send + 'static,
+              M: StateMachine + Send + 'static {
+        let id = node.id();
+        let (commands, rx) = mpsc::unbounded();
+        let driver = Driver {
+            node,
+            state_machine,
+            outbox,
+            pending: BTreeMap::new(),
+            subscribers: Vec::new(),
+            streams: Vec::new(),
+            next_read: 0,
+            reads: BTreeMap::new(),
+            confirmed: Vec::new(),
+        };
+        tokio::spawn(driver.run(tick, inbox, rx));
+        ReplicatedEventLog { id, commands }
+    }
+
+    pub fn id(&self) -> PeerId { self.id }
+
+    /// Appends a given buffer to the log. Returned future completes with an index of appended
+    /// entry once it has been committed by the majority of nodes.
+    ///
+    /// It fails if a current node is not a leader or if an entry has been overridden by another
+    /// leader before it was committed.
+    pub async fn append<B: AsRef<[u8]>>(&self, buf: B) -> Result<LogIndex> {
+        let (tx, rx) = oneshot::channel();
+        self.commands.unbounded_send(Command::Append(buf.as_ref().to_vec(), tx))
+            .map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?;
+        rx.await.map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?
+    }
+
+    /// Performs a linearizable read. Returned future completes with a read index once all entries
+    /// up to it have been applied to a state machine of a current node, which then reflects all
+    /// appends completed before this call.
+    ///
+    /// It fails if a leader is unknown or it has changed before a read could be confirmed.
+    pub async fn read(&self) -> Result<LogIndex> {
+        let (tx, rx) = oneshot::channel();
+        self.commands.unbounded_send(Command::Read(tx))
+            .map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?;
+        rx.await.map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?
+    }
+
+    /// Starts transferring leadership of a current node to a given voter, eg. before restarting it.
+    /// Returned future completes once transfer has started - appends are rejected until it
+    /// finishes or times out.
+    pub async fn transfer_leadership(&self, target: PeerId) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.commands.unbounded_send(Command::TransferLeadership(target, tx))
+            .map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?;
+        rx.await.map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?
+    }
+
+    /// Returns a stream of entries committed from now on.
+    pub fn committed(&self) -> Result<impl Stream<Item=Entry>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.commands.unbounded_send(Command::Subscribe(tx))
+            .map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?;
+        Ok(rx)
+    }
+
+    /// Returns a stream of all entries committed starting from a given index, including entries
+    /// committed before this call. If these entries have already been compacted, a stream starts
+    /// with a snapshot replacing them.
+    pub fn committed_from(&self, index: LogIndex) -> Result<CommittedStream> {
+        let (subscriber, stream) = CommittedStream::new(index);
+        self.commands.unbounded_send(Command::Stream(subscriber))
+            .map_err(|_| anyhow::anyhow!("Raft node {} has been stopped", self.id))?;
+        Ok(stream)
+    }
+}
+
+struct Driver<S, M> {
+    node: Node<S>,
+    state_machine: M,
+    outbox: mpsc::UnboundedSender<Message>,
+    /// Appends awaiting to be committed, together with a term in which they were proposed.
+    pending: BTreeMap<LogIndex, (Term, oneshot::Sender<Result<LogIndex>>)>,
+    subscribers: Vec<mpsc::UnboundedSender<Entry>>,
+    streams: Vec<Subscriber>,
+    next_read: u64,
+    /// Reads awaiting to be confirmed by a leader, together with a term in which they were requested.
+    reads: BTreeMap<u64, (Term, oneshot::Sender<Result<LogIndex>>)>,
+    /// Reads confirmed by a leader, awaiting for their read index to be applied.
+    confirmed: Vec<(LogIndex, oneshot::Sender<Result<LogIndex>>)>,
+}
+
+impl<S: Storage, M: StateMachine> Driver<S, M> {
+    async fn run(mut self, tick: Duration, mut inbox: mpsc::UnboundedReceiver<Message>, mut commands: mpsc::UnboundedReceiver<Command>) {
+        let mut interval = tokio::time::interval(tick);
+        loop {
+            let result = tokio::select! {
+                _ = interval.tick() => self.node.tick(),
+                msg = inbox.next() => match msg {
+                    Some(msg) => self.node.step(msg),
+                    None => break,
+                },
+                cmd = commands.next() => match cmd {
+                    Some(cmd) => self.handle(cmd),
+                    None => break,
+                },
+            };
+            if let Err(e) = result.and_then(|_| self.flush()) {
+                log::error!("Raft node {} stopped: {}", self.node.id(), e);
+                break;
+            }
+        }
+    }
+
+    fn handle(&mut self, cmd: Command) -> Result<()> {
+        match cmd {
+            Command::Append(data, reply) => match self.node.propose(data) {
+                Ok(index) => { self.pending.insert(index, (self.node.term(), reply)); },
+                Err(e) => { let _ = reply.send(Err(e)); },
+            },
+            Command::Subscribe(tx) => self.subscribers.push(tx),
+            Command::Stream(subscriber) => self.streams.push(subscriber),
+            Command::TransferLeadership(target, reply) => {
+                let _ = reply.send(self.node.transfer_leadership(target));
+            },
+            Command::Read(reply) => {
+                self.next_read += 1;
+                match self.node.read(self.next_read) {
+                    Ok(()) => { self.reads.insert(self.next_read, (self.node.term(), reply)); },
+                    Err(e) => { let _ = reply.send(Err(e)); },
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Sends outgoing messages and notifies about committed entries.
+    fn flush(&mut self) -> Result<()> {
+        for msg in self.node.take_messages() {
+            // recipient unreachable, Raft will retry on its own
+            let _ = self.outbox.unbounded_send(msg);
+        }
+
+        // notify streams before committed entries are applied, as they may be compacted afterwards
+        let mut streams = Vec::with_capacity(self.streams.len());
+        for mut subscriber in self.streams.drain(..) {
+            if subscriber.notify(&self.node)? {
+                streams.push(subscriber);
+            }
+        }
+        self.streams = streams;
+
+        for entry in self.node.apply(&mut self.state_machine)? {
+            if let Some((term, reply)) = self.pending.remove(&entry.index) {
+                let _ = if term == entry.term {
+                    reply.send(Ok(entry.index))
+                } else {
+                    reply.send(Err(anyhow::anyhow!("Raft entry {} has been overridden by a leader of term {}", entry.index, entry.term)))
+                };
+            }
+            if entry.kind == EntryKind::Normal {
+                self.subscribers.retain(|s| s.unbounded_send(entry.clone()).is_ok());
+            }
+        }
+
+        // entries could have been replaced by a snapshot received from another leader
+        let applied = self.node.applied_index();
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > applied {
+                break;
+            }
+            let (index, (_, reply)) = entry.remove_entry();
+            let _ = reply.send(Err(anyhow::anyhow!("Raft entry {} has been replaced by a snapshot before it could be confirmed", index)));
+        }
+
+        for read in self.node.take_reads() {
+            if let Some((_, reply)) = self.reads.remove(&read.id) {
+                self.confirmed.push((read.index, reply));
+            }
+        }
+        // read requests are not retried by a new leader
+        let term = self.node.term();
+        let failed: Vec<_> = self.reads.iter()
+            .filter(|(_, (t, _))| *t != term)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in failed {
+            if let Some((t, reply)) = self.reads.remove(&id) {
+                let _ = reply.send(Err(anyhow::anyhow!("Raft read could not be confirmed by a leader of term {}", t)));
+            }
+        }
+        let (ready, waiting) = std::mem::take(&mut self.confirmed).into_iter()
+            .partition(|(index, _)| *index <= applied);
+        self.confirmed = waiting;
+        for (index, reply) in ready {
+            let _ = reply.send(Ok(index));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use crate::raft::log::ReplicatedEventLog;
+    use crate::Result;
+    use crate::raft::node::{Node, Config};
+    use crate::raft::snapshot::StateMachine;
+    use crate::raft::storage::{MemStorage, Entry};
+    use crate::raft::stream::Committed;
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    const TICK: Duration = Duration::from_millis(5);
+
+    #[derive(Debug, Default)]
+    struct Events(Vec<Vec<u8>>);
+
+    impl StateMachine for Events {
+        fn apply(&mut self, entry: &Entry) -> Result<()> {
+            self.0.push(entry.data.clone());
+            Ok(())
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(serde_cbor::to_vec(&self.0)?)
+        }
+
+        fn restore(&mut self, data: &[u8]) -> Result<()> {
+            self.0 = serde_cbor::from_slice(data)?;
+            Ok(())
+        }
+    }
+
+    /// Spawns a cluster of nodes, connected by a task routing messages between them.
+    fn cluster(ids: &[PeerId]) -> BTreeMap<PeerId, ReplicatedEventLog> {
+        let (outbox, mut outgoing) = mpsc::unbounded();
+        let mut inboxes = BTreeMap::new();
+        let mut logs = BTreeMap::new();
+        for &id in ids {
+            let (tx, rx) = mpsc::unbounded();
+            let mut node = Node::new(Config::new(id, ids.to_vec()), MemStorage::default()).unwrap();
+            if id == ids[0] {
+                // don't wait for election timeout
+                node.campaign().unwrap();
+            }
+            inboxes.insert(id, tx);
+            logs.insert(id, ReplicatedEventLog::spawn(node, Events::default(), TICK, rx, outbox.clone()));
+        }
+        tokio::spawn(async move {
+            while let Some(msg) = outgoing.next().await {
+                if let Some(inbox) = inboxes.get(&msg.to) {
+                    let _ = inbox.unbounded_send(msg);
+                }
+            }
+        });
+        logs
+    }
+
+    #[tokio::test]
+    async fn replicated_log_append_committed() {
+        let logs = cluster(&[A, B, C]);
+        let mut streams: Vec<_> = logs.values().map(|log| log.committed().unwrap()).collect();
+
+        let leader = &logs[&A];
+        let first = loop {
+            match leader.append(b"hello").await {
+                Ok(index) => break index,
+                Err(_) => tokio::time::sleep(TICK).await, // election is still in progress
+            }
+        };
+        let second = leader.append(b"world").await.unwrap();
+        assert_eq!(second, first + 1);
+
+        for stream in streams.iter_mut() {
+            let entry = stream.next().await.unwrap();
+            assert_eq!((entry.index, entry.data), (first, b"hello".to_vec()));
+            let entry = stream.next().await.unwrap();
+            assert_eq!((entry.index, entry.data), (second, b"world".to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn replicated_log_append_on_follower_fails() {
+        let logs = cluster(&[A, B, C]);
+        let leader = &logs[&A];
+        while leader.append(b"hello").await.is_err() {
+            tokio::time::sleep(TICK).await;
+        }
+        assert!(logs[&B].append(b"world").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn replicated_log_committed_from() {
+        let logs = cluster(&[A, B, C]);
+        let leader = &logs[&A];
+        let first = loop {
+            match leader.append(b"hello").await {
+                Ok(index) => break index,
+                Err(_) => tokio::time::sleep(TICK).await,
+            }
+        };
+        leader.append(b"world").await.unwrap();
+
+        let stream = logs[&B].committed_from(first).unwrap();
+        let entries: Vec<_> = stream.take(2).map(|c| match c {
+            Committed::Entry(e) => e.data,
+            Committed::Snapshot(_) => panic!("log should not be compacted"),
+        }).collect().await;
+        assert_eq!(entries, vec![b"hello".to_vec(), b"world".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn replicated_log_read() {
+        let logs = cluster(&[A, B, C]);
+        let leader = &logs[&A];
+        let index = loop {
+            match leader.append(b"hello").await {
+                Ok(index) => break index,
+                Err(_) => tokio::time::sleep(TICK).await,
+            }
+        };
+        assert!(leader.read().await.unwrap() >= index);
+        // follower may not know about a leader yet
+        let read = loop {
+            match logs[&B].read().await {
+                Ok(read) => break read,
+                Err(_) => tokio::time::sleep(TICK).await,
+            }
+        };
+        assert!(read >= index);
     }
 }
@@ -0,0 +1,369 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::raft::{PeerId, LogIndex};
+use crate::raft::election::State;
+use crate::raft::node::{Node, Config};
+use crate::raft::replication::Progress;
+use crate::raft::storage::{Storage, EntryKind};
+
+/// Cluster configuration. Changes of voting members are performed using joint consensus: first
+/// a joint configuration is committed, in which decisions require separate majorities of both
+/// old and new voters, and only then a configuration containing new voters alone.
+///
+/// Learners receive log entries, but they don't vote and are not counted into any majority. This
+/// way new nodes can catch up with a leader before they are promoted to voters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Voters of a current (or outgoing, while in joint consensus) configuration.
+    pub voters: BTreeSet<PeerId>,
+    /// Voters of an incoming configuration, present only while in joint consensus.
+    pub joint: Option<BTreeSet<PeerId>>,
+    pub learners: BTreeSet<PeerId>,
+}
+
+impl Membership {
+    pub fn new<I: IntoIterator<Item=PeerId>>(voters: I) -> Self {
+        Membership {
+            voters: voters.into_iter().collect(),
+            joint: None,
+            learners: BTreeSet::new(),
+        }
+    }
+
+    pub fn is_joint(&self) -> bool { self.joint.is_some() }
+
+    /// Checks if a given node is a voter in any of the configurations.
+    pub fn is_voter(&self, id: PeerId) -> bool {
+        self.voters.contains(&id) || self.joint.iter().any(|j| j.contains(&id))
+    }
+
+    pub fn is_learner(&self, id: PeerId) -> bool { self.learners.contains(&id) }
+
+    /// Returns voters of all configurations.
+    pub fn all_voters(&self) -> BTreeSet<PeerId> {
+        let mut result = self.voters.clone();
+        if let Some(joint) = &self.joint {
+            result.extend(joint.iter().cloned());
+        }
+        result
+    }
+
+    /// Returns all voters and learners.
+    pub fn members(&self) -> BTreeSet<PeerId> {
+        let mut result = self.all_voters();
+        result.extend(self.learners.iter().cloned());
+        result
+    }
+
+    /// Checks if voters matching a given predicate form a majority in every configuration.
+    pub fn has_quorum<F: Fn(PeerId) -> bool>(&self, f: F) -> bool {
+        let majority = |voters: &BTreeSet<PeerId>| {
+            voters.iter().filter(|&&id| f(id)).count() > voters.len() / 2
+        };
+        majority(&self.voters) && self.joint.iter().all(majority)
+    }
+
+    /// Returns the highest log index replicated by a majority of voters in every configuration,
+    /// given a function returning the highest log index replicated by each node.
+    pub fn committed<F: Fn(PeerId) -> LogIndex>(&self, f: F) -> LogIndex {
+        let committed = |voters: &BTreeSet<PeerId>| {
+            let mut matched: Vec<_> = voters.iter().map(|&id| f(id)).collect();
+            matched.sort_unstable_by(|a, b| b.cmp(a));
+            matched.get(voters.len() / 2).cloned().unwrap_or(0)
+        };
+        let mut index = committed(&self.voters);
+        if let Some(joint) = &self.joint {
+            index = index.min(committed(joint));
+        }
+        index
+    }
+}
+
+/// Returns the latest membership from the log up to a given index (inclusive), together with
+/// an index of an entry which introduced it. If there's no such entry, a membership from the
+/// snapshot is returned, or the initial one from the config if there's no snapshot either.
+pub(super) fn membership_at<S: Storage>(storage: &S, config: &Config, index: LogIndex) -> Result<(LogIndex, Membership)> {
+    let first_index = storage.first_index()?;
+    if index >= first_index {
+        for e in storage.entries(first_index, index + 1)?.into_iter().rev() {
+            if e.kind == EntryKind::Membership {
+                return Ok((e.index, serde_cbor::from_slice(e.data.as_slice())?));
+            }
+        }
+    }
+    match storage.snapshot()? {
+        Some(snapshot) => Ok((snapshot.index, snapshot.membership)),
+        None => Ok((0, Membership::new(config.voters.iter().cloned()))),
+    }
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Current cluster configuration, as seen by this node. Configuration takes effect as soon as
+    /// it's appended to the log, without waiting for it to be committed.
+    pub fn membership(&self) -> &Membership { &self.membership }
+
+    /// Adds a new non-voting member, which will start receiving log entries from a leader.
+    pub fn add_learner(&mut self, id: PeerId) -> Result<LogIndex> {
+        self.check_membership_change()?;
+        if self.membership.members().contains(&id) {
+            return Err(anyhow::anyhow!("Raft node {} is already a cluster member", id));
+        }
+        let mut membership = self.membership.clone();
+        membership.learners.insert(id);
+        self.append_membership(membership)
+    }
+
+    /// Removes a non-voting member.
+    pub fn remove_learner(&mut self, id: PeerId) -> Result<LogIndex> {
+        self.check_membership_change()?;
+        if !self.membership.is_learner(id) {
+            return Err(anyhow::anyhow!("Raft node {} is not a learner", id));
+        }
+        let mut membership = self.membership.clone();
+        membership.learners.remove(&id);
+        self.append_membership(membership)
+    }
+
+    /// Starts changing a set of voting members using joint consensus. Once a joint configuration
+    /// is committed, leader automatically appends the final one. New voters must be learners,
+    /// which have already caught up with a leader. Voters not present in a new set are removed
+    /// from the cluster - this includes a leader itself, which steps down once the final
+    /// configuration is committed.
+    pub fn change_voters(&mut self, voters: BTreeSet<PeerId>) -> Result<LogIndex> {
+        self.check_membership_change()?;
+        if voters.is_empty() {
+            return Err(anyhow::anyhow!("Raft cluster must have at least one voter"));
+        }
+        if voters == self.membership.voters {
+            return Err(anyhow::anyhow!("Raft cluster voters are already {:?}", voters));
+        }
+        for &id in voters.difference(&self.membership.voters) {
+            let caught_up = match &self.state {
+                State::Leader(leader) => leader.progress(id).map(|p| p.match_index >= self.commit).unwrap_or(false),
+                _ => false,
+            };
+            if !self.membership.is_learner(id) || !caught_up {
+                return Err(anyhow::anyhow!("Raft node {} must be a learner, which has caught up with a leader, before becoming a voter", id));
+            }
+        }
+
+        let mut membership = self.membership.clone();
+        for id in voters.iter() {
+            membership.learners.remove(id);
+        }
+        membership.joint = Some(voters);
+        self.append_membership(membership)
+    }
+
+    fn check_membership_change(&self) -> Result<()> {
+        if !self.is_leader() {
+            Err(anyhow::anyhow!("Raft node {} is not a leader (current leader: {:?})", self.id, self.leader()))
+        } else if self.membership_index > self.commit || self.membership.is_joint() {
+            Err(anyhow::anyhow!("Raft node {} has another membership change in progress", self.id))
+        } else if let Some(target) = self.leadership_transfer() {
+            Err(anyhow::anyhow!("Raft node {} is transferring its leadership to {}", self.id, target))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn append_membership(&mut self, membership: Membership) -> Result<LogIndex> {
+        let data = serde_cbor::to_vec(&membership)?;
+        let index = self.storage.last_index()? + 1;
+        // configuration is used as soon as it's appended, so that new members receive it as well
+        self.set_membership(index, membership)?;
+        self.append_entry(EntryKind::Membership, data)
+    }
+
+    pub(super) fn set_membership(&mut self, index: LogIndex, membership: Membership) -> Result<()> {
+        let next_index = self.storage.last_index()? + 1;
+        let id = self.id;
+        let members = membership.members();
+        if let State::Leader(leader) = &mut self.state {
+            leader.progress.retain(|peer, _| members.contains(peer));
+            for &peer in members.iter().filter(|&&peer| peer != id) {
+                leader.progress.entry(peer).or_insert_with(|| Progress::new(next_index));
+            }
+        }
+        self.membership = membership;
+        self.membership_index = index;
+        Ok(())
+    }
+
+    /// Recovers membership from the latest configuration in the log, eg. after it was truncated.
+    pub(super) fn restore_membership(&mut self) -> Result<()> {
+        let last_index = self.storage.last_index()?;
+        let (index, membership) = membership_at(&self.storage, &self.config, last_index)?;
+        self.set_membership(index, membership)
+    }
+
+    /// Called by a leader after commit index has changed. Finishes joint consensus, once joint
+    /// configuration is committed, and steps down if a leader is no longer a voter.
+    pub(super) fn advance_membership(&mut self) -> Result<()> {
+        if !self.is_leader() || self.commit < self.membership_index {
+            return Ok(());
+        }
+        if let Some(voters) = self.membership.joint.clone() {
+            let mut membership = self.membership.clone();
+            membership.voters = voters;
+            membership.joint = None;
+            self.append_membership(membership)?;
+        } else if !self.membership.is_voter(self.id) {
+            // let followers know about commit of the final configuration before stepping down
+            self.broadcast_heartbeat();
+            self.become_follower(self.term, None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use crate::raft::membership::Membership;
+    use crate::raft::node::{Node, Config};
+    use crate::raft::sim::Network;
+    use crate::raft::storage::Storage;
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+    const D: PeerId = 4;
+    const E: PeerId = 5;
+
+    fn set(ids: &[PeerId]) -> BTreeSet<PeerId> {
+        ids.iter().cloned().collect()
+    }
+
+    /// Adds given nodes to the cluster as learners and waits until they catch up.
+    fn add_learners(net: &mut Network, leader: PeerId, ids: &[PeerId]) {
+        for &id in ids {
+            net.add_node(id);
+            net.node_mut(leader).add_learner(id).unwrap();
+            net.deliver();
+            net.heartbeat(leader);
+        }
+    }
+
+    #[test]
+    fn membership_quorum() {
+        let mut m = Membership::new(vec![A, B, C]);
+        assert!(m.has_quorum(|id| id == A || id == B));
+        assert!(!m.has_quorum(|id| id == A));
+        assert_eq!(m.committed(|id| id), B);
+
+        m.joint = Some(set(&[C, D, E]));
+        assert!(!m.has_quorum(|id| id == A || id == B));
+        assert!(m.has_quorum(|id| id != A));
+        assert_eq!(m.all_voters(), set(&[A, B, C, D, E]));
+        assert_eq!(m.committed(|id| if id == A { 10 } else { id }), C);
+    }
+
+    #[test]
+    fn membership_promote_learner() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        add_learners(&mut net, A, &[D]);
+        assert!(net.node(D).membership().is_learner(D));
+        assert!(!net.node(A).membership().has_quorum(|id| id == A || id == D));
+
+        net.node_mut(A).change_voters(set(&[A, B, C, D])).unwrap();
+        net.deliver();
+        net.heartbeat(A);
+        for &id in &[A, B, C, D] {
+            let m = net.node(id).membership();
+            assert_eq!(m.voters, set(&[A, B, C, D]));
+            assert!(!m.is_joint());
+            assert!(m.learners.is_empty());
+        }
+
+        // configuration is recovered from the log after restart
+        let storage = net.node(D).storage().clone();
+        let d = Node::new(Config::new(D, vec![]), storage).unwrap();
+        assert!(d.membership().is_voter(D));
+    }
+
+    #[test]
+    fn membership_promote_requires_caught_up_learner() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.add_node(D);
+        assert!(net.node_mut(A).change_voters(set(&[A, B, C, D])).is_err()); // not a learner
+
+        net.isolate(D);
+        net.node_mut(A).add_learner(D).unwrap();
+        net.deliver();
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+        assert!(net.node_mut(A).change_voters(set(&[A, B, C, D])).is_err()); // not caught up
+
+        net.heal();
+        net.heartbeat(A);
+        assert!(net.node_mut(A).change_voters(set(&[A, B, C, D])).is_ok());
+        // only one change at the time
+        assert!(net.node_mut(A).add_learner(E).is_err());
+    }
+
+    #[test]
+    fn membership_joint_consensus_requires_both_majorities() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        add_learners(&mut net, A, &[D, E]);
+
+        net.isolate(D);
+        net.isolate(E);
+        let index = net.node_mut(A).change_voters(set(&[A, D, E])).unwrap();
+        net.deliver();
+        net.heartbeat(A);
+        // old majority has accepted joint configuration, but a new one didn't
+        assert!(net.node(B).storage().last_index().unwrap() >= index);
+        assert!(net.node(A).commit_index() < index);
+        assert!(net.node(A).membership().is_joint());
+
+        net.heal();
+        net.heartbeat(A);
+        net.heartbeat(A);
+        assert!(net.node(A).commit_index() > index);
+        for &id in &[A, D, E] {
+            let m = net.node(id).membership();
+            assert_eq!(m.voters, set(&[A, D, E]));
+            assert!(!m.is_joint());
+        }
+        assert_eq!(net.node(A).membership().members(), set(&[A, D, E]));
+    }
+
+    #[test]
+    fn membership_remove_leader() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.node_mut(A).change_voters(set(&[B, C])).unwrap();
+        net.deliver();
+        net.heartbeat(A);
+        net.heartbeat(A);
+
+        assert!(!net.node(A).is_leader());
+        assert!(!net.node(A).membership().is_voter(A));
+        for &id in &[B, C] {
+            assert_eq!(net.node(id).membership().voters, set(&[B, C]));
+            assert_eq!(net.node(id).commit_index(), net.node(A).commit_index());
+        }
+
+        // removed node never starts an election
+        let term = net.node(A).term();
+        for _ in 0..4 * net.node(A).config.election_timeout {
+            net.tick(A);
+        }
+        net.deliver();
+        assert_eq!(net.node(A).term(), term);
+        assert!(net.node_mut(A).campaign().is_err());
+
+        net.elect(B);
+        assert_eq!(net.leader(), Some(B));
+        net.node_mut(B).propose(vec![1]).unwrap();
+        net.deliver();
+        assert_eq!(net.node(B).commit_index(), net.node(B).storage().last_index().unwrap());
+    }
+}
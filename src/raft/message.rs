@@ -0,0 +1,52 @@
+use serde::{Serialize, Deserialize};
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::membership::Membership;
+use crate::raft::storage::Entry;
+
+/// A message exchanged between Raft nodes. Every message carries a term of its sender, which is
+/// used to detect stale leaders and candidates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub term: Term,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload {
+    /// Sent by a candidate to gather votes. Contains a position of candidate's last log entry, so
+    /// that only candidates with an up to date log can become a leader. `transfer` is set when
+    /// a candidate has been asked to campaign by a leader transferring its leadership.
+    RequestVote { last_log_index: LogIndex, last_log_term: Term, transfer: bool },
+    RequestVoteResponse { granted: bool },
+    /// Asks if a recipient would vote for a sender in a term of this message. Sender's term is not
+    /// incremented until a majority grants its pre-vote.
+    PreVote { last_log_index: LogIndex, last_log_term: Term },
+    PreVoteResponse { granted: bool },
+    /// Sent by a leader to replicate log entries. A follower accepts them only if its log contains
+    /// an entry at `prev_log_index` with a matching `prev_log_term`.
+    AppendEntries { prev_log_index: LogIndex, prev_log_term: Term, entries: Vec<Entry>, leader_commit: LogIndex },
+    /// If `success` is true, `last_index` is an index of the last entry matching leader's log.
+    /// Otherwise it's a hint about where leader should retry replication from.
+    AppendEntriesResponse { success: bool, last_index: LogIndex },
+    /// Sent periodically by a leader to maintain its authority and prevent new elections. It also
+    /// propagates a commit index, which a recipient is known to have replicated.
+    Heartbeat { commit: LogIndex, seq: u64 },
+    /// Acknowledges a heartbeat with a given sequence number, confirming leadership of its sender
+    /// at the time it was sent.
+    HeartbeatResponse { seq: u64 },
+    /// Sent by a leader to a follower, which is missing entries already replaced by a snapshot.
+    /// Snapshot is send in chunks, starting at a given byte `offset`.
+    InstallSnapshot { last_index: LogIndex, last_term: Term, membership: Membership, offset: u64, data: Vec<u8>, done: bool },
+    /// Confirms that a follower has received snapshot bytes up to a given `offset`. Once the whole
+    /// snapshot is installed, follower responds with `AppendEntriesResponse` instead.
+    InstallSnapshotResponse { last_index: LogIndex, offset: u64 },
+    /// Sent by a leader transferring its leadership, asking a recipient to start an election
+    /// immediately.
+    TimeoutNow,
+    /// Sent by a follower to a leader, asking for a read index of a given read request.
+    ReadIndex { id: u64 },
+    /// Read index of a given read request, confirmed by a leader.
+    ReadIndexResponse { id: u64, index: LogIndex },
+}
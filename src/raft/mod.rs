@@ -1,5 +1,18 @@
 pub mod log;
 pub mod election;
+pub mod replication;
+pub mod snapshot;
 pub mod stream;
+pub mod node;
+pub mod storage;
+pub mod sled_storage;
+pub mod message;
+pub mod membership;
+pub mod read;
+pub mod transfer;
+#[cfg(test)]
+mod sim;
 
-pub type PeerId = u64;
\ No newline at end of file
+pub type PeerId = u64;
+pub type Term = u64;
+pub type LogIndex = u64;
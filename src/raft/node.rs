@@ -0,0 +1,280 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::Result;
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::election::{State, FollowerState};
+use crate::raft::membership::{Membership, membership_at};
+use crate::raft::message::{Message, Payload};
+use crate::raft::read::ReadState;
+use crate::raft::storage::{Storage, HardState, Snapshot};
+
+const DEFAULT_ELECTION_TIMEOUT: u64 = 10;
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 2;
+const DEFAULT_MAX_APPEND_ENTRIES: u64 = 64;
+const DEFAULT_SNAPSHOT_THRESHOLD: u64 = 1024;
+const DEFAULT_SNAPSHOT_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_CLOCK_DRIFT: u64 = 1;
+
+/// Configuration of a Raft `Node`. All timeouts are expressed in number of ticks.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Identifier of a current node.
+    pub id: PeerId,
+    /// Identifiers of all voting members of a cluster, including a current node, used when there's
+    /// no configuration in the log yet. It should be empty for nodes joining an existing cluster.
+    pub voters: Vec<PeerId>,
+    /// Minimal number of ticks without hearing from a leader, after which a follower starts a new
+    /// election. Actual timeout is randomized within `[election_timeout, 2 * election_timeout)`
+    /// range, so that nodes rarely become candidates at the same time.
+    pub election_timeout: u64,
+    /// Number of ticks between heartbeats sent by a leader. It should be significantly lower
+    /// than `election_timeout`.
+    pub heartbeat_interval: u64,
+    /// Maximum number of entries send within a single `AppendEntries` message.
+    pub max_append_entries: u64,
+    /// Number of applied entries, after which a new snapshot is taken and the log is compacted.
+    pub snapshot_threshold: u64,
+    /// Maximum number of snapshot bytes send within a single `InstallSnapshot` message.
+    pub snapshot_chunk_size: usize,
+    /// If enabled, a leader serves reads without confirming its leadership with a heartbeat
+    /// round, as long as it holds a lease. In order to respect leases, followers ignore vote
+    /// requests while they hear from a leader.
+    pub lease_reads: bool,
+    /// If enabled, candidates must first learn that they could win an election, before they
+    /// increment their term. Followers which have recently heard from a leader refuse to do so.
+    pub pre_vote: bool,
+    /// If enabled, a leader steps down if it hasn't heard from a majority of voters within
+    /// an election timeout, while followers ignore vote requests as long as they hear from
+    /// a leader.
+    pub check_quorum: bool,
+    /// Maximum difference in tick rates between nodes, expressed in number of ticks per election
+    /// timeout. Leader lease is shortened by this value.
+    pub max_clock_drift: u64,
+}
+
+impl Config {
+    pub fn new(id: PeerId, voters: Vec<PeerId>) -> Self {
+        Config {
+            id,
+            voters,
+            election_timeout: DEFAULT_ELECTION_TIMEOUT,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            max_append_entries: DEFAULT_MAX_APPEND_ENTRIES,
+            snapshot_threshold: DEFAULT_SNAPSHOT_THRESHOLD,
+            snapshot_chunk_size: DEFAULT_SNAPSHOT_CHUNK_SIZE,
+            lease_reads: false,
+            pre_vote: false,
+            check_quorum: false,
+            max_clock_drift: DEFAULT_MAX_CLOCK_DRIFT,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.voters.is_empty() && !self.voters.contains(&self.id) {
+            Err(anyhow::anyhow!("Raft node {} is not one of the voters {:?}", self.id, self.voters))
+        } else if self.heartbeat_interval == 0 || self.heartbeat_interval >= self.election_timeout {
+            Err(anyhow::anyhow!("Raft heartbeat interval ({}) must be positive and lower than election timeout ({})", self.heartbeat_interval, self.election_timeout))
+        } else if self.max_append_entries == 0 || self.snapshot_threshold == 0 || self.snapshot_chunk_size == 0 {
+            Err(anyhow::anyhow!("Raft max append entries, snapshot threshold and snapshot chunk size must be positive"))
+        } else if self.max_clock_drift >= self.election_timeout {
+            Err(anyhow::anyhow!("Raft max clock drift ({}) must be lower than election timeout ({})", self.max_clock_drift, self.election_timeout))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A single member of a Raft cluster. It's implemented as a deterministic state machine, which
+/// doesn't perform any IO on its own: passage of time is signalled by calling `tick`, incoming
+/// messages are passed through `step`, while messages that should be sent to other nodes are
+/// buffered and can be obtained using `take_messages`.
+#[derive(Debug)]
+pub struct Node<S> {
+    pub(super) id: PeerId,
+    pub(super) config: Config,
+    pub(super) membership: Membership,
+    /// Index of the log entry, which introduced a current membership.
+    pub(super) membership_index: LogIndex,
+    pub(super) storage: S,
+    pub(super) state: State,
+    pub(super) term: Term,
+    pub(super) voted_for: Option<PeerId>,
+    /// Index of the highest log entry known to be committed.
+    pub(super) commit: LogIndex,
+    /// Index of the highest log entry returned by `committed_entries` or applied to a state machine.
+    pub(super) applied: LogIndex,
+    /// Number of ticks since this node was created.
+    pub(super) ticks: u64,
+    pub(super) election_elapsed: u64,
+    pub(super) randomized_timeout: u64,
+    pub(super) heartbeat_elapsed: u64,
+    pub(super) rng: StdRng,
+    pub(super) outbox: Vec<Message>,
+    pub(super) ready_reads: Vec<ReadState>,
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Creates a new node, restoring its term and vote from a given storage. Every node starts
+    /// as a follower.
+    pub fn new(config: Config, storage: S) -> Result<Self> {
+        config.validate()?;
+        let hard_state = storage.hard_state()?;
+        // entries replaced by a snapshot are always committed
+        let commit = storage.first_index()? - 1;
+        let (membership_index, membership) = membership_at(&storage, &config, storage.last_index()?)?;
+        let mut node = Node {
+            id: config.id,
+            membership,
+            membership_index,
+            config,
+            storage,
+            state: State::Follower(FollowerState::default()),
+            term: hard_state.term,
+            voted_for: hard_state.voted_for,
+            commit,
+            applied: 0,
+            ticks: 0,
+            election_elapsed: 0,
+            randomized_timeout: 0,
+            heartbeat_elapsed: 0,
+            rng: StdRng::from_entropy(),
+            outbox: Vec::new(),
+            ready_reads: Vec::new(),
+        };
+        node.reset_election_timeout();
+        Ok(node)
+    }
+
+    pub fn id(&self) -> PeerId { self.id }
+
+    /// Current term of this node.
+    pub fn term(&self) -> Term { self.term }
+
+    /// Index of the highest log entry known to be committed.
+    pub fn commit_index(&self) -> LogIndex { self.commit }
+
+    /// Index of the highest log entry applied to a state machine.
+    pub fn applied_index(&self) -> LogIndex { self.applied }
+
+    pub fn state(&self) -> &State { &self.state }
+
+    pub fn storage(&self) -> &S { &self.storage }
+
+    pub fn is_leader(&self) -> bool { matches!(self.state, State::Leader(_)) }
+
+    /// Returns an identifier of a current leader, as far as this node knows.
+    pub fn leader(&self) -> Option<PeerId> {
+        match &self.state {
+            State::Leader(_) => Some(self.id),
+            State::Follower(follower) => follower.leader,
+            State::Candidate(_) => None,
+        }
+    }
+
+    /// Advances logical clock of this node by a single tick.
+    pub fn tick(&mut self) -> Result<()> {
+        self.ticks += 1;
+        if self.is_leader() {
+            self.tick_heartbeat()
+        } else {
+            self.tick_election()
+        }
+    }
+
+    /// Handles a message received from another node.
+    pub fn step(&mut self, msg: Message) -> Result<()> {
+        if msg.term > self.term && (self.config.lease_reads || self.config.check_quorum) && self.in_lease() {
+            if let Payload::RequestVote { transfer: false, .. } = msg.payload {
+                // current leader is still active and may serve reads using its lease
+                return Ok(());
+            }
+        }
+        if msg.term > self.term {
+            match msg.payload {
+                // pre-votes are send with a term candidate would have, without changing it
+                Payload::PreVote { .. } | Payload::PreVoteResponse { granted: true } => {},
+                _ => {
+                    let leader = match msg.payload {
+                        Payload::Heartbeat { .. } | Payload::AppendEntries { .. } | Payload::InstallSnapshot { .. } => Some(msg.from),
+                        _ => None,
+                    };
+                    self.become_follower(msg.term, leader)?;
+                },
+            }
+        } else if msg.term < self.term {
+            // let a stale sender know about a newer term, so that it can step down
+            match msg.payload {
+                Payload::RequestVote { .. } => self.send(msg.from, Payload::RequestVoteResponse { granted: false }),
+                Payload::PreVote { .. } => self.send(msg.from, Payload::PreVoteResponse { granted: false }),
+                Payload::AppendEntries { .. } | Payload::InstallSnapshot { .. } =>
+                    self.send(msg.from, Payload::AppendEntriesResponse { success: false, last_index: 0 }),
+                Payload::Heartbeat { .. } => self.send(msg.from, Payload::HeartbeatResponse { seq: 0 }),
+                _ => {},
+            }
+            return Ok(());
+        }
+
+        if msg.term == self.term {
+            if let Some(progress) = self.progress_mut(msg.from) {
+                progress.recent_active = true;
+            }
+        }
+        match msg.payload {
+            Payload::RequestVote { last_log_index, last_log_term, .. } =>
+                self.handle_request_vote(msg.from, last_log_index, last_log_term),
+            Payload::RequestVoteResponse { granted } => self.handle_request_vote_response(msg.from, granted),
+            Payload::PreVote { last_log_index, last_log_term } =>
+                self.handle_pre_vote(msg.from, msg.term, last_log_index, last_log_term),
+            Payload::PreVoteResponse { granted } => self.handle_pre_vote_response(msg.from, msg.term, granted),
+            Payload::AppendEntries { prev_log_index, prev_log_term, entries, leader_commit } =>
+                self.handle_append_entries(msg.from, prev_log_index, prev_log_term, entries, leader_commit),
+            Payload::AppendEntriesResponse { success, last_index } =>
+                self.handle_append_entries_response(msg.from, success, last_index),
+            Payload::Heartbeat { commit, seq } => self.handle_heartbeat(msg.from, commit, seq),
+            Payload::HeartbeatResponse { seq } => self.handle_heartbeat_response(msg.from, seq),
+            Payload::InstallSnapshot { last_index, last_term, membership, offset, data, done } => {
+                let chunk = Snapshot { index: last_index, term: last_term, membership, data };
+                self.handle_install_snapshot(msg.from, chunk, offset, done)
+            },
+            Payload::InstallSnapshotResponse { last_index, offset } =>
+                self.handle_install_snapshot_response(msg.from, last_index, offset),
+            Payload::TimeoutNow => self.handle_timeout_now(msg.from),
+            Payload::ReadIndex { id } => self.handle_read_index(msg.from, id),
+            Payload::ReadIndexResponse { id, index } => self.handle_read_index_response(id, index),
+        }
+    }
+
+    /// Returns all messages produced by this node since the last call, which should be sent to
+    /// their recipients.
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub(super) fn send(&mut self, to: PeerId, payload: Payload) {
+        self.send_with_term(to, self.term, payload);
+    }
+
+    pub(super) fn send_with_term(&mut self, to: PeerId, term: Term, payload: Payload) {
+        self.outbox.push(Message { from: self.id, to, term, payload });
+    }
+
+    /// Persists current term and vote.
+    pub(super) fn persist(&mut self) -> Result<()> {
+        let state = HardState { term: self.term, voted_for: self.voted_for };
+        self.storage.set_hard_state(&state)
+    }
+
+    /// Returns an index and a term of the last entry in a log.
+    pub(super) fn last_log(&self) -> Result<(LogIndex, Term)> {
+        let index = self.storage.last_index()?;
+        let term = self.storage.term(index)?.unwrap_or(0);
+        Ok((index, term))
+    }
+
+    pub(super) fn reset_election_timeout(&mut self) {
+        let timeout = self.config.election_timeout;
+        self.election_elapsed = 0;
+        self.randomized_timeout = self.rng.gen_range(timeout, 2 * timeout);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::Result;
+use crate::raft::{PeerId, LogIndex};
+use crate::raft::election::State;
+use crate::raft::message::Payload;
+use crate::raft::node::Node;
+use crate::raft::storage::Storage;
+
+/// A read request confirmed by a leader. It can be served from a local state machine once
+/// entries up to `index` have been applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadState {
+    /// Identifier of a read request, as passed to `Node::read`.
+    pub id: u64,
+    pub index: LogIndex,
+}
+
+/// A read request awaiting for a leader to confirm its leadership.
+#[derive(Debug, Clone)]
+pub(super) struct PendingRead {
+    /// Node which has requested a read.
+    pub from: PeerId,
+    pub state: ReadState,
+    /// Sequence number of a heartbeat round, which must be acknowledged by a majority.
+    pub seq: u64,
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Requests a linearizable read with a given identifier. Once a leader confirms that it's
+    /// still a leader, a read index is returned by `take_reads`: reading from a state machine,
+    /// which has applied all entries up to that index, reflects all writes committed before
+    /// a read was requested.
+    ///
+    /// Leader confirms its leadership with a round of heartbeats acknowledged by a majority or,
+    /// if `lease_reads` are enabled, using a lease obtained by a previous round. Followers forward
+    /// read requests to a leader. Requests may be lost, eg. when a leader changes in the meantime,
+    /// in which case they should be retried.
+    pub fn read(&mut self, id: u64) -> Result<()> {
+        match self.leader() {
+            Some(leader) if leader == self.id => self.read_index(self.id, id),
+            Some(leader) => self.send(leader, Payload::ReadIndex { id }),
+            None => return Err(anyhow::anyhow!("Raft node {} doesn't know a current leader", self.id)),
+        }
+        Ok(())
+    }
+
+    /// Returns all reads confirmed since the last call.
+    pub fn take_reads(&mut self) -> Vec<ReadState> {
+        std::mem::take(&mut self.ready_reads)
+    }
+
+    /// Checks if a current node is covered by a leader lease: either it's a leader, which
+    /// heartbeats have recently been acknowledged by a majority, or it's a follower which has
+    /// recently heard from a leader.
+    pub(super) fn in_lease(&self) -> bool {
+        match &self.state {
+            State::Leader(leader) => self.ticks < leader.lease_expires,
+            _ => self.has_active_leader(),
+        }
+    }
+
+    fn read_index(&mut self, from: PeerId, id: u64) {
+        let lease = self.config.lease_reads && self.in_lease();
+        let commit = self.commit;
+        let leader = match &mut self.state {
+            State::Leader(leader) => leader,
+            _ => return,
+        };
+        // new leader doesn't know which entries from previous terms are committed until it
+        // commits an entry from its own term
+        let state = ReadState { id, index: commit.max(leader.term_start) };
+        if lease {
+            self.respond_read(from, state);
+        } else {
+            let seq = self.broadcast_heartbeat();
+            if let State::Leader(leader) = &mut self.state {
+                leader.reads.push_back(PendingRead { from, state, seq });
+            }
+            // a single voter confirms its leadership on its own
+            self.confirm_reads();
+        }
+    }
+
+    /// Finds the latest heartbeat round acknowledged by a majority, extends a leader lease and
+    /// confirms all reads waiting for that round or earlier ones.
+    pub(super) fn confirm_reads(&mut self) {
+        let id = self.id;
+        let lease = self.config.election_timeout - self.config.max_clock_drift;
+        let leader = match &mut self.state {
+            State::Leader(leader) => leader,
+            _ => return,
+        };
+        let progress = &leader.progress;
+        let membership = &self.membership;
+        let confirmed = leader.rounds.iter().rev()
+            .find(|&&(seq, _)| membership.has_quorum(|peer| {
+                peer == id || progress.get(&peer).map(|p| p.heartbeat_seq >= seq).unwrap_or(false)
+            }))
+            .cloned();
+        let (seq, sent) = match confirmed {
+            Some(round) => round,
+            None => return,
+        };
+
+        // followers don't vote for anyone else for at least election timeout since they've
+        // received a heartbeat, which happened after it was sent - unless they are asked to by
+        // a leadership transfer
+        if leader.transfer.is_none() {
+            leader.lease_expires = leader.lease_expires.max(sent + lease);
+        }
+        while leader.rounds.front().map(|&(s, _)| s <= seq).unwrap_or(false) {
+            leader.rounds.pop_front();
+        }
+        let mut ready = Vec::new();
+        while leader.reads.front().map(|r| r.seq <= seq).unwrap_or(false) {
+            ready.extend(leader.reads.pop_front());
+        }
+        for read in ready {
+            self.respond_read(read.from, read.state);
+        }
+    }
+
+    fn respond_read(&mut self, from: PeerId, state: ReadState) {
+        if from == self.id {
+            self.ready_reads.push(state);
+        } else {
+            self.send(from, Payload::ReadIndexResponse { id: state.id, index: state.index });
+        }
+    }
+
+    pub(super) fn handle_read_index(&mut self, from: PeerId, id: u64) -> Result<()> {
+        // requests received by non-leaders are dropped, follower will retry them
+        self.read_index(from, id);
+        Ok(())
+    }
+
+    pub(super) fn handle_read_index_response(&mut self, id: u64, index: LogIndex) -> Result<()> {
+        self.ready_reads.push(ReadState { id, index });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::message::{Message, Payload};
+    use crate::raft::read::ReadState;
+    use crate::raft::sim::Network;
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    #[test]
+    fn read_confirmed_by_majority() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+
+        net.node_mut(A).read(1).unwrap();
+        assert!(net.node_mut(A).take_reads().is_empty());
+        net.isolate(C);
+        net.deliver();
+        assert_eq!(net.node_mut(A).take_reads(), vec![ReadState { id: 1, index: 2 }]);
+    }
+
+    #[test]
+    fn read_requires_majority() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.isolate(B);
+        net.isolate(C);
+        net.node_mut(A).read(1).unwrap();
+        net.deliver();
+        net.heartbeat(A);
+        assert!(net.node_mut(A).take_reads().is_empty());
+
+        net.heal();
+        net.heartbeat(A);
+        assert_eq!(net.node_mut(A).take_reads(), vec![ReadState { id: 1, index: 1 }]);
+    }
+
+    #[test]
+    fn read_on_follower() {
+        let mut net = Network::new(&[A, B, C]);
+        assert!(net.node_mut(B).read(1).is_err()); // no leader yet
+
+        net.elect(A);
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+        net.node_mut(B).read(7).unwrap();
+        net.deliver();
+        assert_eq!(net.node_mut(B).take_reads(), vec![ReadState { id: 7, index: 2 }]);
+        assert!(net.node_mut(A).take_reads().is_empty());
+    }
+
+    #[test]
+    fn read_with_lease() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.lease_reads = true);
+        net.elect(A);
+        // there's no lease until the first heartbeat round is acknowledged
+        net.node_mut(A).read(1).unwrap();
+        assert!(net.node_mut(A).take_reads().is_empty());
+        net.deliver();
+        assert_eq!(net.node_mut(A).take_reads().len(), 1);
+
+        net.node_mut(A).read(2).unwrap();
+        assert_eq!(net.node_mut(A).take_reads(), vec![ReadState { id: 2, index: 1 }]);
+
+        // lease expires, if heartbeats are not acknowledged
+        net.isolate(A);
+        for _ in 0..net.node(A).config.election_timeout {
+            net.tick(A);
+        }
+        net.deliver();
+        net.node_mut(A).read(3).unwrap();
+        net.deliver();
+        assert!(net.node_mut(A).take_reads().is_empty());
+    }
+
+    #[test]
+    fn read_lease_respected_by_followers() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.lease_reads = true);
+        net.elect(A);
+        net.heartbeat(A);
+
+        let term = net.node(B).term();
+        let payload = Payload::RequestVote { last_log_index: 10, last_log_term: term + 1, transfer: false };
+        net.node_mut(B).step(Message { from: C, to: B, term: term + 1, payload }).unwrap();
+        assert_eq!(net.node(B).term(), term);
+        assert_eq!(net.node(B).leader(), Some(A));
+        assert!(net.node_mut(B).take_messages().is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+use crate::Result;
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::election::State;
+use crate::raft::message::Payload;
+use crate::raft::node::Node;
+use crate::raft::snapshot::SnapshotTransfer;
+use crate::raft::storage::{Storage, Entry, EntryKind};
+
+/// Replication progress of a single follower, as tracked by a leader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Index of the next log entry to send to a follower.
+    pub next_index: LogIndex,
+    /// Index of the highest log entry known to be replicated on a follower.
+    pub match_index: LogIndex,
+    /// Snapshot which is being send to a follower. No entries are replicated until it's done.
+    pub snapshot: Option<SnapshotTransfer>,
+    /// Sequence number of the latest heartbeat acknowledged by a follower.
+    pub heartbeat_seq: u64,
+    /// Set whenever a follower responds to a leader, used to check if a leader can still reach
+    /// a majority of voters.
+    pub recent_active: bool,
+}
+
+impl Progress {
+    pub fn new(next_index: LogIndex) -> Self {
+        Progress { next_index, match_index: 0, snapshot: None, heartbeat_seq: 0, recent_active: false }
+    }
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Appends a new entry to a log of a current node and starts replicating it to followers.
+    /// Returns an index of appended entry. Only a leader can accept new proposals.
+    ///
+    /// Returned index doesn't mean that an entry has been committed - a leader may be replaced
+    /// before that happens and its uncommitted entries overridden by a new leader.
+    pub fn propose(&mut self, data: Vec<u8>) -> Result<LogIndex> {
+        if !self.is_leader() {
+            return Err(anyhow::anyhow!("Raft node {} is not a leader (current leader: {:?})", self.id, self.leader()));
+        }
+        if let Some(target) = self.leadership_transfer() {
+            return Err(anyhow::anyhow!("Raft node {} is transferring its leadership to {}", self.id, target));
+        }
+        self.append_entry(EntryKind::Normal, data)
+    }
+
+    /// Returns all entries, which have been committed since the last call. Fails if some of
+    /// these entries have already been replaced by a snapshot - use `apply` in that case.
+    pub fn committed_entries(&mut self) -> Result<Vec<Entry>> {
+        let snapshot_index = self.storage.first_index()? - 1;
+        if self.applied < snapshot_index {
+            return Err(anyhow::anyhow!("Raft node {} has entries up to {} compacted into a snapshot, which needs to be applied first", self.id, snapshot_index));
+        }
+        if self.applied >= self.commit {
+            return Ok(Vec::new());
+        }
+        let entries = self.storage.entries(self.applied + 1, self.commit + 1)?;
+        self.applied = self.commit;
+        Ok(entries)
+    }
+
+    pub(super) fn append_entry(&mut self, kind: EntryKind, data: Vec<u8>) -> Result<LogIndex> {
+        let index = self.storage.last_index()? + 1;
+        let entry = Entry { term: self.term, index, kind, data };
+        self.storage.append(&[entry])?;
+
+        let peers: Vec<_> = match &self.state {
+            State::Leader(leader) => leader.progress.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        for peer in peers {
+            self.send_append(peer)?;
+        }
+        self.maybe_commit()?;
+        Ok(index)
+    }
+
+    /// Sends entries, which given follower is missing, starting from its `next_index`.
+    pub(super) fn send_append(&mut self, to: PeerId) -> Result<()> {
+        let next_index = match self.progress_mut(to) {
+            Some(p) if p.snapshot.is_none() => p.next_index,
+            _ => return Ok(()),
+        };
+        if next_index < self.storage.first_index()? {
+            // entries follower needs have already been compacted
+            return self.send_snapshot(to);
+        }
+        let last_index = self.storage.last_index()?;
+        let prev_log_index = next_index - 1;
+        let prev_log_term = self.storage.term(prev_log_index)?
+            .ok_or_else(|| anyhow::anyhow!("Raft node {} has no log entry at index {}", self.id, prev_log_index))?;
+        let hi = (last_index + 1).min(next_index + self.config.max_append_entries);
+        let entries = self.storage.entries(next_index, hi)?;
+
+        // optimistically assume that entries will be accepted, so that they are not send again
+        // with the next proposal
+        if let Some(p) = self.progress_mut(to) {
+            p.next_index = hi;
+        }
+        let leader_commit = self.commit;
+        self.send(to, Payload::AppendEntries { prev_log_index, prev_log_term, entries, leader_commit });
+        Ok(())
+    }
+
+    pub(super) fn progress_mut(&mut self, peer: PeerId) -> Option<&mut Progress> {
+        match &mut self.state {
+            State::Leader(leader) => leader.progress.get_mut(&peer),
+            _ => None,
+        }
+    }
+
+    /// Advances commit index to the highest entry replicated by a majority of voters.
+    fn maybe_commit(&mut self) -> Result<()> {
+        let last_index = self.storage.last_index()?;
+        let id = self.id;
+        let index = match &self.state {
+            State::Leader(leader) => self.membership.committed(|peer| if peer == id {
+                last_index
+            } else {
+                leader.progress.get(&peer).map(|p| p.match_index).unwrap_or(0)
+            }),
+            _ => return Ok(()),
+        };
+
+        // entries from previous terms are committed only indirectly, by committing an entry from
+        // a current term
+        if index > self.commit && self.storage.term(index)? == Some(self.term) {
+            self.commit = index;
+            self.advance_membership()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn handle_append_entries(&mut self, leader: PeerId, prev_log_index: LogIndex, prev_log_term: Term, mut entries: Vec<Entry>, leader_commit: LogIndex) -> Result<()> {
+        self.accept_leader(leader)?;
+
+        let last_index = self.storage.last_index()?;
+        let last_new = prev_log_index + entries.len() as LogIndex;
+        let snapshot_index = self.storage.first_index()? - 1;
+        if prev_log_index < snapshot_index {
+            // entries replaced by a snapshot are committed, so they must match leader's log
+            entries.retain(|e| e.index > snapshot_index);
+        } else {
+            match self.storage.term(prev_log_index)? {
+                Some(term) if term == prev_log_term => {},
+                _ => {
+                    // retry from the last entry we have, or step back by one if it's a conflicting one
+                    let hint = last_index.min(prev_log_index.saturating_sub(1));
+                    self.send(leader, Payload::AppendEntriesResponse { success: false, last_index: hint });
+                    return Ok(());
+                }
+            }
+        }
+
+        // skip entries we already have and truncate the log on the first conflicting one
+        let mut first_new = entries.len();
+        for (i, e) in entries.iter().enumerate() {
+            match self.storage.term(e.index)? {
+                Some(term) if term == e.term => continue,
+                Some(_) => {
+                    if e.index <= self.commit {
+                        return Err(anyhow::anyhow!("Raft node {} received entry {} conflicting with committed log (commit index: {})", self.id, e.index, self.commit));
+                    }
+                    self.storage.truncate(e.index)?;
+                    // truncated entries could have changed cluster configuration
+                    self.restore_membership()?;
+                },
+                None => {},
+            }
+            first_new = i;
+            break;
+        }
+        self.storage.append(&entries[first_new..])?;
+        for e in entries[first_new..].iter() {
+            if e.kind == EntryKind::Membership {
+                self.set_membership(e.index, serde_cbor::from_slice(e.data.as_slice())?)?;
+            }
+        }
+
+        self.commit = self.commit.max(leader_commit.min(last_new));
+        self.send(leader, Payload::AppendEntriesResponse { success: true, last_index: last_new });
+        Ok(())
+    }
+
+    pub(super) fn handle_append_entries_response(&mut self, from: PeerId, success: bool, last_index: LogIndex) -> Result<()> {
+        let progress = match self.progress_mut(from) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        if success {
+            if last_index > progress.match_index {
+                progress.match_index = last_index;
+            }
+            if progress.next_index <= progress.match_index {
+                progress.next_index = progress.match_index + 1;
+            }
+            if progress.snapshot.iter().any(|s| s.index <= progress.match_index) {
+                progress.snapshot = None;
+            }
+            let next_index = progress.next_index;
+            self.maybe_commit()?;
+            self.maybe_send_timeout_now(from)?;
+            // continue if not all entries fit into a single message
+            if next_index <= self.storage.last_index()? {
+                self.send_append(from)?;
+            }
+        } else if last_index >= progress.match_index {
+            progress.next_index = last_index + 1;
+            self.send_append(from)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn handle_heartbeat_response(&mut self, from: PeerId, seq: u64) -> Result<()> {
+        let last_index = self.storage.last_index()?;
+        if let Some(progress) = self.progress_mut(from) {
+            if seq > progress.heartbeat_seq {
+                progress.heartbeat_seq = seq;
+                self.confirm_reads();
+            }
+            let progress = self.progress_mut(from)
+                .expect("Defect: Node::handle_heartbeat_response - follower progress not found");
+            if progress.snapshot.is_some() {
+                // previous snapshot chunk may have been lost
+                return self.send_snapshot(from);
+            }
+            // previous AppendEntries may have been lost, resend everything that wasn't confirmed
+            if progress.match_index < last_index {
+                progress.next_index = progress.match_index + 1;
+                self.send_append(from)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::node::{Node, Config};
+    use crate::raft::sim::Network;
+    use crate::raft::storage::{MemStorage, Entry, EntryKind, Storage};
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    fn log(node: &Node<MemStorage>) -> Vec<Entry> {
+        let storage = node.storage();
+        storage.entries(1, storage.last_index().unwrap() + 1).unwrap()
+    }
+
+    fn data(entries: Vec<Entry>) -> Vec<Vec<u8>> {
+        entries.into_iter()
+            .filter(|e| e.kind == EntryKind::Normal)
+            .map(|e| e.data)
+            .collect()
+    }
+
+    #[test]
+    fn replication_commit_on_majority() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        assert_eq!(net.node(A).commit_index(), 1); // no-op entry of a new leader
+
+        for i in 1..=3u8 {
+            net.node_mut(A).propose(vec![i]).unwrap();
+        }
+        net.deliver();
+        assert_eq!(net.node(A).commit_index(), 4);
+        let expected = vec![vec![1], vec![2], vec![3]];
+        assert_eq!(data(net.node_mut(A).committed_entries().unwrap()), expected);
+        assert!(net.node_mut(A).committed_entries().unwrap().is_empty());
+
+        net.heartbeat(A);
+        for &id in &[B, C] {
+            assert_eq!(net.node(id).commit_index(), 4);
+            assert_eq!(data(net.node_mut(id).committed_entries().unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn replication_no_commit_without_majority() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.isolate(B);
+        net.isolate(C);
+        let index = net.node_mut(A).propose(vec![1]).unwrap();
+        net.deliver();
+        assert!(net.node(A).commit_index() < index);
+
+        net.heal();
+        net.heartbeat(A);
+        assert_eq!(net.node(A).commit_index(), index);
+    }
+
+    #[test]
+    fn replication_propose_on_follower_fails() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        assert!(net.node_mut(B).propose(vec![1]).is_err());
+    }
+
+    #[test]
+    fn replication_lagging_follower_catches_up() {
+        let mut net = Network::with_config(&[A, B, C], |c| c.max_append_entries = 2);
+        net.elect(A);
+        net.isolate(C);
+        for i in 1..=5u8 {
+            net.node_mut(A).propose(vec![i]).unwrap();
+            net.deliver();
+        }
+        assert_eq!(net.node(A).commit_index(), 6);
+        assert_eq!(net.node(C).storage().last_index().unwrap(), 1);
+
+        net.heal();
+        net.heartbeat(A);
+        net.heartbeat(A);
+        assert_eq!(log(net.node(C)), log(net.node(A)));
+        assert_eq!(net.node(C).commit_index(), 6);
+    }
+
+    #[test]
+    fn replication_conflicting_entries_truncated() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+
+        // old leader accepts proposals, which are never replicated
+        net.isolate(A);
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.node_mut(A).propose(vec![2]).unwrap();
+        net.deliver();
+
+        net.elect(B);
+        net.node_mut(B).propose(vec![3]).unwrap();
+        net.deliver();
+        assert_eq!(net.node(B).commit_index(), 3);
+
+        net.heal();
+        net.heartbeat(B);
+        assert!(!net.node(A).is_leader());
+        assert_eq!(log(net.node(A)), log(net.node(B)));
+        assert_eq!(data(log(net.node(A))), vec![vec![3]]);
+    }
+
+    #[test]
+    fn replication_single_node() {
+        let mut a = Node::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        a.campaign().unwrap();
+        let index = a.propose(vec![1]).unwrap();
+        assert_eq!(a.commit_index(), index);
+        assert_eq!(data(a.committed_entries().unwrap()), vec![vec![1]]);
+    }
+}
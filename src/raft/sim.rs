@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::raft::PeerId;
+use crate::raft::message::Message;
+use crate::raft::node::{Node, Config};
+use crate::raft::storage::MemStorage;
+
+type Filter = Box<dyn FnMut(&Message) -> bool>;
+
+/// Deterministic in-memory network of Raft nodes, used for testing.
+pub(crate) struct Network {
+    nodes: BTreeMap<PeerId, Node<MemStorage>>,
+    /// Nodes cut off from the rest of the network.
+    isolated: BTreeSet<PeerId>,
+    /// Messages sent but not yet delivered.
+    in_flight: VecDeque<Message>,
+    /// Filter deciding which messages should be dropped.
+    filter: Option<Filter>,
+}
+
+impl Network {
+    pub fn new(ids: &[PeerId]) -> Self {
+        Self::with_config(ids, |_| {})
+    }
+
+    /// Creates a new network, allowing to adjust configuration of every node.
+    pub fn with_config<F: Fn(&mut Config)>(ids: &[PeerId], f: F) -> Self {
+        let nodes = ids.iter().map(|&id| {
+            let mut config = Config::new(id, ids.to_vec());
+            f(&mut config);
+            (id, Node::new(config, MemStorage::default()).unwrap())
+        }).collect();
+        Network {
+            nodes,
+            isolated: BTreeSet::new(),
+            in_flight: VecDeque::new(),
+            filter: None,
+        }
+    }
+
+    /// Adds a new node, which is not a member of the cluster yet.
+    pub fn add_node(&mut self, id: PeerId) {
+        let node = Node::new(Config::new(id, vec![]), MemStorage::default()).unwrap();
+        self.nodes.insert(id, node);
+    }
+
+    pub fn node(&self, id: PeerId) -> &Node<MemStorage> {
+        self.nodes.get(&id).expect("node not found")
+    }
+
+    pub fn node_mut(&mut self, id: PeerId) -> &mut Node<MemStorage> {
+        self.nodes.get_mut(&id).expect("node not found")
+    }
+
+    pub fn tick(&mut self, id: PeerId) {
+        self.node_mut(id).tick().unwrap();
+    }
+
+    pub fn tick_all(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.tick().unwrap();
+        }
+    }
+
+    /// Ticks a given leader until it sends heartbeats, and delivers all messages.
+    pub fn heartbeat(&mut self, leader: PeerId) {
+        for _ in 0..self.node(leader).config.heartbeat_interval {
+            self.tick(leader);
+        }
+        self.deliver();
+    }
+
+    /// Makes a given node a leader by starting an election on it and delivering all messages.
+    pub fn elect(&mut self, id: PeerId) {
+        self.node_mut(id).campaign().unwrap();
+        self.deliver();
+    }
+
+    /// Returns a leader with the highest term, as seen by nodes which are not isolated.
+    pub fn leader(&self) -> Option<PeerId> {
+        self.nodes.values()
+            .filter(|n| n.is_leader() && !self.isolated.contains(&n.id()))
+            .max_by_key(|n| n.term())
+            .map(|n| n.id())
+    }
+
+    /// Cuts a given node off from the rest of the network.
+    pub fn isolate(&mut self, id: PeerId) {
+        self.isolated.insert(id);
+    }
+
+    /// Drops all messages matching a given predicate.
+    pub fn drop_if<F: FnMut(&Message) -> bool + 'static>(&mut self, f: F) {
+        self.filter = Some(Box::new(f));
+    }
+
+    pub fn heal(&mut self) {
+        self.isolated.clear();
+        self.filter = None;
+    }
+
+    /// Collects messages sent by all nodes.
+    pub fn collect(&mut self) {
+        for node in self.nodes.values_mut() {
+            self.in_flight.extend(node.take_messages());
+        }
+    }
+
+    /// Delivers messages between nodes until there are no more messages in flight. Messages sent
+    /// from or to isolated nodes or matching a filter are dropped.
+    pub fn deliver(&mut self) {
+        self.collect();
+        while let Some(msg) = self.in_flight.pop_front() {
+            if self.isolated.contains(&msg.from) || self.isolated.contains(&msg.to) {
+                continue;
+            }
+            if let Some(filter) = self.filter.as_mut() {
+                if filter(&msg) {
+                    continue;
+                }
+            }
+            if let Some(node) = self.nodes.get_mut(&msg.to) {
+                node.step(msg).unwrap();
+            }
+            self.collect();
+        }
+    }
+}
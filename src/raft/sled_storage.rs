@@ -0,0 +1,245 @@
+use std::convert::TryInto;
+use sled::Transactional;
+use sled::transaction::ConflictableTransactionError;
+use crate::Result;
+use crate::raft::{Term, LogIndex};
+use crate::raft::storage::{Storage, HardState, Entry, Snapshot};
+
+const HARD_STATE_KEY: &[u8] = b"hard_state";
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+
+/// Persistent Raft `Storage` backed by sled. Log entries are kept in a separate tree keyed by
+/// their index, while hard state and the latest snapshot are stored in a metadata tree. Every
+/// modification is flushed to disk before returning, so that a node never acknowledges anything
+/// it could forget after a crash.
+#[derive(Debug)]
+pub struct SledStorage {
+    log: sled::Tree,
+    meta: sled::Tree,
+    /// Index and term of the last entry covered by a snapshot.
+    snapshot_index: LogIndex,
+    snapshot_term: Term,
+    last_index: LogIndex,
+}
+
+impl SledStorage {
+
+    /// Opens a Raft storage inside of a given database, recovering its state if it was already
+    /// created before.
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let log = db.open_tree("raft_log")?;
+        let meta = db.open_tree("raft_meta")?;
+
+        let (snapshot_index, snapshot_term) = match meta.get(SNAPSHOT_KEY)? {
+            None => (0, 0),
+            Some(bytes) => {
+                let snapshot: Snapshot = serde_cbor::from_slice(bytes.as_ref())?;
+                (snapshot.index, snapshot.term)
+            },
+        };
+        let last_index = match log.last()? {
+            None => snapshot_index,
+            Some((key, _)) => decode_index(key.as_ref())?.max(snapshot_index),
+        };
+
+        Ok(SledStorage { log, meta, snapshot_index, snapshot_term, last_index })
+    }
+
+    fn entry(&self, index: LogIndex) -> Result<Option<Entry>> {
+        match self.log.get(index.to_be_bytes())? {
+            None => Ok(None),
+            Some(bytes) => Ok(Some(serde_cbor::from_slice(bytes.as_ref())?)),
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.log.flush()?;
+        Ok(())
+    }
+}
+
+impl Storage for SledStorage {
+    fn hard_state(&self) -> Result<HardState> {
+        match self.meta.get(HARD_STATE_KEY)? {
+            None => Ok(HardState::default()),
+            Some(bytes) => Ok(serde_cbor::from_slice(bytes.as_ref())?),
+        }
+    }
+
+    fn set_hard_state(&mut self, state: &HardState) -> Result<()> {
+        self.meta.insert(HARD_STATE_KEY, serde_cbor::to_vec(state)?)?;
+        self.flush()
+    }
+
+    fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        let mut last_index = self.last_index;
+        for e in entries {
+            if e.index != last_index + 1 {
+                return Err(anyhow::anyhow!("SledStorage: expected entry at index {} but got {}", last_index + 1, e.index));
+            }
+            batch.insert(&e.index.to_be_bytes(), serde_cbor::to_vec(e)?);
+            last_index = e.index;
+        }
+        self.log.apply_batch(batch)?;
+        self.flush()?;
+        self.last_index = last_index;
+        Ok(())
+    }
+
+    fn truncate(&mut self, index: LogIndex) -> Result<()> {
+        if index <= self.snapshot_index {
+            return Err(anyhow::anyhow!("SledStorage: cannot truncate log at index {} covered by a snapshot", index));
+        }
+        let mut batch = sled::Batch::default();
+        for entry in self.log.range(index.to_be_bytes()..) {
+            let (key, _) = entry?;
+            batch.remove(key);
+        }
+        self.log.apply_batch(batch)?;
+        self.flush()?;
+        self.last_index = self.last_index.min(index - 1);
+        Ok(())
+    }
+
+    fn entries(&self, lo: LogIndex, hi: LogIndex) -> Result<Vec<Entry>> {
+        if lo <= self.snapshot_index || lo > hi || hi > self.last_index + 1 {
+            return Err(anyhow::anyhow!("SledStorage: entries range [{}, {}) is out of bounds", lo, hi));
+        }
+        let mut result = Vec::with_capacity((hi - lo) as usize);
+        for entry in self.log.range(lo.to_be_bytes()..hi.to_be_bytes()) {
+            let (_, bytes) = entry?;
+            result.push(serde_cbor::from_slice(bytes.as_ref())?);
+        }
+        Ok(result)
+    }
+
+    fn first_index(&self) -> Result<LogIndex> {
+        Ok(self.snapshot_index + 1)
+    }
+
+    fn last_index(&self) -> Result<LogIndex> {
+        Ok(self.last_index)
+    }
+
+    fn term(&self, index: LogIndex) -> Result<Option<Term>> {
+        if index == self.snapshot_index {
+            Ok(Some(self.snapshot_term))
+        } else if index < self.snapshot_index || index > self.last_index {
+            Ok(None)
+        } else {
+            Ok(self.entry(index)?.map(|e| e.term))
+        }
+    }
+
+    fn snapshot(&self) -> Result<Option<Snapshot>> {
+        match self.meta.get(SNAPSHOT_KEY)? {
+            None => Ok(None),
+            Some(bytes) => Ok(Some(serde_cbor::from_slice(bytes.as_ref())?)),
+        }
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        if snapshot.index < self.snapshot_index {
+            return Err(anyhow::anyhow!("SledStorage: snapshot at index {} is older than the current one at {}", snapshot.index, self.snapshot_index));
+        }
+        let retain = self.term(snapshot.index)? == Some(snapshot.term);
+        let mut removed = Vec::new();
+        let end = if retain { snapshot.index } else { LogIndex::MAX };
+        for entry in self.log.range(..=end.to_be_bytes()) {
+            let (key, _) = entry?;
+            removed.push(key);
+        }
+
+        let bytes = serde_cbor::to_vec(&snapshot)?;
+        (&self.log, &self.meta)
+            .transaction(|(log, meta)| {
+                for key in removed.iter() {
+                    log.remove(key)?;
+                }
+                meta.insert(SNAPSHOT_KEY, bytes.as_slice())?;
+                Ok::<(), ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| anyhow::anyhow!("SledStorage failed to install snapshot at index {}: {:?}", snapshot.index, e))?;
+        self.flush()?;
+
+        self.last_index = if retain { self.last_index.max(snapshot.index) } else { snapshot.index };
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        Ok(())
+    }
+}
+
+fn decode_index(bytes: &[u8]) -> Result<LogIndex> {
+    let bytes: [u8; 8] = bytes.try_into()
+        .map_err(|_| anyhow::anyhow!("SledStorage: invalid log index key"))?;
+    Ok(LogIndex::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::sled_storage::SledStorage;
+    use crate::raft::storage::{Storage, HardState, Entry, Snapshot};
+
+    fn db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn sled_storage_append_truncate() {
+        let db = db();
+        let mut s = SledStorage::open(&db).unwrap();
+        assert_eq!(s.last_index().unwrap(), 0);
+        assert_eq!(s.term(0).unwrap(), Some(0));
+
+        s.append(&[Entry::new(1, 1, vec![1]), Entry::new(1, 2, vec![2]), Entry::new(2, 3, vec![3])]).unwrap();
+        assert!(s.append(&[Entry::new(2, 5, vec![])]).is_err());
+        assert_eq!(s.term(3).unwrap(), Some(2));
+
+        s.truncate(2).unwrap();
+        assert_eq!(s.last_index().unwrap(), 1);
+        assert_eq!(s.term(2).unwrap(), None);
+        s.append(&[Entry::new(3, 2, vec![4])]).unwrap();
+        assert_eq!(s.entries(1, 3).unwrap(), vec![Entry::new(1, 1, vec![1]), Entry::new(3, 2, vec![4])]);
+    }
+
+    #[test]
+    fn sled_storage_install_snapshot() {
+        let db = db();
+        let mut s = SledStorage::open(&db).unwrap();
+        s.append(&[Entry::new(1, 1, vec![1]), Entry::new(1, 2, vec![2]), Entry::new(2, 3, vec![3])]).unwrap();
+
+        let snapshot = Snapshot { index: 2, term: 1, data: vec![1, 2], ..Default::default() };
+        s.install_snapshot(snapshot.clone()).unwrap();
+        assert_eq!(s.snapshot().unwrap(), Some(snapshot));
+        assert_eq!(s.first_index().unwrap(), 3);
+        assert_eq!(s.entries(3, 4).unwrap(), vec![Entry::new(2, 3, vec![3])]);
+        assert!(s.entries(2, 4).is_err());
+
+        s.install_snapshot(Snapshot { index: 5, term: 3, data: vec![], ..Default::default() }).unwrap();
+        assert_eq!(s.first_index().unwrap(), 6);
+        assert_eq!(s.last_index().unwrap(), 5);
+        assert_eq!(s.term(3).unwrap(), None);
+        assert_eq!(s.term(5).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn sled_storage_recover() {
+        let db = db();
+        let state = HardState { term: 3, voted_for: Some(2) };
+        {
+            let mut s = SledStorage::open(&db).unwrap();
+            s.set_hard_state(&state).unwrap();
+            s.append(&[Entry::new(1, 1, vec![1]), Entry::new(2, 2, vec![2]), Entry::new(3, 3, vec![3])]).unwrap();
+            s.install_snapshot(Snapshot { index: 1, term: 1, data: vec![1], ..Default::default() }).unwrap();
+        }
+
+        let mut s = SledStorage::open(&db).unwrap();
+        assert_eq!(s.hard_state().unwrap(), state);
+        assert_eq!(s.first_index().unwrap(), 2);
+        assert_eq!(s.last_index().unwrap(), 3);
+        assert_eq!(s.term(1).unwrap(), Some(1));
+        s.append(&[Entry::new(3, 4, vec![4])]).unwrap();
+        assert_eq!(s.entries(2, 5).unwrap().len(), 3);
+    }
+}
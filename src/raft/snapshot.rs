@@ -0,0 +1,283 @@
+use crate::Result;
+use crate::raft::{PeerId, LogIndex};
+use crate::raft::election::State;
+use crate::raft::message::Payload;
+use crate::raft::membership::membership_at;
+use crate::raft::node::Node;
+use crate::raft::storage::{Storage, Entry, EntryKind, Snapshot};
+
+/// Application state built from committed log entries. It must be able to serialize itself into
+/// a snapshot, so that entries applied so far can be removed from the log.
+pub trait StateMachine {
+    /// Applies a committed entry to a current state.
+    fn apply(&mut self, entry: &Entry) -> Result<()>;
+
+    /// Serializes a current state.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Replaces a current state with the one serialized in a snapshot.
+    fn restore(&mut self, data: &[u8]) -> Result<()>;
+}
+
+/// State of a snapshot transfer to a single follower.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTransfer {
+    /// Index of the last entry included in a transferred snapshot.
+    pub index: LogIndex,
+    /// Offset of the next snapshot chunk.
+    pub offset: u64,
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Applies all entries committed since the last call to a given state machine, returning
+    /// them. If a snapshot was installed in the meantime, a state machine is restored from it
+    /// first. Once the number of applied entries in the log reaches a configured threshold,
+    /// a new snapshot is taken and the log is compacted.
+    pub fn apply<M: StateMachine>(&mut self, state_machine: &mut M) -> Result<Vec<Entry>> {
+        let snapshot_index = self.storage.first_index()? - 1;
+        if self.applied < snapshot_index {
+            let snapshot = self.storage.snapshot()?
+                .expect("Defect: Node::apply - log has been compacted, but there's no snapshot");
+            state_machine.restore(&snapshot.data)?;
+            self.applied = snapshot.index;
+        }
+
+        let entries = self.committed_entries()?;
+        for e in entries.iter() {
+            if e.kind == EntryKind::Normal {
+                state_machine.apply(e)?;
+            }
+        }
+
+        if self.applied - snapshot_index >= self.config.snapshot_threshold {
+            self.compact(state_machine)?;
+        }
+        Ok(entries)
+    }
+
+    /// Takes a snapshot of a state machine and removes all applied entries from the log.
+    fn compact<M: StateMachine>(&mut self, state_machine: &M) -> Result<()> {
+        let index = self.applied;
+        let term = self.storage.term(index)?
+            .ok_or_else(|| anyhow::anyhow!("Raft node {} has no log entry at index {}", self.id, index))?;
+        let (_, membership) = membership_at(&self.storage, &self.config, index)?;
+        let data = state_machine.snapshot()?;
+        self.storage.install_snapshot(Snapshot { index, term, membership, data })
+    }
+
+    /// Sends the next chunk of the latest snapshot to a given follower.
+    pub(super) fn send_snapshot(&mut self, to: PeerId) -> Result<()> {
+        let snapshot = self.storage.snapshot()?
+            .ok_or_else(|| anyhow::anyhow!("Raft node {} has no snapshot to send", self.id))?;
+        let offset = match self.progress_mut(to) {
+            None => return Ok(()),
+            Some(p) => match &p.snapshot {
+                Some(transfer) if transfer.index == snapshot.index => transfer.offset,
+                // start over if snapshot has been replaced by a newer one
+                _ => {
+                    p.snapshot = Some(SnapshotTransfer { index: snapshot.index, offset: 0 });
+                    0
+                },
+            },
+        };
+
+        let len = snapshot.data.len();
+        let start = (offset as usize).min(len);
+        let end = (start + self.config.snapshot_chunk_size).min(len);
+        let data = snapshot.data[start..end].to_vec();
+        self.send(to, Payload::InstallSnapshot {
+            last_index: snapshot.index,
+            last_term: snapshot.term,
+            membership: snapshot.membership,
+            offset: start as u64,
+            data,
+            done: end == len,
+        });
+        Ok(())
+    }
+
+    /// Handles a single snapshot chunk, starting at a given `offset`.
+    pub(super) fn handle_install_snapshot(&mut self, leader: PeerId, chunk: Snapshot, offset: u64, done: bool) -> Result<()> {
+        self.accept_leader(leader)?;
+        let last_index = chunk.index;
+        if last_index <= self.commit {
+            // all entries covered by a snapshot are already committed here
+            let commit = self.commit;
+            self.send(leader, Payload::AppendEntriesResponse { success: true, last_index: commit });
+            return Ok(());
+        }
+
+        let follower = match &mut self.state {
+            State::Follower(follower) => follower,
+            _ => return Ok(()),
+        };
+        let Snapshot { index, term, membership, data } = chunk;
+        let same = follower.snapshot.iter().any(|s| s.index == index && s.term == term);
+        if !same || offset == 0 {
+            follower.snapshot = Some(Snapshot { index, term, membership, data: Vec::new() });
+        }
+        let snapshot = follower.snapshot.as_mut()
+            .expect("Defect: Node::handle_install_snapshot - snapshot has not been initialized");
+
+        if snapshot.data.len() as u64 != offset {
+            // a chunk has been lost or duplicated, let leader know where to resume from
+            let offset = snapshot.data.len() as u64;
+            self.send(leader, Payload::InstallSnapshotResponse { last_index, offset });
+        } else if !done {
+            snapshot.data.extend(data);
+            let offset = snapshot.data.len() as u64;
+            self.send(leader, Payload::InstallSnapshotResponse { last_index, offset });
+        } else {
+            snapshot.data.extend(data);
+            let snapshot = follower.snapshot.take()
+                .expect("Defect: Node::handle_install_snapshot - snapshot has not been initialized");
+            self.storage.install_snapshot(snapshot)?;
+            self.restore_membership()?;
+            self.commit = last_index;
+            self.send(leader, Payload::AppendEntriesResponse { success: true, last_index });
+        }
+        Ok(())
+    }
+
+    pub(super) fn handle_install_snapshot_response(&mut self, from: PeerId, last_index: LogIndex, offset: u64) -> Result<()> {
+        if let Some(progress) = self.progress_mut(from) {
+            match &mut progress.snapshot {
+                Some(transfer) if transfer.index == last_index => transfer.offset = offset,
+                _ => return Ok(()),
+            }
+            self.send_snapshot(from)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use crate::Result;
+    use crate::raft::election::State;
+    use crate::raft::message::Payload;
+    use crate::raft::node::{Node, Config};
+    use crate::raft::sim::Network;
+    use crate::raft::snapshot::StateMachine;
+    use crate::raft::storage::{MemStorage, Entry, Storage};
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    #[derive(Debug, Default, Clone, PartialEq)]
+    struct Events(Vec<Vec<u8>>);
+
+    impl StateMachine for Events {
+        fn apply(&mut self, entry: &Entry) -> Result<()> {
+            self.0.push(entry.data.clone());
+            Ok(())
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(serde_cbor::to_vec(&self.0)?)
+        }
+
+        fn restore(&mut self, data: &[u8]) -> Result<()> {
+            self.0 = serde_cbor::from_slice(data)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn snapshot_compacts_log() {
+        let mut config = Config::new(A, vec![A]);
+        config.snapshot_threshold = 3;
+        let mut a = Node::new(config.clone(), MemStorage::default()).unwrap();
+        let mut events = Events::default();
+        a.campaign().unwrap();
+        for i in 1..=5u8 {
+            a.propose(vec![i]).unwrap();
+        }
+        a.apply(&mut events).unwrap();
+        assert_eq!(events.0.len(), 5);
+        assert_eq!(a.storage().first_index().unwrap(), 7); // no-op entry + 5 proposals
+        assert!(a.committed_entries().unwrap().is_empty());
+
+        a.propose(vec![6]).unwrap();
+        assert_eq!(a.apply(&mut events).unwrap().len(), 1);
+        assert_eq!(a.storage().first_index().unwrap(), 7); // below threshold
+
+        // restarted node restores its state from a snapshot
+        let mut a = Node::new(config, a.storage().clone()).unwrap();
+        let mut restored = Events::default();
+        assert!(a.committed_entries().is_err());
+        a.apply(&mut restored).unwrap();
+        assert_eq!(restored.0, events.0[..5].to_vec());
+        assert_eq!(a.applied_index(), 6);
+    }
+
+    #[test]
+    fn snapshot_install_on_lagging_follower() {
+        let mut net = Network::with_config(&[A, B, C], |c| {
+            c.snapshot_threshold = 4;
+            c.snapshot_chunk_size = 4;
+        });
+        let mut events: BTreeMap<_, _> = [A, B, C].iter().map(|&id| (id, Events::default())).collect();
+        net.elect(A);
+        net.isolate(C);
+        for i in 1..=10u8 {
+            net.node_mut(A).propose(vec![i]).unwrap();
+        }
+        net.deliver();
+        net.node_mut(A).apply(events.get_mut(&A).unwrap()).unwrap();
+        assert_eq!(net.node(A).storage().first_index().unwrap(), 12);
+
+        // entries appended after a snapshot are replicated as usual
+        net.node_mut(A).propose(vec![11]).unwrap();
+        net.deliver();
+
+        net.heal();
+        net.heartbeat(A);
+        assert_eq!(net.node(C).storage().snapshot().unwrap(), net.node(A).storage().snapshot().unwrap());
+        assert_eq!(net.node(C).storage().last_index().unwrap(), 12);
+
+        net.node_mut(A).apply(events.get_mut(&A).unwrap()).unwrap();
+        net.node_mut(C).apply(events.get_mut(&C).unwrap()).unwrap();
+        assert_eq!(events[&C].0.len(), 11);
+        assert_eq!(events[&C], events[&A]);
+    }
+
+    #[test]
+    fn snapshot_resume_lost_chunk() {
+        let mut net = Network::with_config(&[A, B, C], |c| {
+            c.snapshot_threshold = 1;
+            c.snapshot_chunk_size = 2;
+        });
+        let mut events = Events::default();
+        net.elect(A);
+        net.isolate(C);
+        net.node_mut(A).propose(vec![1, 2, 3, 4, 5, 6]).unwrap();
+        net.deliver();
+        net.node_mut(A).apply(&mut events).unwrap();
+        net.heal();
+
+        // drop the second chunk once
+        let mut dropped = false;
+        net.drop_if(move |msg| match &msg.payload {
+            Payload::InstallSnapshot { offset: 2, .. } if !dropped => {
+                dropped = true;
+                true
+            },
+            _ => false,
+        });
+        net.heartbeat(A);
+        assert!(matches!(&net.node(A).state, State::Leader(l) if l.progress(C).unwrap().snapshot.is_some()));
+        assert!(net.node(C).storage().snapshot().unwrap().is_none());
+
+        // next heartbeat resumes transfer from the lost chunk
+        net.heartbeat(A);
+        assert_eq!(net.node(C).storage().snapshot().unwrap(), net.node(A).storage().snapshot().unwrap());
+        let mut restored = Events::default();
+        net.node_mut(C).apply(&mut restored).unwrap();
+        assert_eq!(restored, events);
+    }
+}
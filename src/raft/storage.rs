@@ -0,0 +1,225 @@
+use serde::{Serialize, Deserialize};
+use crate::Result;
+use crate::raft::{PeerId, Term, LogIndex};
+use crate::raft::membership::Membership;
+
+/// A part of Raft node state, which must be persisted before responding to any message, so that
+/// a node never votes twice within the same term, even after restart.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub term: Term,
+    pub voted_for: Option<PeerId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    /// Entry containing data appended by a user.
+    Normal,
+    /// Empty entry appended by a newly elected leader, so that it can commit entries from
+    /// previous terms.
+    Noop,
+    /// Entry containing a new cluster `Membership`.
+    Membership,
+}
+
+/// A single entry of a replicated log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub term: Term,
+    pub index: LogIndex,
+    pub kind: EntryKind,
+    pub data: Vec<u8>,
+}
+
+impl Entry {
+    pub fn new(term: Term, index: LogIndex, data: Vec<u8>) -> Self {
+        Entry { term, index, kind: EntryKind::Normal, data }
+    }
+
+    pub fn noop(term: Term, index: LogIndex) -> Self {
+        Entry { term, index, kind: EntryKind::Noop, data: Vec::new() }
+    }
+}
+
+/// Snapshot of a state machine, which replaces a prefix of the log up to (and including)
+/// an entry at `index`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Index of the last entry included in a snapshot.
+    pub index: LogIndex,
+    /// Term of the last entry included in a snapshot.
+    pub term: Term,
+    /// Cluster configuration as of the last entry included in a snapshot.
+    pub membership: Membership,
+    pub data: Vec<u8>,
+}
+
+/// Durable storage used by a Raft `Node`. Log indexes start from 1, while an index 0 is used to
+/// describe an empty log (its term is always 0). All changes must be durable once a method
+/// modifying the storage returns.
+pub trait Storage {
+    /// Returns the last persisted hard state.
+    fn hard_state(&self) -> Result<HardState>;
+
+    fn set_hard_state(&mut self, state: &HardState) -> Result<()>;
+
+    /// Appends given entries at the end of the log.
+    fn append(&mut self, entries: &[Entry]) -> Result<()>;
+
+    /// Removes all entries starting from a given index (inclusive).
+    fn truncate(&mut self, index: LogIndex) -> Result<()>;
+
+    /// Returns entries within `[lo, hi)` range of indexes.
+    fn entries(&self, lo: LogIndex, hi: LogIndex) -> Result<Vec<Entry>>;
+
+    /// Returns an index of the first entry available in the log. All entries before it have been
+    /// replaced by a snapshot.
+    fn first_index(&self) -> Result<LogIndex>;
+
+    /// Returns an index of the last entry in the log.
+    fn last_index(&self) -> Result<LogIndex>;
+
+    /// Returns a term of an entry at a given index or `None` if there's no such entry. Term of
+    /// the last entry included in a snapshot is always available.
+    fn term(&self, index: LogIndex) -> Result<Option<Term>>;
+
+    /// Returns the latest snapshot, if any was installed.
+    fn snapshot(&self) -> Result<Option<Snapshot>>;
+
+    /// Installs a given snapshot, discarding log entries it covers. Entries following a snapshot
+    /// are retained only if the log contains an entry matching snapshot's index and term,
+    /// otherwise the whole log is discarded.
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> Result<()>;
+}
+
+/// In-memory `Storage` implementation, used mostly for testing.
+#[derive(Debug, Clone, Default)]
+pub struct MemStorage {
+    hard_state: HardState,
+    snapshot: Option<Snapshot>,
+    /// Entries following the snapshot.
+    entries: Vec<Entry>,
+}
+
+impl MemStorage {
+    /// Index of the last entry covered by a snapshot.
+    fn offset(&self) -> LogIndex {
+        self.snapshot.as_ref().map(|s| s.index).unwrap_or(0)
+    }
+}
+
+impl Storage for MemStorage {
+    fn hard_state(&self) -> Result<HardState> {
+        Ok(self.hard_state.clone())
+    }
+
+    fn set_hard_state(&mut self, state: &HardState) -> Result<()> {
+        self.hard_state = state.clone();
+        Ok(())
+    }
+
+    fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        for e in entries {
+            let expected = self.offset() + self.entries.len() as LogIndex + 1;
+            if e.index != expected {
+                return Err(anyhow::anyhow!("MemStorage: expected entry at index {} but got {}", expected, e.index));
+            }
+            self.entries.push(e.clone());
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, index: LogIndex) -> Result<()> {
+        let offset = self.offset();
+        if index <= offset {
+            return Err(anyhow::anyhow!("MemStorage: cannot truncate log at index {} covered by a snapshot", index));
+        }
+        self.entries.truncate((index - offset) as usize - 1);
+        Ok(())
+    }
+
+    fn entries(&self, lo: LogIndex, hi: LogIndex) -> Result<Vec<Entry>> {
+        let offset = self.offset();
+        if lo <= offset || lo > hi || hi > offset + self.entries.len() as LogIndex + 1 {
+            return Err(anyhow::anyhow!("MemStorage: entries range [{}, {}) is out of bounds", lo, hi));
+        }
+        Ok(self.entries[(lo - offset - 1) as usize..(hi - offset - 1) as usize].to_vec())
+    }
+
+    fn first_index(&self) -> Result<LogIndex> {
+        Ok(self.offset() + 1)
+    }
+
+    fn last_index(&self) -> Result<LogIndex> {
+        Ok(self.offset() + self.entries.len() as LogIndex)
+    }
+
+    fn term(&self, index: LogIndex) -> Result<Option<Term>> {
+        let offset = self.offset();
+        if index == offset {
+            Ok(Some(self.snapshot.as_ref().map(|s| s.term).unwrap_or(0)))
+        } else if index < offset {
+            Ok(None)
+        } else {
+            Ok(self.entries.get((index - offset) as usize - 1).map(|e| e.term))
+        }
+    }
+
+    fn snapshot(&self) -> Result<Option<Snapshot>> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        let offset = self.offset();
+        if snapshot.index < offset {
+            return Err(anyhow::anyhow!("MemStorage: snapshot at index {} is older than the current one at {}", snapshot.index, offset));
+        }
+        if self.term(snapshot.index)? == Some(snapshot.term) {
+            self.entries.drain(..(snapshot.index - offset) as usize);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::storage::{MemStorage, Storage, Entry, Snapshot};
+
+    #[test]
+    fn mem_storage_truncate() {
+        let mut s = MemStorage::default();
+        s.append(&[Entry::new(1, 1, vec![1]), Entry::new(1, 2, vec![2]), Entry::new(2, 3, vec![3])]).unwrap();
+        assert!(s.append(&[Entry::new(2, 5, vec![])]).is_err()); // gap in the log
+
+        s.truncate(2).unwrap();
+        assert_eq!(s.last_index().unwrap(), 1);
+        assert_eq!(s.term(2).unwrap(), None);
+        s.append(&[Entry::new(3, 2, vec![4])]).unwrap();
+        assert_eq!(s.entries(1, 3).unwrap(), vec![Entry::new(1, 1, vec![1]), Entry::new(3, 2, vec![4])]);
+    }
+
+    #[test]
+    fn mem_storage_install_snapshot() {
+        let mut s = MemStorage::default();
+        s.append(&[Entry::new(1, 1, vec![1]), Entry::new(1, 2, vec![2]), Entry::new(2, 3, vec![3])]).unwrap();
+
+        // matching snapshot retains the following entries
+        s.install_snapshot(Snapshot { index: 2, term: 1, data: vec![], ..Default::default() }).unwrap();
+        assert_eq!(s.first_index().unwrap(), 3);
+        assert_eq!(s.last_index().unwrap(), 3);
+        assert_eq!(s.term(2).unwrap(), Some(1));
+        assert_eq!(s.term(1).unwrap(), None);
+        assert!(s.entries(2, 4).is_err());
+        assert!(s.truncate(2).is_err());
+
+        // conflicting snapshot discards the whole log
+        s.install_snapshot(Snapshot { index: 3, term: 3, data: vec![], ..Default::default() }).unwrap();
+        assert_eq!(s.first_index().unwrap(), 4);
+        assert_eq!(s.last_index().unwrap(), 3);
+        assert_eq!(s.term(3).unwrap(), Some(3));
+        assert!(s.install_snapshot(Snapshot { index: 1, term: 1, data: vec![], ..Default::default() }).is_err());
+    }
+}
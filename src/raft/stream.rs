@@ -0,0 +1,130 @@
+use crate::Result;
+use crate::raft::LogIndex;
+use crate::raft::node::Node;
+use crate::raft::storage::{Storage, Entry, Snapshot};
+use crate::stream::CommittedLog;
+
+/// An item of a `CommittedStream`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Committed {
+    /// Entries up to a snapshot index have been compacted. A state machine should be restored
+    /// from a snapshot, and subsequent entries applied on top of it.
+    Snapshot(Snapshot),
+    Entry(Entry),
+}
+
+impl Committed {
+    /// Index of the last log entry covered by this item.
+    pub fn index(&self) -> LogIndex {
+        match self {
+            Committed::Snapshot(snapshot) => snapshot.index,
+            Committed::Entry(entry) => entry.index,
+        }
+    }
+}
+
+/// A stream of committed log entries, starting from a requested index. All entries are emitted
+/// in the log order without gaps, including no-op and membership ones. If requested entries
+/// have already been compacted, a stream starts with a snapshot covering them instead.
+pub type CommittedStream = crate::stream::CommittedStream<Committed>;
+
+/// A sending side of a `CommittedStream`, which should be fed by a task driving a Raft node.
+pub type Subscriber = crate::stream::Subscriber<Committed>;
+
+impl<S: Storage> CommittedLog for Node<S> {
+    type Item = Committed;
+
+    fn read_committed(&self, from: LogIndex) -> Result<(Vec<Committed>, LogIndex)> {
+        let commit = self.commit_index();
+        let mut next = from.max(1);
+        let mut items = Vec::new();
+        if next > commit {
+            return Ok((items, next));
+        }
+        let storage = self.storage();
+        if next < storage.first_index()? {
+            let snapshot = storage.snapshot()?
+                .expect("Defect: Node::read_committed - log has been compacted, but there's no snapshot");
+            next = snapshot.index + 1;
+            items.push(Committed::Snapshot(snapshot));
+        }
+        if next <= commit {
+            items.extend(storage.entries(next, commit + 1)?.into_iter().map(Committed::Entry));
+            next = commit + 1;
+        }
+        Ok((items, next))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{FutureExt, StreamExt};
+    use crate::raft::node::{Node, Config};
+    use crate::raft::storage::{MemStorage, Storage, Snapshot};
+    use crate::raft::stream::{CommittedStream, Committed};
+    use crate::raft::{PeerId, LogIndex};
+
+    const A: PeerId = 1;
+
+    fn leader() -> Node<MemStorage> {
+        let mut node = Node::new(Config::new(A, vec![A]), MemStorage::default()).unwrap();
+        node.campaign().unwrap();
+        node
+    }
+
+    /// Returns all items, which have been sent to a stream so far.
+    fn received(stream: &mut CommittedStream) -> Vec<Committed> {
+        let mut result = Vec::new();
+        while let Some(Some(item)) = stream.next().now_or_never() {
+            result.push(item);
+        }
+        result
+    }
+
+    fn indexes(items: &[Committed]) -> Vec<LogIndex> {
+        items.iter().map(Committed::index).collect()
+    }
+
+    #[test]
+    fn stream_from_index() {
+        let mut node = leader();
+        for i in 1..=3u8 {
+            node.propose(vec![i]).unwrap();
+        }
+        let (mut subscriber, mut stream) = CommittedStream::new(3);
+        assert!(subscriber.notify(&node).unwrap());
+        let items = received(&mut stream);
+        assert_eq!(indexes(&items), vec![3, 4]);
+        assert!(matches!(&items[0], Committed::Entry(e) if e.data == vec![2] && e.term == 1));
+
+        node.propose(vec![4]).unwrap();
+        subscriber.notify(&node).unwrap();
+        assert_eq!(indexes(&received(&mut stream)), vec![5]);
+
+        drop(stream);
+        assert!(!subscriber.notify(&node).unwrap());
+    }
+
+    #[test]
+    fn stream_resume_from_snapshot() {
+        let mut node = leader();
+        for i in 1..=4u8 {
+            node.propose(vec![i]).unwrap();
+        }
+        let snapshot = Snapshot { index: 3, term: 1, data: vec![1, 2], ..Default::default() };
+        node.storage.install_snapshot(snapshot.clone()).unwrap();
+
+        let (mut subscriber, mut stream) = CommittedStream::new(0);
+        subscriber.notify(&node).unwrap();
+        let items = received(&mut stream);
+        assert_eq!(items[0], Committed::Snapshot(snapshot));
+        assert_eq!(indexes(&items), vec![3, 4, 5]);
+
+        // entries which have not been compacted yet are streamed directly
+        let (mut subscriber, mut stream) = CommittedStream::new(4);
+        subscriber.notify(&node).unwrap();
+        assert_eq!(indexes(&received(&mut stream)), vec![4, 5]);
+        drop(subscriber);
+        assert_eq!(futures::executor::block_on(stream.next()), None);
+    }
+}
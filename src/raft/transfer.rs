@@ -0,0 +1,161 @@
+use crate::Result;
+use crate::raft::PeerId;
+use crate::raft::election::State;
+use crate::raft::message::Payload;
+use crate::raft::node::Node;
+use crate::raft::storage::Storage;
+
+/// State of a leadership transfer, as tracked by a leader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Transfer {
+    /// Node which should become a new leader.
+    pub target: PeerId,
+    /// Number of ticks since transfer has started.
+    pub elapsed: u64,
+}
+
+impl<S: Storage> Node<S> {
+
+    /// Starts transferring leadership to a given voter. Once a target has replicated the whole
+    /// log of a current leader, it's asked to start an election right away, which it's going
+    /// to win, since its log is up to date. New proposals are rejected for the time of transfer.
+    /// If a target doesn't become a leader within an election timeout, transfer is aborted.
+    pub fn transfer_leadership(&mut self, target: PeerId) -> Result<()> {
+        if !self.is_leader() {
+            return Err(anyhow::anyhow!("Raft node {} is not a leader (current leader: {:?})", self.id, self.leader()));
+        }
+        if target == self.id || !self.membership.is_voter(target) {
+            return Err(anyhow::anyhow!("Raft node {} cannot transfer leadership to {}, which is not another voter", self.id, target));
+        }
+        if let State::Leader(leader) = &mut self.state {
+            match &leader.transfer {
+                Some(transfer) if transfer.target == target => return Ok(()),
+                Some(transfer) => return Err(anyhow::anyhow!("Raft node {} is already transferring its leadership to {}", self.id, transfer.target)),
+                None => {},
+            }
+            leader.transfer = Some(Transfer { target, elapsed: 0 });
+            // lease is not respected by nodes voting for a transfer target
+            leader.lease_expires = 0;
+        }
+
+        let last_index = self.storage.last_index()?;
+        match self.progress_mut(target) {
+            Some(p) if p.match_index == last_index => self.send(target, Payload::TimeoutNow),
+            Some(p) => {
+                p.next_index = p.match_index + 1;
+                self.send_append(target)?;
+            },
+            None => {},
+        }
+        Ok(())
+    }
+
+    /// Returns a target of leadership transfer in progress.
+    pub fn leadership_transfer(&self) -> Option<PeerId> {
+        match &self.state {
+            State::Leader(leader) => leader.transfer.as_ref().map(|t| t.target),
+            _ => None,
+        }
+    }
+
+    /// Aborts leadership transfer, which didn't finish within an election timeout.
+    pub(super) fn tick_transfer(&mut self) {
+        let timeout = self.config.election_timeout;
+        if let State::Leader(leader) = &mut self.state {
+            if let Some(transfer) = &mut leader.transfer {
+                transfer.elapsed += 1;
+                if transfer.elapsed >= timeout {
+                    leader.transfer = None;
+                }
+            }
+        }
+    }
+
+    /// Asks a transfer target to start an election, once it has caught up with a leader.
+    pub(super) fn maybe_send_timeout_now(&mut self, from: PeerId) -> Result<()> {
+        if self.leadership_transfer() != Some(from) {
+            return Ok(());
+        }
+        let last_index = self.storage.last_index()?;
+        if self.progress_mut(from).map(|p| p.match_index == last_index).unwrap_or(false) {
+            self.send(from, Payload::TimeoutNow);
+        }
+        Ok(())
+    }
+
+    pub(super) fn handle_timeout_now(&mut self, leader: PeerId) -> Result<()> {
+        if self.leader() == Some(leader) && self.membership.is_voter(self.id) {
+            self.start_campaign(true)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raft::sim::Network;
+    use crate::raft::storage::Storage;
+    use crate::raft::PeerId;
+
+    const A: PeerId = 1;
+    const B: PeerId = 2;
+    const C: PeerId = 3;
+
+    #[test]
+    fn transfer_leadership() {
+        let mut net = Network::with_config(&[A, B, C], |c| {
+            c.pre_vote = true;
+            c.check_quorum = true;
+        });
+        net.elect(A);
+        assert!(net.node_mut(A).transfer_leadership(A).is_err());
+        assert!(net.node_mut(B).transfer_leadership(C).is_err());
+
+        // followers vote for a target, even though they still hear from a current leader
+        net.node_mut(A).transfer_leadership(B).unwrap();
+        net.deliver();
+        assert_eq!(net.leader(), Some(B));
+        assert_eq!(net.node(B).term(), 2);
+        assert_eq!(net.node(A).leader(), Some(B));
+        assert_eq!(net.node(C).leader(), Some(B));
+    }
+
+    #[test]
+    fn transfer_leadership_to_lagging_follower() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.isolate(C);
+        net.node_mut(A).propose(vec![1]).unwrap();
+        net.node_mut(A).propose(vec![2]).unwrap();
+        net.deliver();
+        net.heal();
+
+        net.node_mut(A).transfer_leadership(C).unwrap();
+        assert_eq!(net.node(A).leadership_transfer(), Some(C));
+        assert!(net.node_mut(A).propose(vec![3]).is_err());
+        assert!(net.node_mut(A).transfer_leadership(B).is_err());
+        net.deliver();
+        assert_eq!(net.leader(), Some(C));
+        assert_eq!(net.node(C).storage().term(3).unwrap(), Some(1));
+        assert_eq!(net.node(C).storage().last_index().unwrap(), 4); // no-op entry of a new leader
+    }
+
+    #[test]
+    fn transfer_leadership_timeout() {
+        let mut net = Network::new(&[A, B, C]);
+        net.elect(A);
+        net.isolate(B);
+        net.node_mut(A).transfer_leadership(B).unwrap();
+        net.deliver();
+        assert!(net.node_mut(A).propose(vec![1]).is_err());
+
+        for _ in 0..net.node(A).config.election_timeout {
+            net.tick(A);
+        }
+        net.deliver();
+        assert_eq!(net.node(A).leadership_transfer(), None);
+        assert_eq!(net.leader(), Some(A));
+        assert!(net.node_mut(A).propose(vec![1]).is_ok());
+    }
+}
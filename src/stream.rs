@@ -0,0 +1,65 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use crate::Result;
+
+/// A replicated log, whose committed prefix can be followed with a `CommittedStream`. Positions
+/// within a log are protocol specific, e.g. Raft indexes or Multi-Paxos slots.
+pub trait CommittedLog {
+    type Item;
+
+    /// Returns all items committed at positions starting from `from`, together with a position
+    /// following the last returned one. A single item may cover several positions, e.g. a snapshot
+    /// of compacted entries.
+    fn read_committed(&self, from: u64) -> Result<(Vec<Self::Item>, u64)>;
+}
+
+/// A stream of committed items of a replicated log, starting from a requested position. Items
+/// are emitted in the log order without gaps.
+#[derive(Debug)]
+pub struct CommittedStream<T> {
+    receiver: mpsc::UnboundedReceiver<T>,
+}
+
+impl<T> CommittedStream<T> {
+    /// Creates a new stream of committed items starting at position `from`, together with its
+    /// subscriber, which should be fed by a task driving a replicated log.
+    pub fn new(from: u64) -> (Subscriber<T>, Self) {
+        let (sender, receiver) = mpsc::unbounded();
+        let subscriber = Subscriber { next: from, sender };
+        (subscriber, CommittedStream { receiver })
+    }
+}
+
+impl<T> Stream for CommittedStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+/// A sending side of a `CommittedStream`.
+#[derive(Debug)]
+pub struct Subscriber<T> {
+    /// Position of the next item to send.
+    next: u64,
+    sender: mpsc::UnboundedSender<T>,
+}
+
+impl<T> Subscriber<T> {
+
+    /// Sends all items committed in a given log, which were not sent yet. Returns false once
+    /// a receiving stream has been dropped.
+    pub fn notify<L: CommittedLog<Item = T>>(&mut self, log: &L) -> Result<bool> {
+        let (items, next) = log.read_committed(self.next)?;
+        for item in items {
+            if self.sender.unbounded_send(item).is_err() {
+                return Ok(false);
+            }
+        }
+        self.next = next;
+        Ok(!self.sender.is_closed())
+    }
+}
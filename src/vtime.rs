@@ -14,6 +14,9 @@ pub struct Dot(PID, u64);
 /// within the scope of that replica. These values, combined, can be used to uniquely represent
 /// events in distributed systems across different actors, even in a face of concurrent operations.
 impl Dot {
+    /// Creates a new `Dot` for a given replica `id` and its `seq_nr`.
+    pub fn new(id: PID, seq_nr: u64) -> Self { Dot(id, seq_nr) }
+
     /// Replica identifer of a creator of current Dot.
     pub fn pid(&self) -> PID { self.0 }
 
@@ -121,56 +124,18 @@ impl Convergent for VTime {
 
 impl PartialOrd for VTime {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let mut a = self.0.iter();
-        let mut b = other.0.iter();
-        let mut result = Some(Ordering::Equal);
-        let mut e1 = a.next();
-        let mut e2 = b.next();
-        while result != None {
-            match (e1, e2) {
-                (None, None) => break,
-                (None, Some(_)) => {
-                    match result {
-                        Some(Ordering::Greater) => result = None,
-                        Some(Ordering::Equal) => result = Some(Ordering::Less),
-                        _ => {},
-                    }
-                    break;
-                },
-                (Some(_), None) => {
-                    match result {
-                        Some(Ordering::Less) => result = None,
-                        Some(Ordering::Equal) => result = Some(Ordering::Greater),
-                        _ => {},
-                    }
-                    break;
-                },
-                (Some((k1, v1)), Some((k2, v2))) => {
-                    match k1.cmp(k2) {
-                        Ordering::Equal => {
-                            result =
-                                match v1.partial_cmp(v2) {
-                                    Some(Ordering::Greater) if result == Some(Ordering::Less) => {
-                                        None
-                                    },
-                                    Some(Ordering::Less) if result == Some(Ordering::Greater) => {
-                                        None
-                                    },
-                                    other if result == Some(Ordering::Equal) => other,
-                                    _ => result,
-                                };
-
-                            e1 = a.next(); // A B
-                            e2 = b.next(); // A C
-                        },
-                        Ordering::Less | Ordering::Greater => {
-                            result = None;
-                        },
-                    }
-                }
+        // missing entries are treated as 0, so zipping is required to compare clocks which have
+        // observed different sets of replicas
+        let mut result = Ordering::Equal;
+        for (_, left, right) in self.zip(other) {
+            match left.cmp(right) {
+                Ordering::Equal => {},
+                ord if result == Ordering::Equal => result = ord,
+                ord if ord != result => return None,
+                _ => {},
             }
         }
-        result
+        Some(result)
     }
 }
 
@@ -264,6 +229,8 @@ mod test {
             (vtime(1,2,2), vtime(1,2,3), Some(Ordering::Less)),
             (vtime(1,2,3), vtime(3,2,1), None),
             (vtime(1,0,1), vtime(1,1,0), None),
+            (vtime(0,1,0), vtime(2,1,0), Some(Ordering::Less)),
+            (vtime(0,1,1), vtime(2,1,0), None),
         ];
 
         for (left, right, expected) in cases {